@@ -3,9 +3,10 @@ use crate::error::Result;
 use crate::helper;
 use crate::types::{Bytes, TipSet, DomainSeparationTag, ChainEpoch, Randomness, BytesRef, TipSetKey,
                    BlockHeader, CidJsonRef, BlockMessages, MessageReceipt, ParentMessage, ObjStat,
-                   BigIntWrapper, UnsignedMessage, HeadChange, BigInt, Cid};
-use forest_blocks::{self, tipset::tipset_json::TipsetJson};
+                   BigIntWrapper, UnsignedMessage, TipsetChange, BigInt, Cid};
+use forest_blocks::{self, TipsetKeys, tipset::{tipset_json::TipsetJson, tipset_keys_json::TipsetKeysJson}};
 use rpc::BlockMessages as forest_BlockMessages;
+use forest_message::unsigned_message::json::UnsignedMessageJson;
 #[async_trait::async_trait]
 pub trait ChainApi: JsonApi {
 
@@ -41,8 +42,8 @@ pub trait ChainApi: JsonApi {
             .await
     }
 
-    async fn chain_get_tipset(&self, key: &TipSetKey) -> Result<TipSet> {
-        self.request("ChainGetTipSet", vec![helper::serialize(key)])
+    async fn chain_get_tipset(&self, key: &TipsetKeys) -> Result<TipsetJson> {
+        self.request("ChainGetTipSet", vec![helper::serialize(&TipsetKeysJson(key.clone()))])
             .await
     }
 
@@ -51,8 +52,8 @@ pub trait ChainApi: JsonApi {
             .await
     }
 
-    async fn chain_get_parent_receipts(&self, cid: &Cid) -> Result<Vec<MessageReceipt>> {
-        self.request("ChainGetParentReceipts", vec![helper::serialize(&CidJsonRef(cid))])
+    async fn chain_get_parent_receipts(&self, cid: &forest_cid::Cid) -> Result<Vec<MessageReceipt>> {
+        self.request("ChainGetParentReceipts", vec![helper::serialize(&forest_cid::json::CidJson(cid.clone()))])
             .await
     }
 
@@ -61,21 +62,29 @@ pub trait ChainApi: JsonApi {
             .await
     }
 
+    /// The unsigned message of a CID, also of signed messages that are only in the pool.
+    async fn chain_get_message(&self, cid: &forest_cid::Cid) -> Result<forest_message::UnsignedMessage> {
+        let message: UnsignedMessageJson = self
+            .request("ChainGetMessage", vec![helper::serialize(&forest_cid::json::CidJson(cid.clone()))])
+            .await?;
+        Ok(message.0)
+    }
+
     async fn chain_get_tipset_by_height(
         &self,
         height: ChainEpoch,
-        key: &TipSetKey,
-    ) -> Result<TipSet> {
+        key: &TipsetKeys,
+    ) -> Result<TipsetJson> {
         self.request(
             "ChainGetTipSetByHeight",
-            vec![helper::serialize(&height), helper::serialize(key)],
+            vec![helper::serialize(&height), helper::serialize(&TipsetKeysJson(key.clone()))],
         )
             .await
     }
 
-    async fn chain_read_obj(&self, cid: &Cid) -> Result<Vec<u8>> {
+    async fn chain_read_obj(&self, cid: &forest_cid::Cid) -> Result<Vec<u8>> {
         let bytes: Bytes = self
-            .request("ChainReadObj", vec![helper::serialize(&CidJsonRef(cid))])
+            .request("ChainReadObj", vec![helper::serialize(&forest_cid::json::CidJson(cid.clone()))])
             .await?;
         Ok(bytes.into_inner())
     }
@@ -114,10 +123,11 @@ pub trait ChainApi: JsonApi {
             .await
     }
 
-    async fn chain_get_path(&self, from: &TipSetKey, to: &TipSetKey) -> Result<Vec<HeadChange>> {
+    /// Reverts from `from` back to the common ancestor, then applies up to `to`.
+    async fn chain_get_path(&self, from: &TipsetKeys, to: &TipsetKeys) -> Result<Vec<TipsetChange>> {
         self.request(
             "ChainGetPath",
-            vec![helper::serialize(from), helper::serialize(to)],
+            vec![helper::serialize(&TipsetKeysJson(from.clone())), helper::serialize(&TipsetKeysJson(to.clone()))],
         )
             .await
     }
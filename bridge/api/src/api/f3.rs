@@ -0,0 +1,22 @@
+use super::JsonApi;
+use crate::error::Result;
+use crate::helper;
+use crate::types::{FinalityCertificate, f3::PowerEntry};
+
+#[async_trait::async_trait]
+pub trait F3Api: JsonApi {
+    async fn f3_get_certificate(&self, instance: u64) -> Result<FinalityCertificate> {
+        self.request("F3GetCertificate", vec![helper::serialize(&instance)])
+            .await
+    }
+
+    async fn f3_get_latest_certificate(&self) -> Result<FinalityCertificate> {
+        self.request("F3GetLatestCertificate", vec![])
+            .await
+    }
+
+    async fn f3_get_power_table_by_instance(&self, instance: u64) -> Result<Vec<PowerEntry>> {
+        self.request("F3GetF3PowerTableByInstance", vec![helper::serialize(&instance)])
+            .await
+    }
+}
@@ -0,0 +1,44 @@
+use super::JsonApi;
+use crate::error::Result;
+use crate::helper;
+use crate::types::{BigInt, BigIntWrapper};
+use forest_message::{self, unsigned_message::json::UnsignedMessageJson};
+use forest_blocks::{TipsetKeys, tipset::tipset_keys_json::TipsetKeysJson};
+
+#[async_trait::async_trait]
+pub trait GasApi: JsonApi {
+    /// Fills in the gas limit, fee cap and premium of `msg` left at zero.
+    async fn gas_estimate_message_gas(&self, msg: &forest_message::UnsignedMessage, key: &TipsetKeys)
+        -> Result<forest_message::UnsignedMessage>
+    {
+        let estimated: UnsignedMessageJson = self
+            .request(
+                "GasEstimateMessageGas",
+                vec![
+                    helper::serialize(&UnsignedMessageJson(msg.clone())),
+                    // no send spec, Lotus applies its default max fee
+                    helper::serialize(&Option::<()>::None),
+                    helper::serialize(&TipsetKeysJson(key.clone())),
+                ],
+            )
+            .await?;
+        Ok(estimated.0)
+    }
+
+    /// Fee cap for `msg` to be included within `max_queue_blocks` epochs.
+    async fn gas_estimate_fee_cap(&self, msg: &forest_message::UnsignedMessage, max_queue_blocks: i64, key: &TipsetKeys)
+        -> Result<BigInt>
+    {
+        let fee_cap: BigIntWrapper = self
+            .request(
+                "GasEstimateFeeCap",
+                vec![
+                    helper::serialize(&UnsignedMessageJson(msg.clone())),
+                    helper::serialize(&max_queue_blocks),
+                    helper::serialize(&TipsetKeysJson(key.clone())),
+                ],
+            )
+            .await?;
+        Ok(fee_cap.into_inner())
+    }
+}
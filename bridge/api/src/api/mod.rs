@@ -3,6 +3,9 @@ mod chain;
 //mod wallet;
 //mod sync;
 mod mpool;
+mod f3;
+mod gas;
+mod state;
 //mod miner;
 //mod client;
 
@@ -11,6 +14,9 @@ pub use chain::ChainApi;
 //pub use wallet::WalletApi;
 //pub use sync::SyncApi;
 pub use mpool::MpoolApi;
+pub use f3::F3Api;
+pub use gas::GasApi;
+pub use state::StateApi;
 //pub use miner::MinerApi;
 //pub use client::ClientApi;
 
@@ -49,4 +55,7 @@ impl ChainApi for Http {}
 //impl WalletApi for Http {}
 //impl SyncApi for Http {}
 impl MpoolApi for Http {}
+impl F3Api for Http {}
+impl GasApi for Http {}
+impl StateApi for Http {}
 //impl MinerApi for Http {}
@@ -5,11 +5,14 @@ use crate::types::{TipSetKey, SignedMessage, Cid, UnsignedMessage, Address, Mpoo
 BigIntWrapper, CidJson};
 use forest_address::{self, json::AddressJson};
 use forest_message::{self, signed_message::json::SignedMessageJson};
+use forest_blocks::{TipsetKeys, tipset::tipset_keys_json::TipsetKeysJson};
 #[async_trait::async_trait]
 pub trait MpoolApi: JsonApi {
-    async fn mpool_pending(&self, key: &TipSetKey) -> Result<Vec<SignedMessage>> {
-        self.request("MpoolPending", vec![helper::serialize(key)])
-            .await
+    /// Messages in the pool, on top of the head when `key` is empty.
+    async fn mpool_pending(&self, key: &TipsetKeys) -> Result<Vec<forest_message::SignedMessage>> {
+        let pending: Vec<SignedMessageJson> = self.request("MpoolPending", vec![helper::serialize(&TipsetKeysJson(key.clone()))])
+            .await?;
+        Ok(pending.into_iter().map(|msg| msg.0).collect())
     }
 
     async fn mpool_push(&self, signed_msg: &forest_message::SignedMessage) -> Result<forest_cid::Cid> {
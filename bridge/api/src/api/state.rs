@@ -0,0 +1,42 @@
+use super::JsonApi;
+use crate::error::Result;
+use crate::helper;
+use crate::types::{ActorState, MsgLookup};
+use forest_address::{self, json::AddressJson};
+use forest_cid::json::CidJson;
+use forest_blocks::{TipsetKeys, tipset::tipset_keys_json::TipsetKeysJson};
+
+#[async_trait::async_trait]
+pub trait StateApi: JsonApi {
+    /// The ID address of an actor, at the head when `key` is empty.
+    async fn state_lookup_id(&self, addr: &forest_address::Address, key: &TipsetKeys) -> Result<forest_address::Address> {
+        let id: AddressJson = self
+            .request("StateLookupID", vec![helper::serialize(&AddressJson(*addr)), helper::serialize(&TipsetKeysJson(key.clone()))])
+            .await?;
+        Ok(id.0)
+    }
+
+    /// The public key address of an account actor, at the head when `key` is empty.
+    async fn state_account_key(&self, addr: &forest_address::Address, key: &TipsetKeys) -> Result<forest_address::Address> {
+        let robust: AddressJson = self
+            .request("StateAccountKey", vec![helper::serialize(&AddressJson(*addr)), helper::serialize(&TipsetKeysJson(key.clone()))])
+            .await?;
+        Ok(robust.0)
+    }
+
+    async fn state_get_actor(&self, addr: &forest_address::Address, key: &TipsetKeys) -> Result<ActorState> {
+        self.request("StateGetActor", vec![helper::serialize(&AddressJson(*addr)), helper::serialize(&TipsetKeysJson(key.clone()))])
+            .await
+    }
+
+    /// Where a message was executed, `None` while it is not on chain.
+    async fn state_search_msg(&self, cid: &forest_cid::Cid) -> Result<Option<MsgLookup>> {
+        self.request("StateSearchMsg", vec![helper::serialize(&CidJson(cid.clone()))]).await
+    }
+
+    /// Waits until a message is executed and buried under `confidence` epochs.
+    async fn state_wait_msg(&self, cid: &forest_cid::Cid, confidence: u64) -> Result<MsgLookup> {
+        self.request("StateWaitMsg", vec![helper::serialize(&CidJson(cid.clone())), helper::serialize(&confidence)])
+            .await
+    }
+}
@@ -0,0 +1,65 @@
+use serde::{Deserialize, Serialize};
+use num_bigint::BigInt;
+use cid::Cid;
+use super::utils::{bigint_json, bytes_json, cid_json, vec_cid_json};
+
+/// A fast finality certificate, as returned by `F3GetCertificate`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct FinalityCertificate {
+    #[serde(rename = "GPBFTInstance")]
+    pub gpbft_instance: u64,
+    /// The decided chain, starting with the head decided by the previous instance.
+    #[serde(rename = "ECChain")]
+    pub ec_chain: Vec<ECTipSet>,
+    pub supplemental_data: SupplementalData,
+    /// RLE+ bitfield of the signers' indices in the power table, as run lengths
+    /// starting with a run of unset bits.
+    pub signers: Vec<u64>,
+    #[serde(with = "bytes_json")]
+    pub signature: Vec<u8>,
+    #[serde(default)]
+    pub power_table_delta: Option<Vec<PowerTableDelta>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ECTipSet {
+    #[serde(with = "vec_cid_json")]
+    pub key: Vec<Cid>,
+    pub epoch: i64,
+    #[serde(with = "cid_json")]
+    pub power_table: Cid,
+    pub commitments: [u8; 32],
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SupplementalData {
+    pub commitments: [u8; 32],
+    #[serde(with = "cid_json")]
+    pub power_table: Cid,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PowerTableDelta {
+    #[serde(rename = "ParticipantID")]
+    pub participant_id: u64,
+    #[serde(with = "bigint_json")]
+    pub power_delta: BigInt,
+    #[serde(with = "bytes_json")]
+    pub signing_key: Vec<u8>,
+}
+
+/// A participant of an F3 instance.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PowerEntry {
+    #[serde(rename = "ID")]
+    pub id: u64,
+    #[serde(with = "bigint_json")]
+    pub power: BigInt,
+    #[serde(with = "bytes_json")]
+    pub pub_key: Vec<u8>,
+}
@@ -8,6 +8,7 @@ use super::proofs::{ElectionProof, PoStProof};
 use super::crypto::Signature;
 use super::utils::{vec_cid_json, cid_json, bytes_json, bigint_json};
 use super::tipset::TipSet;
+use forest_blocks::tipset::tipset_json::TipsetJson;
 
 pub type ChainEpoch = i64;
 
@@ -84,6 +85,14 @@ pub struct HeadChange {
     pub val: TipSet,
 }
 
+/// A head change with the tipset decoded by forest, as the adapter consumes it.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct TipsetChange {
+    pub r#type: HeadChangeType,
+    pub val: TipsetJson,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
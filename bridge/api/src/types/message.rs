@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 pub use num_bigint::{BigInt};
 use cid::Cid;
-use super::utils::{bigint_json, bytes_json, vec_cid_json, cid_json};
+use super::utils::{bigint_json, bytes_json, vec_cid_json};
 use super::crypto::Signature;
 use super::address::{Address,Protocol};
 use super::bytes::Bytes;
@@ -70,14 +70,13 @@ pub struct UnsignedMessage {
 #[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct MessageReceipt {
-    /// The exit code of VM.
-    pub exit_code: u8,
+    /// The exit code of VM, zero if the message executed successfully.
+    pub exit_code: i64,
     /// The return bytes.
     #[serde(with = "bytes_json")]
     pub r#return: Vec<u8>,
-    /// The used number of gas.
-    #[serde(with = "bigint_json")]
-    pub gas_used: BigInt,
+    /// The used number of gas, a plain number in Lotus JSON.
+    pub gas_used: i64,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -92,9 +91,11 @@ pub struct BlockMessages {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct ParentMessage {
-    #[serde(with = "cid_json")]
-    pub cid: Cid,
-    pub message: UnsignedMessage,
+    /// CID of the message as included in the block, signed for secp256k1 senders.
+    #[serde(with = "forest_cid::json")]
+    pub cid: forest_cid::Cid,
+    #[serde(with = "forest_message::unsigned_message::json")]
+    pub message: forest_message::UnsignedMessage,
 }
 
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
@@ -128,8 +129,14 @@ impl UnsignedMessage {
 #[cfg(test)]
 mod tests {
 
+    use super::MessageReceipt;
+
     #[test]
     fn message_receipt_json_test() {
-
+        let json = r#"{"ExitCode":16,"Return":null,"GasUsed":1725186}"#;
+        let receipt: MessageReceipt = serde_json::from_str(json).unwrap();
+        assert_eq!(receipt.exit_code, 16);
+        assert!(receipt.r#return.is_empty());
+        assert_eq!(receipt.gas_used, 1725186);
     }
 }
\ No newline at end of file
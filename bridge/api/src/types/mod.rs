@@ -20,6 +20,8 @@ pub mod miner;
 pub mod sector;
 pub mod deal;
 pub mod piece;
+pub mod f3;
+pub mod state;
 
 pub use num_bigint::BigInt;
 pub use cid::Cid;
@@ -31,10 +33,11 @@ pub use libp2p_core::{PeerId};
 pub use utils::{peerid_json::{PeerIdWrapper, PeerIdRefWrapper}, cid_json::{CidJson, CidJsonRef}, bigint_json::BigIntWrapper};
 pub use tipset::{TipSet, TipSetKey};
 pub use crypto::DomainSeparationTag;
-pub use header::{ChainEpoch, BlockHeader, HeadChange};
+pub use header::{ChainEpoch, BlockHeader, HeadChange, HeadChangeType, TipsetChange};
 pub use hash::{H256, Randomness};
 pub use message::{BlockMessages, MessageReceipt, ParentMessage, UnsignedMessage, SignedMessage, ObjStat};
 pub use address::Address;
+pub use state::{ActorState, MsgLookup};
 pub use crypto::{SignatureType, Signature};
 pub use keystore::KeyInfo;
 pub use sync::{SyncState, SyncStateStage, ActiveSync};
@@ -42,4 +45,5 @@ pub use block::BlockMsg;
 pub use mpool::{MpoolChange, MpoolUpdate};
 pub use miner::{MiningBaseInfo, BlockTemplate};
 pub use sector::{SectorSize};
+pub use f3::FinalityCertificate;
 pub use deal::{Import, DealInfo, StartDealParams, QueryOffer, RetrievalOrder, CommPRet, FileRef};
\ No newline at end of file
@@ -0,0 +1,55 @@
+use serde::{Deserialize, Serialize};
+use super::utils::bigint_json;
+use super::BigInt;
+use super::MessageReceipt;
+use forest_blocks::{TipsetKeys, tipset::tipset_keys_json};
+
+/// The state of an actor as `StateGetActor` returns it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ActorState {
+    #[serde(with = "forest_cid::json")]
+    pub code: forest_cid::Cid,
+    #[serde(with = "forest_cid::json")]
+    pub head: forest_cid::Cid,
+    /// Nonce of the next message the actor sends.
+    pub nonce: u64,
+    #[serde(with = "bigint_json")]
+    pub balance: BigInt,
+}
+
+/// Where a message was executed, as `StateSearchMsg` and `StateWaitMsg` return it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct MsgLookup {
+    /// The message that was executed, a replacement of the one searched for if it was replaced.
+    #[serde(with = "forest_cid::json")]
+    pub message: forest_cid::Cid,
+    pub receipt: MessageReceipt,
+    /// The tipset the message was executed in.
+    #[serde(rename = "TipSet", with = "tipset_keys_json")]
+    pub tipset: TipsetKeys,
+    pub height: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ActorState, MsgLookup};
+
+    #[test]
+    fn actor_state_json_test() {
+        let json = r#"{"Code":{"/":"bafkqadlgnfwc6mjpmfrwg33vnz2a"},"Head":{"/":"bafy2bzaceaa43et73tgxsoh2xizd4mxhbrcfig4kqp25zfa5scdgkzppllyuu"},"Nonce":7,"Balance":"1000000000000000000"}"#;
+        let actor: ActorState = serde_json::from_str(json).unwrap();
+        assert_eq!(actor.nonce, 7);
+        assert_eq!(actor.balance.to_string(), "1000000000000000000");
+    }
+
+    #[test]
+    fn msg_lookup_json_test() {
+        let json = r#"{"Message":{"/":"bafy2bzaceaa43et73tgxsoh2xizd4mxhbrcfig4kqp25zfa5scdgkzppllyuu"},"Receipt":{"ExitCode":0,"Return":null,"GasUsed":488384},"ReturnDec":null,"TipSet":[{"/":"bafy2bzaceaa43et73tgxsoh2xizd4mxhbrcfig4kqp25zfa5scdgkzppllyuu"}],"Height":149063}"#;
+        let lookup: MsgLookup = serde_json::from_str(json).unwrap();
+        assert_eq!(lookup.receipt.gas_used, 488384);
+        assert_eq!(lookup.tipset.cids().len(), 1);
+        assert_eq!(lookup.height, 149063);
+    }
+}
@@ -47,12 +47,12 @@ pub mod bytes_json {
         base64::encode(bytes).serialize(serializer)
     }
 
-    /// Implement JSON deserialization of Vec<u8> using base64.
+    /// Implement JSON deserialization of Vec<u8> using base64, `null` being empty.
     pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
         where
             D: de::Deserializer<'de>,
     {
-        base64::decode(&String::deserialize(deserializer)?)
+        base64::decode(&Option::<String>::deserialize(deserializer)?.unwrap_or_default())
             .map_err(|err| de::Error::custom(format!("base64 decode error: {}", err)))
     }
 }
@@ -0,0 +1,103 @@
+//! Pays several withdrawals out with one Filecoin message.
+//!
+//! The batching actor is an FEVM contract exposing
+//! `function batchSend(bytes[] recipients, uint256[] amounts) external payable`
+//! which sends `amounts[i]` to the Filecoin address whose bytes are
+//! `recipients[i]` and reverts when any send fails, so that one receipt holds
+//! for the whole batch. It is invoked with the EVM actor's `InvokeContract`
+//! method, the params being the ABI encoded call as a CBOR byte string. The
+//! contract is `contracts/BatchSend.sol`, root sets the address it is deployed
+//! at with `set_batch_actor`.
+
+/// FRC-42 method number of `InvokeContract` on EVM actors.
+pub const INVOKE_CONTRACT_METHOD: u64 = 3_844_450_837;
+
+const BATCH_SEND_SIGNATURE: &[u8] = b"batchSend(bytes[],uint256[])";
+
+/// Params of the `batchSend` call paying `outputs`, pairs of address bytes and attoFIL.
+pub fn batch_send_params(outputs: &[(Vec<u8>, u128)]) -> Vec<u8> {
+    let mut call = sp_io::hashing::keccak_256(BATCH_SEND_SIGNATURE)[..4].to_vec();
+
+    let mut recipients = word(outputs.len() as u128).to_vec();
+    let mut tails = Vec::new();
+    for (address, _) in outputs {
+        // Offsets are counted from the first one.
+        recipients.extend_from_slice(&word((outputs.len() * 32 + tails.len()) as u128));
+        tails.extend_from_slice(&word(address.len() as u128));
+        tails.extend_from_slice(address);
+        tails.resize((tails.len() + 31) / 32 * 32, 0);
+    }
+    recipients.extend_from_slice(&tails);
+
+    call.extend_from_slice(&word(64));
+    call.extend_from_slice(&word(64 + recipients.len() as u128));
+    call.extend_from_slice(&recipients);
+    call.extend_from_slice(&word(outputs.len() as u128));
+    for (_, amount) in outputs {
+        call.extend_from_slice(&word(*amount));
+    }
+    cbor_bytes(&call)
+}
+
+fn word(value: u128) -> [u8; 32] {
+    let mut word = [0u8; 32];
+    word[16..].copy_from_slice(&value.to_be_bytes());
+    word
+}
+
+fn cbor_bytes(bytes: &[u8]) -> Vec<u8> {
+    let mut out = match bytes.len() {
+        len @ 0..=23 => vec![0x40 | len as u8],
+        len @ 24..=0xff => vec![0x58, len as u8],
+        len @ 0x100..=0xffff => {
+            let mut out = vec![0x59];
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+            out
+        },
+        len => {
+            let mut out = vec![0x5a];
+            out.extend_from_slice(&(len as u32).to_be_bytes());
+            out
+        },
+    };
+    out.extend_from_slice(bytes);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    #[test]
+    fn encodes_batch_send_call() {
+        // An f1 and an f0 address.
+        let f1 = [vec![1u8], vec![0xaa; 20]].concat();
+        let f0 = vec![0u8, 0xe8, 0x07];
+        let params = batch_send_params(&[(f1.clone(), 5), (f0.clone(), 1_000_000_000_000_000_000)]);
+
+        // CBOR byte string header, then the selector.
+        assert_eq!(&params[..3], &[0x59, 0x01, 0x84]);
+        let call = &params[3..];
+        assert_eq!(call.len(), 4 + 12 * 32);
+        assert_eq!(&call[..4], &sp_io::hashing::keccak_256(b"batchSend(bytes[],uint256[])")[..4]);
+
+        let words: Vec<&[u8]> = call[4..].chunks(32).collect();
+        let num = |w: &[u8]| u128::from_be_bytes(w[16..].try_into().unwrap());
+        // Offsets of both arrays.
+        assert_eq!(num(words[0]), 64);
+        assert_eq!(num(words[1]), 64 + 7 * 32);
+        // bytes[]: length, offsets of the elements, then each element padded.
+        assert_eq!(num(words[2]), 2);
+        assert_eq!(num(words[3]), 64);
+        assert_eq!(num(words[4]), 128);
+        assert_eq!(num(words[5]), 21);
+        assert_eq!(&words[6][..21], &f1[..]);
+        assert_eq!(num(words[7]), 3);
+        assert_eq!(&words[8][..3], &f0[..]);
+        // uint256[]
+        assert_eq!(num(words[9]), 2);
+        assert_eq!(num(words[10]), 5);
+        assert_eq!(num(words[11]), 1_000_000_000_000_000_000);
+    }
+}
@@ -43,4 +43,54 @@ impl <Block,B,C>ChainState<Block,B,C>
             .tss_pub_key(&at)
             .unwrap()
     }
+
+    pub fn fc_best_height(&self) -> u64 {
+        let info = self.client.info();
+        let at: BlockId<Block> = BlockId::Hash(info.best_hash);
+
+        self.client
+            .runtime_api()
+            .fc_best_height(&at)
+            .unwrap()
+    }
+
+    pub fn fc_finalized_height(&self) -> u64 {
+        let info = self.client.info();
+        let at: BlockId<Block> = BlockId::Hash(info.best_hash);
+
+        self.client
+            .runtime_api()
+            .fc_finalized_height(&at)
+            .unwrap()
+    }
+
+    pub fn fc_deposit_final_height(&self) -> u64 {
+        let info = self.client.info();
+        let at: BlockId<Block> = BlockId::Hash(info.best_hash);
+
+        self.client
+            .runtime_api()
+            .fc_deposit_final_height(&at)
+            .unwrap()
+    }
+
+    pub fn fc_f3_instance(&self) -> Option<u64> {
+        let info = self.client.info();
+        let at: BlockId<Block> = BlockId::Hash(info.best_hash);
+
+        self.client
+            .runtime_api()
+            .fc_f3_instance(&at)
+            .unwrap()
+    }
+
+    pub fn fc_bridge_id_address(&self) -> Vec<u8> {
+        let info = self.client.info();
+        let at: BlockId<Block> = BlockId::Hash(info.best_hash);
+
+        self.client
+            .runtime_api()
+            .fc_bridge_id_address(&at)
+            .unwrap()
+    }
 }
\ No newline at end of file
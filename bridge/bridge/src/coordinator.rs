@@ -0,0 +1,261 @@
+//! Picks the node that proposes and broadcasts each Filecoin transfer.
+//!
+//! Every node sees the same withdrawals and refunds. Were they all to act, a
+//! transfer would be signed in as many sessions and sent under racing nonces.
+//! Instead the members of the TSS key are ranked per transfer by the hash of the
+//! transfer id and their key, and the first one coordinates: it builds the
+//! message and proposes it on chain, the others only join the signing session
+//! of the proposed message. Once it is signed the coordinator broadcasts it and
+//! reports it sent.
+//!
+//! A turn lasts `deadline` blocks. When a transfer is still not sent at the end
+//! of a turn it passes to the next member of the ranking, who proposes it or,
+//! if it was proposed and signed already, broadcasts the signed message. Turns
+//! start over from the proposer's when a transfer is proposed.
+
+use std::collections::BTreeMap;
+use codec::Encode;
+use parking_lot::Mutex;
+
+/// Members ranked by the hash of the transfer id and their key, the coordinator first.
+pub fn rank(id: &[u8], members: &[Vec<u8>]) -> Vec<usize> {
+    let mut ranked: Vec<([u8; 32], usize)> = members.iter()
+        .enumerate()
+        .map(|(i, member)| (sp_io::hashing::blake2_256(&[id, &member[..]].concat()), i))
+        .collect();
+    ranked.sort();
+    ranked.into_iter().map(|(_, i)| i).collect()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Turn {
+    /// Build the transfer and propose it on chain.
+    Propose,
+    /// Broadcast the signed message of the proposed transfer.
+    Broadcast(Vec<u8>),
+}
+
+#[derive(Debug)]
+struct Pending<P> {
+    /// What the transfer pays out, proposals are checked against it.
+    payout: P,
+    /// Block the current proposal, or the request if there is none, was seen at.
+    since: u64,
+    /// Last turn this node took.
+    taken: Option<u64>,
+    proposal: Option<Vec<u8>>,
+    /// Message this node proposed.
+    ours: Option<Vec<u8>>,
+    /// Nonce reserved for the message this node proposed.
+    reserved: Option<u64>,
+    /// Whether this node broadcasts the proposal once it is signed.
+    mine: bool,
+    signed: Option<Vec<u8>>,
+    sent: bool,
+}
+
+#[derive(Debug)]
+pub struct Coordinator<K, P> {
+    /// Position of this node's key among the members.
+    me: usize,
+    deadline: u64,
+    pending: Mutex<BTreeMap<K, Pending<P>>>,
+}
+
+impl<K: Ord + Clone + Encode, P: Clone> Coordinator<K, P> {
+    pub fn new(me: usize, deadline: u64) -> Self {
+        Coordinator {
+            me,
+            deadline: deadline.max(1),
+            pending: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Tracks a transfer requested at block `at`.
+    pub fn request(&self, id: K, payout: P, at: u64) {
+        self.pending.lock().entry(id).or_insert(Pending {
+            payout,
+            since: at,
+            taken: None,
+            proposal: None,
+            ours: None,
+            reserved: None,
+            mine: false,
+            signed: None,
+            sent: false,
+        });
+    }
+
+    /// What a tracked transfer pays out.
+    pub fn expected(&self, id: &K) -> Option<P> {
+        self.pending.lock().get(id).map(|p| p.payout.clone())
+    }
+
+    /// The message last proposed for a transfer.
+    pub fn proposal(&self, id: &K) -> Option<Vec<u8>> {
+        self.pending.lock().get(id).and_then(|p| p.proposal.clone())
+    }
+
+    /// The last proposals of the tracked transfers other than `id`.
+    pub fn other_proposals(&self, id: &K) -> Vec<Vec<u8>> {
+        self.pending.lock().iter()
+            .filter(|(other, _)| *other != id)
+            .filter_map(|(_, p)| p.proposal.clone())
+            .collect()
+    }
+
+    /// Remembers the message this node is about to propose.
+    pub fn propose(&self, id: &K, message: Vec<u8>, reserved: Option<u64>) {
+        if let Some(p) = self.pending.lock().get_mut(id) {
+            p.ours = Some(message);
+            p.reserved = p.reserved.or(reserved);
+        }
+    }
+
+    /// The nonce reserved for this node's last proposal, to reuse when it did not make it.
+    pub fn reserved(&self, id: &K) -> Option<u64> {
+        self.pending.lock().get(id).and_then(|p| p.reserved)
+    }
+
+    /// Records a proposal seen on chain at block `at`. Returns the nonce this
+    /// node reserved for a proposal of its own, which it will not broadcast.
+    pub fn proposed(&self, id: &K, message: Vec<u8>, at: u64) -> Option<u64> {
+        let mut pending = self.pending.lock();
+        let p = pending.get_mut(id)?;
+        p.mine = p.ours.as_ref() == Some(&message);
+        p.proposal = Some(message);
+        p.signed = None;
+        p.sent = false;
+        p.since = at;
+        p.taken = None;
+        p.ours = None;
+        let reserved = p.reserved.take();
+        if p.mine { None } else { reserved }
+    }
+
+    /// Records the signed proposal, returns whether this node broadcasts it.
+    pub fn signed(&self, id: &K, signed: Vec<u8>) -> bool {
+        match self.pending.lock().get_mut(id) {
+            Some(p) => {
+                p.signed = Some(signed);
+                p.mine
+            },
+            None => false,
+        }
+    }
+
+    /// Whether the last proposal of a tracked transfer is reported sent.
+    pub fn is_sent(&self, id: &K) -> bool {
+        self.pending.lock().get(id).map_or(false, |p| p.sent)
+    }
+
+    pub fn sent(&self, id: &K) {
+        if let Some(p) = self.pending.lock().get_mut(id) {
+            p.sent = true;
+        }
+    }
+
+    /// Stops tracking a transfer whose result is settled.
+    pub fn forget(&self, id: &K) {
+        self.pending.lock().remove(id);
+    }
+
+    /// What this node has to do at block `now` for the transfers whose turn it holds.
+    pub fn turns(&self, now: u64, members: &[Vec<u8>]) -> Vec<(K, Turn)> {
+        if members.is_empty() {
+            return Vec::new();
+        }
+        let mut turns = Vec::new();
+        for (id, p) in self.pending.lock().iter_mut() {
+            if p.sent {
+                continue;
+            }
+            let turn = now.saturating_sub(p.since) / self.deadline;
+            // The first turn after a proposal is the proposer's.
+            if p.taken == Some(turn) || (p.proposal.is_some() && turn == 0) {
+                continue;
+            }
+            let ranked = rank(&id.encode(), members);
+            if ranked[(turn % ranked.len() as u64) as usize] != self.me {
+                continue;
+            }
+            p.taken = Some(turn);
+            match (&p.proposal, &p.signed) {
+                (None, _) => turns.push((id.clone(), Turn::Propose)),
+                (Some(_), Some(signed)) => {
+                    p.mine = true;
+                    turns.push((id.clone(), Turn::Broadcast(signed.clone())));
+                },
+                // The members are still signing it.
+                (Some(_), None) => {},
+            }
+        }
+        turns
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn members() -> Vec<Vec<u8>> {
+        (1u8..=3).map(|i| vec![i; 65]).collect()
+    }
+
+    #[test]
+    fn ranking_is_deterministic_and_spreads_transfers() {
+        let members = members();
+        let ranked = rank(&1u64.encode(), &members);
+        assert_eq!(ranked, rank(&1u64.encode(), &members));
+        let mut sorted = ranked.clone();
+        sorted.sort();
+        assert_eq!(sorted, vec![0, 1, 2]);
+        let coordinators: std::collections::BTreeSet<usize> = (0u64..32)
+            .map(|uid| rank(&uid.encode(), &members)[0])
+            .collect();
+        assert_eq!(coordinators.len(), 3);
+    }
+
+    #[test]
+    fn only_the_coordinator_acts_and_fails_over() {
+        let members = members();
+        let ranked = rank(&7u64.encode(), &members);
+        let nodes: Vec<Coordinator<u64, u128>> = (0..3).map(|me| Coordinator::new(me, 10)).collect();
+        for node in &nodes {
+            node.request(7, 100, 50);
+        }
+        let turns = |now| nodes.iter().map(|node| node.turns(now, &members)).collect::<Vec<_>>();
+
+        // Only the first ranked member proposes, once per turn.
+        let first = turns(51);
+        assert_eq!(first[ranked[0]], vec![(7, Turn::Propose)]);
+        assert_eq!(first.iter().filter(|t| !t.is_empty()).count(), 1);
+        assert!(turns(55).iter().all(|t| t.is_empty()));
+
+        // It stays silent, the second ranked member proposes instead.
+        let second = turns(60);
+        assert_eq!(second[ranked[1]], vec![(7, Turn::Propose)]);
+        assert_eq!(second.iter().filter(|t| !t.is_empty()).count(), 1);
+
+        let message = b"message".to_vec();
+        nodes[ranked[1]].propose(&7, message.clone(), Some(4));
+        nodes[ranked[0]].propose(&7, b"late".to_vec(), Some(4));
+        assert_eq!(nodes[ranked[1]].proposed(&7, message.clone(), 61), None);
+        assert_eq!(nodes[ranked[0]].proposed(&7, message.clone(), 61), Some(4));
+        assert_eq!(nodes[ranked[2]].proposed(&7, message, 61), None);
+        assert!(nodes[ranked[1]].signed(&7, b"signed".to_vec()));
+        assert!(!nodes[ranked[2]].signed(&7, b"signed".to_vec()));
+
+        // The proposer broadcasts during the first turn, then retries during its
+        // own turn, then the member after it broadcasts the signed message.
+        assert!(turns(65).iter().all(|t| t.is_empty()));
+        assert_eq!(turns(71)[ranked[1]], vec![(7, Turn::Broadcast(b"signed".to_vec()))]);
+        let third = turns(81);
+        assert_eq!(third[ranked[2]], vec![(7, Turn::Broadcast(b"signed".to_vec()))]);
+        assert_eq!(third.iter().filter(|t| !t.is_empty()).count(), 1);
+        for node in &nodes {
+            node.sent(&7);
+        }
+        assert!(turns(100).iter().all(|t| t.is_empty()));
+    }
+}
@@ -0,0 +1,54 @@
+//! The last height a chain follower is done with, a Filecoin epoch or a block
+//! of this chain, kept in a file so that a restarted node picks up where it
+//! stopped.
+
+use std::{fs, io, path::PathBuf};
+
+pub struct ScanCursor {
+    path: Option<PathBuf>,
+}
+
+impl ScanCursor {
+    /// Without a path nothing is persisted and the follower starts from the head.
+    pub fn new(path: Option<PathBuf>) -> Self {
+        ScanCursor { path }
+    }
+
+    pub fn load(&self) -> Option<u64> {
+        let path = self.path.as_ref()?;
+        fs::read_to_string(path).ok()?.trim().parse().ok()
+    }
+
+    pub fn store(&self, height: u64) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Written aside and renamed, a crash must not leave a truncated cursor.
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, height.to_string())?;
+        fs::rename(tmp, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn persists_height() {
+        let path = std::env::temp_dir().join(format!("fc-scan-cursor-{}", std::process::id())).join("cursor");
+        let cursor = ScanCursor::new(Some(path.clone()));
+        assert_eq!(cursor.load(), None);
+        cursor.store(149_063).unwrap();
+        assert_eq!(ScanCursor::new(Some(path.clone())).load(), Some(149_063));
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        let memory = ScanCursor::new(None);
+        memory.store(1).unwrap();
+        assert_eq!(memory.load(), None);
+    }
+}
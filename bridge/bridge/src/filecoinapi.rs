@@ -1,24 +1,287 @@
 use tokio::runtime::Runtime as tokioRuntime;
-use lotus_api_forest::api::MpoolApi;
+use lotus_api_forest::{api::{ChainApi, GasApi, MpoolApi, StateApi}, error::Error as LotusError, types::MsgLookup};
+use forest_blocks::{Tipset, TipsetKeys};
+use std::convert::TryFrom;
 use forest_message;
 use forest_cid;
 use forest_vm::{self, Serialized};
 use forest_encoding::Cbor;
 use forest_crypto;
-use num_traits::cast::FromPrimitive;
-use std::env;
+use num_traits::cast::{FromPrimitive, ToPrimitive};
+use std::str::FromStr;
+use crate::batch::{batch_send_params, INVOKE_CONTRACT_METHOD};
+use crate::proof::{build_message_proof, ProofBuildError};
+use pallet_fc_relay::MessageProof;
+use log::debug;
 
-pub fn get_nonce(addr: forest_address::Address) -> u64 {
+/// Blocks a message may wait in the pool before it is included, for the fee cap estimate.
+const MAX_QUEUE_BLOCKS: i64 = 20;
+
+/// Gas limit of a whole block, no message can use more.
+const BLOCK_GAS_LIMIT: i64 = 10_000_000_000;
+
+/// API of the local Lotus node.
+pub const DEFAULT_LOTUS_URL: &str = "http://127.0.0.1:1234/rpc/v0";
+
+/// Filecoin network the addresses of the bridge are written for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FcNetwork {
+    Mainnet,
+    Testnet,
+}
+
+impl FromStr for FcNetwork {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "mainnet" => Ok(FcNetwork::Mainnet),
+            "testnet" => Ok(FcNetwork::Testnet),
+            _ => Err(format!("unknown Filecoin network {}, expected mainnet or testnet", s)),
+        }
+    }
+}
+
+/// The Lotus node the bridge reads Filecoin from and pushes its messages to.
+#[derive(Clone, Debug)]
+pub struct LotusConfig {
+    pub url: String,
+    /// Token of the Lotus API, messages cannot be pushed without one.
+    pub token: Option<String>,
+    pub network: FcNetwork,
+}
+
+impl Default for LotusConfig {
+    fn default() -> Self {
+        LotusConfig {
+            url: DEFAULT_LOTUS_URL.into(),
+            token: None,
+            network: FcNetwork::Testnet,
+        }
+    }
+}
+
+impl LotusConfig {
+    pub fn http(&self) -> lotus_api_forest::Http {
+        match &self.token {
+            Some(token) => lotus_api_forest::Http::new_auth(&self.url, token.clone()),
+            None => lotus_api_forest::Http::new(&self.url),
+        }
+    }
+
+    /// `addr` written for the configured network.
+    pub fn address(&self, mut addr: forest_address::Address) -> forest_address::Address {
+        addr.set_network(match self.network {
+            FcNetwork::Mainnet => forest_address::Network::Mainnet,
+            FcNetwork::Testnet => forest_address::Network::Testnet,
+        });
+        addr
+    }
+}
+
+/// Limits on the gas of the messages the bridge signs.
+#[derive(Clone, Debug)]
+pub struct GasConfig {
+    /// Highest fee cap in attoFIL per gas unit, messages estimated above it are not signed.
+    pub max_fee_cap: u128,
+    /// Highest fee in attoFIL a message may pay, its gas limit times its fee cap.
+    pub max_fee: u128,
+    /// Percent added to the estimated gas limit.
+    pub limit_margin: u64,
+    /// Epochs a message may stay in the pool before it is replaced with a higher premium.
+    pub replace_after: u64,
+}
+
+impl Default for GasConfig {
+    fn default() -> Self {
+        GasConfig {
+            max_fee_cap: 10_000_000_000,
+            max_fee: 1_000_000_000_000_000_000,
+            limit_margin: 25,
+            replace_after: 20,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum GasError {
+    Lotus(LotusError),
+    /// The estimated fee cap is above `GasConfig::max_fee_cap`.
+    FeeCapTooHigh(u128),
+    /// The gas limit times the fee cap is above `GasConfig::max_fee`.
+    FeeTooHigh(u128),
+    /// Lotus rejects messages whose premium is above the fee cap.
+    PremiumAboveFeeCap,
+    /// The payout cannot be made with one message.
+    Payout(String),
+}
+
+impl From<LotusError> for GasError {
+    fn from(e: LotusError) -> Self {
+        GasError::Lotus(e)
+    }
+}
+
+pub fn get_nonce(lotus: &LotusConfig, addr: forest_address::Address) -> u64 {
     let mut rt = tokioRuntime::new().unwrap();
-    let http = lotus_api_forest::Http::new("http://127.0.0.1:1234/rpc/v0");
-    let ret:u64 = rt.block_on(http.mpool_get_nonce(&addr)).unwrap();
+    let http = lotus.http();
+    let ret:u64 = rt.block_on(http.mpool_get_nonce(&lotus.address(addr))).unwrap();
     ret
 }
 
-pub fn message_create(from:Vec<u8>,to:Vec<u8>,val:u128,) -> (forest_message::UnsignedMessage ,Vec<u8>){
-    let from_addr = forest_address::Address::new_secp256k1(&from).unwrap();
-    let to_addr = forest_address::Address::from_bytes(&to).unwrap();
-    let nonce = get_nonce(from_addr.clone());
+/// The nonce of `addr` on chain and the nonces of its messages in the pool.
+pub fn account_nonces(lotus: &LotusConfig, addr: &forest_address::Address) -> Result<(u64, Vec<u64>), LotusError> {
+    let mut rt = tokioRuntime::new().unwrap();
+    let http = lotus.http();
+    let head = TipsetKeys::new(Vec::new());
+    let actor = rt.block_on(http.state_get_actor(&lotus.address(addr.clone()), &head))?;
+    let pooled = rt.block_on(http.mpool_pending(&head))?
+        .into_iter()
+        .filter(|msg| msg.message.from.to_bytes() == addr.to_bytes())
+        .map(|msg| msg.message.sequence)
+        .collect();
+    Ok((actor.nonce, pooled))
+}
+
+pub fn chain_head_epoch(lotus: &LotusConfig) -> Result<u64, LotusError> {
+    let mut rt = tokioRuntime::new().unwrap();
+    let http = lotus.http();
+    let head: Tipset = rt.block_on(http.chain_head())?.into();
+    Ok(head.epoch() as u64)
+}
+
+pub fn get_message(lotus: &LotusConfig, cid: &str) -> Result<forest_message::UnsignedMessage, LotusError> {
+    let cid = forest_cid::Cid::try_from(cid).map_err(|e| LotusError::Json(format!("{:?}", e)))?;
+    let mut rt = tokioRuntime::new().unwrap();
+    let http = lotus.http();
+    rt.block_on(http.chain_get_message(&cid))
+}
+
+/// Where a message was executed, `None` while it is not on chain.
+pub fn search_message(lotus: &LotusConfig, cid: &str) -> Result<Option<MsgLookup>, LotusError> {
+    let cid = forest_cid::Cid::try_from(cid).map_err(|e| LotusError::Json(format!("{:?}", e)))?;
+    let mut rt = tokioRuntime::new().unwrap();
+    let http = lotus.http();
+    rt.block_on(http.state_search_msg(&cid))
+}
+
+/// Proves the execution `lookup` found. The receipt is in the tipset it names,
+/// the message in the parent of that tipset.
+pub fn prove_lookup(lotus: &LotusConfig, lookup: &MsgLookup) -> Result<MessageProof, ProofBuildError> {
+    let mut rt = tokioRuntime::new().unwrap();
+    let http = lotus.http();
+    let child: Tipset = rt.block_on(http.chain_get_tipset(&lookup.tipset))?.into();
+    let tipset: Tipset = rt.block_on(http.chain_get_tipset(child.parents()))?.into();
+    rt.block_on(build_message_proof(&http, &tipset, &child, &lookup.message.to_bytes()))
+}
+
+/// Raises the premium of a pooled message enough for Lotus to replace it, with
+/// a fee cap covering the base fee Lotus estimates now.
+pub fn replace_message(lotus: &LotusConfig, message: forest_message::UnsignedMessage, gas: &GasConfig)
+    -> Result<forest_message::UnsignedMessage, GasError>
+{
+    let mut rt = tokioRuntime::new().unwrap();
+    let http = lotus.http();
+    let head = TipsetKeys::new(Vec::new());
+    let fee_cap = rt.block_on(http.gas_estimate_fee_cap(&message, MAX_QUEUE_BLOCKS, &head))?
+        .to_u128()
+        .unwrap_or(u128::max_value());
+    replacement_gas(message, fee_cap, gas)
+}
+
+/// Lowest amount a replacement may pay for gas where the replaced message
+/// paid `amount`: Lotus requires the premium to rise by a quarter.
+pub fn replacement_raise(amount: &forest_vm::TokenAmount) -> u128 {
+    let amount = amount.to_u128().unwrap_or(u128::max_value());
+    amount.saturating_add(amount / 4).saturating_add(1)
+}
+
+/// Raises the premium and the fee cap of `message` by the ratio Lotus requires
+/// of replacements, and the fee cap at least to `estimated_fee_cap`.
+fn replacement_gas(mut message: forest_message::UnsignedMessage, estimated_fee_cap: u128, gas: &GasConfig)
+    -> Result<forest_message::UnsignedMessage, GasError>
+{
+    let premium = replacement_raise(&message.gas_premium);
+    let fee_cap = replacement_raise(&message.gas_fee_cap).max(estimated_fee_cap).max(premium);
+    message.gas_premium = forest_vm::TokenAmount::from_u128(premium).unwrap();
+    message.gas_fee_cap = forest_vm::TokenAmount::from_u128(fee_cap).unwrap();
+    check_gas(&message, gas)?;
+    Ok(message)
+}
+
+/// Checks the gas of a message against the limits the bridge signs with.
+pub fn check_gas(message: &forest_message::UnsignedMessage, gas: &GasConfig) -> Result<(), GasError> {
+    let fee_cap = message.gas_fee_cap.to_u128().unwrap_or(u128::max_value());
+    if fee_cap > gas.max_fee_cap {
+        return Err(GasError::FeeCapTooHigh(fee_cap));
+    }
+    let fee = fee_cap.saturating_mul(message.gas_limit.max(0) as u128);
+    if fee > gas.max_fee {
+        return Err(GasError::FeeTooHigh(fee));
+    }
+    if message.gas_premium > message.gas_fee_cap {
+        return Err(GasError::PremiumAboveFeeCap);
+    }
+    Ok(())
+}
+
+/// The highest gas limit the bridge gives `message`, from what Lotus estimates now.
+pub fn max_gas_limit(lotus: &LotusConfig, message: &forest_message::UnsignedMessage, gas: &GasConfig)
+    -> Result<i64, LotusError>
+{
+    let mut message = message.clone();
+    message.gas_limit = 0;
+    message.gas_fee_cap = forest_vm::TokenAmount::from_u128(0).unwrap();
+    message.gas_premium = forest_vm::TokenAmount::from_u128(0).unwrap();
+    let mut rt = tokioRuntime::new().unwrap();
+    let estimated = rt.block_on(lotus.http().gas_estimate_message_gas(&message, &TipsetKeys::new(Vec::new())))?;
+    Ok(with_margin(estimated.gas_limit, gas))
+}
+
+/// An estimated gas limit raised by the margin of `gas`, up to the block gas limit.
+fn with_margin(limit: i64, gas: &GasConfig) -> i64 {
+    limit.saturating_add(limit * gas.limit_margin as i64 / 100).min(BLOCK_GAS_LIMIT)
+}
+
+/// What one message of the bridge pays out.
+#[derive(Clone, Debug, PartialEq, codec::Encode, codec::Decode)]
+pub struct Payout {
+    /// Receiver address bytes and attoFIL.
+    pub outputs: Vec<(Vec<u8>, u128)>,
+    /// Address of the batch actor paying several outputs, empty for a single one.
+    pub batch_actor: Vec<u8>,
+}
+
+impl Payout {
+    /// Receiver, value, method and params of the message paying it out.
+    pub fn call(&self) -> Result<(Vec<u8>, u128, u64, Vec<u8>), String> {
+        let value = self.outputs.iter()
+            .try_fold(0u128, |sum, (_, amount)| sum.checked_add(*amount))
+            .ok_or_else(|| "payout overflows".to_string())?;
+        match &self.outputs[..] {
+            [] => Err("empty payout".to_string()),
+            [(receiver, _)] if self.batch_actor.is_empty() => Ok((receiver.clone(), value, 0, Vec::new())),
+            _ if self.batch_actor.is_empty() => Err("several outputs without a batch actor".to_string()),
+            outputs => Ok((self.batch_actor.clone(), value, INVOKE_CONTRACT_METHOD, batch_send_params(outputs))),
+        }
+    }
+}
+
+/// Builds the message of a payout with the gas Lotus estimates for it.
+pub fn payout_message_create(lotus:&LotusConfig,from:Vec<u8>,payout:&Payout,nonce:u64,gas:&GasConfig) -> Result<(forest_message::UnsignedMessage ,Vec<u8>), GasError>{
+    let (to, value, method, params) = payout.call().map_err(GasError::Payout)?;
+    build_message(lotus, from, to, value, method, params, nonce, gas)
+}
+
+/// Builds a transfer with the gas Lotus estimates for it.
+pub fn message_create(lotus:&LotusConfig,from:Vec<u8>,to:Vec<u8>,val:u128,nonce:u64,gas:&GasConfig) -> Result<(forest_message::UnsignedMessage ,Vec<u8>), GasError>{
+    build_message(lotus, from, to, val, 0, Vec::new(), nonce, gas)
+}
+
+fn build_message(lotus:&LotusConfig,from:Vec<u8>,to:Vec<u8>,val:u128,method:u64,params:Vec<u8>,nonce:u64,gas:&GasConfig) -> Result<(forest_message::UnsignedMessage ,Vec<u8>), GasError>{
+    let from_addr = lotus.address(forest_address::Address::new_secp256k1(&from).unwrap());
+    let to_addr = forest_address::Address::from_bytes(&to).map_err(|e| GasError::Payout(format!("{:?}", e)))?;
+    let to_addr = lotus.address(to_addr);
 
     println!("from {:?}",from);
     println!("to {:?}",to);
@@ -32,32 +295,94 @@ pub fn message_create(from:Vec<u8>,to:Vec<u8>,val:u128,) -> (forest_message::Uns
 //        .from(to_addr)
 //        .build()
 //        .unwrap();
-    let unsignedtx = forest_message::UnsignedMessage {
+    let mut unsignedtx = forest_message::UnsignedMessage {
         version: 0,
         to: to_addr,
         from: from_addr,
         sequence: nonce,
         value: forest_vm::TokenAmount::from_u128(val).unwrap(),
-        method_num: 0u64,
-        params: Serialized::new(vec![0u8]),
-        gas_limit: 600000i64,
-        gas_fee_cap:forest_vm::TokenAmount::from_u128(600000u128).unwrap(),
-        gas_premium:forest_vm::TokenAmount::from_u128(600000u128).unwrap(),
+        method_num: method,
+        params: Serialized::new(params),
+        gas_limit: 0i64,
+        gas_fee_cap:forest_vm::TokenAmount::from_u128(0u128).unwrap(),
+        gas_premium:forest_vm::TokenAmount::from_u128(0u128).unwrap(),
     };
-    (unsignedtx.clone(),unsignedtx.to_signing_bytes())
+
+    let mut rt = tokioRuntime::new().unwrap();
+    let http = lotus.http();
+    let head = TipsetKeys::new(Vec::new());
+    let estimated = rt.block_on(http.gas_estimate_message_gas(&unsignedtx, &head))?;
+    unsignedtx.gas_limit = with_margin(estimated.gas_limit, gas);
+
+    let fee_cap = rt.block_on(http.gas_estimate_fee_cap(&unsignedtx, MAX_QUEUE_BLOCKS, &head))?
+        .to_u128()
+        .unwrap_or(u128::max_value());
+    if fee_cap > gas.max_fee_cap {
+        return Err(GasError::FeeCapTooHigh(fee_cap));
+    }
+    let fee_cap = forest_vm::TokenAmount::from_u128(fee_cap).unwrap();
+    unsignedtx.gas_premium = estimated.gas_premium.min(fee_cap.clone());
+    unsignedtx.gas_fee_cap = fee_cap;
+    check_gas(&unsignedtx, gas)?;
+    debug!(target:"keysign", "gas limit {} fee cap {} premium {}", unsignedtx.gas_limit, unsignedtx.gas_fee_cap, unsignedtx.gas_premium);
+
+    Ok((unsignedtx.clone(),unsignedtx.to_signing_bytes()))
 }
 
-pub fn send_fc_message(message: forest_message::SignedMessage) -> Result<forest_cid::Cid,String> {
-    let auth_res = env::var("auth");
-    if auth_res.is_err(){
-        return Err("no auth key".to_string());
+pub fn send_fc_message(lotus: &LotusConfig, message: forest_message::SignedMessage) -> Result<forest_cid::Cid,String> {
+    if lotus.token.is_none(){
+        return Err("no Lotus API token".to_string());
     }
 
-    let auth = auth_res.unwrap();
     let mut rt = tokioRuntime::new().unwrap();
-    let http = lotus_api_forest::Http::new_auth("http://127.0.0.1:1234/rpc/v0",auth);
+    let http = lotus.http();
     match rt.block_on(http.mpool_push(&message)){
         Ok(cid) => Ok(cid),
         Err(err) => Err("".to_string()),
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pooled(fee_cap: u128, premium: u128) -> forest_message::UnsignedMessage {
+        forest_message::UnsignedMessage {
+            version: 0,
+            to: forest_address::Address::new_id(1),
+            from: forest_address::Address::new_id(2),
+            sequence: 7,
+            value: forest_vm::TokenAmount::from_u128(1).unwrap(),
+            method_num: 0,
+            params: Serialized::new(Vec::new()),
+            gas_limit: 1_000_000,
+            gas_fee_cap: forest_vm::TokenAmount::from_u128(fee_cap).unwrap(),
+            gas_premium: forest_vm::TokenAmount::from_u128(premium).unwrap(),
+        }
+    }
+
+    #[test]
+    fn replaces_messages_priced_out_by_the_base_fee() {
+        let gas = GasConfig { max_fee_cap: 10_000, ..GasConfig::default() };
+        // The base fee rose above the fee cap of the pooled message.
+        let replaced = replacement_gas(pooled(1_000, 100), 5_000, &gas).unwrap();
+        assert_eq!(replaced.gas_fee_cap.to_u128(), Some(5_000));
+        assert_eq!(replaced.gas_premium.to_u128(), Some(126));
+        assert_eq!(replaced.sequence, 7);
+
+        // Raised by the replacement ratio when the estimate is lower.
+        let replaced = replacement_gas(pooled(1_000, 100), 500, &gas).unwrap();
+        assert_eq!(replaced.gas_fee_cap.to_u128(), Some(1_251));
+
+        match replacement_gas(pooled(1_000, 100), 20_000, &gas) {
+            Err(GasError::FeeCapTooHigh(20_000)) => {},
+            other => panic!("unexpected {:?}", other.map(|m| m.gas_fee_cap)),
+        }
+
+        let gas = GasConfig { max_fee: 4_000_000_000, ..gas };
+        match replacement_gas(pooled(1_000, 100), 5_000, &gas) {
+            Err(GasError::FeeTooHigh(5_000_000_000)) => {},
+            other => panic!("unexpected {:?}", other.map(|m| m.gas_fee_cap)),
+        }
+    }
+}
@@ -0,0 +1,337 @@
+//! Bridge operations recorded in the node's data directory.
+//!
+//! Key generations, signatures, submitted extrinsics and Filecoin transfers are
+//! recorded as jobs with their state, attempts and result, in a JSON file that
+//! is rewritten on every change. The jobs a stopped node did not finish are
+//! resumed when it starts again, finished ones are kept without the calls and
+//! transfers they carried, as a short history. Most are safe to replay: the
+//! runtime rejects deposits, tipsets and reports it has seen already, a key is
+//! not generated again once it is on chain and a transfer is only tracked once
+//! by the coordinator. Other extrinsics, e.g. a withdrawal request, would take effect
+//! twice, so one is not submitted again while its job is unfinished. Signing
+//! sessions are not resumed, the other parties finished or gave them up while
+//! the node was stopped.
+//!
+//! A file that cannot be read or parsed stops the node rather than dropping
+//! the jobs it holds.
+
+use std::{fs, io, path::PathBuf};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+/// Finished jobs kept in the file, the oldest ones are dropped.
+const FINISHED_KEPT: usize = 100;
+
+/// Finished extrinsic jobs kept in the file, there are many more of them.
+const FINISHED_EXTRINSICS_KEPT: usize = 20;
+
+/// Id of the job `kind` on the operation `key`, e.g. a call hash.
+pub fn job_id(kind: &str, key: &[u8]) -> String {
+    format!("{}:{}", kind, hex_string(key))
+}
+
+pub(crate) fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum JobState {
+    Pending,
+    Running,
+    Done,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Task {
+    KeyGen {
+        url: String,
+        store: String,
+    },
+    Sign {
+        url: String,
+        #[serde(with = "hex")]
+        message: Vec<u8>,
+        #[serde(with = "hex")]
+        pubkey: Vec<u8>,
+        btc: bool,
+    },
+    /// A SCALE encoded runtime call, signed by the TSS key or the relayer key.
+    Extrinsic {
+        #[serde(with = "hex")]
+        call: Vec<u8>,
+        tss: bool,
+    },
+    /// A SCALE encoded transfer id and payout.
+    Transfer {
+        #[serde(with = "hex")]
+        transfer: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Job {
+    /// Names the operation, a job is not recorded twice while it is not finished.
+    pub id: String,
+    pub task: Task,
+    pub state: JobState,
+    pub attempts: u32,
+    pub result: Option<String>,
+    /// Block the next attempt is scheduled at after a failed one.
+    #[serde(default)]
+    pub retry_at: Option<u64>,
+}
+
+impl Job {
+    pub fn is_finished(&self) -> bool {
+        self.state == JobState::Done || self.state == JobState::Failed
+    }
+
+    fn is_extrinsic(&self) -> bool {
+        matches!(self.task, Task::Extrinsic { .. })
+    }
+}
+
+impl Task {
+    /// Drops the call or transfer of a finished job, it is not replayed.
+    fn strip(&mut self) {
+        match self {
+            Task::Extrinsic { call, .. } => call.clear(),
+            Task::Transfer { transfer } => transfer.clear(),
+            Task::KeyGen { .. } | Task::Sign { .. } => {},
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct JobQueue {
+    path: Option<PathBuf>,
+    /// In the order they were recorded.
+    jobs: Mutex<Vec<Job>>,
+}
+
+impl JobQueue {
+    /// Without a path nothing is persisted. Jobs that were running when the
+    /// node stopped are pending again.
+    pub fn new(path: Option<PathBuf>) -> io::Result<Self> {
+        let mut jobs: Vec<Job> = match &path {
+            Some(path) => match fs::read_to_string(path) {
+                Ok(content) => serde_json::from_str(&content)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+                Err(e) => return Err(io::Error::new(e.kind(), format!("{}: {}", path.display(), e))),
+            },
+            None => Vec::new(),
+        };
+        for job in jobs.iter_mut().filter(|job| job.state == JobState::Running) {
+            job.state = JobState::Pending;
+        }
+        Ok(JobQueue { path, jobs: Mutex::new(jobs) })
+    }
+
+    /// Records a job, unless one with the same id is not finished yet. Returns whether it was recorded.
+    pub fn enqueue(&self, id: &str, task: Task) -> bool {
+        self.update(|jobs| {
+            if jobs.iter().any(|job| job.id == id && !job.is_finished()) {
+                return false;
+            }
+            jobs.retain(|job| job.id != id);
+            jobs.push(Job { id: id.into(), task, state: JobState::Pending, attempts: 0, result: None, retry_at: None });
+            true
+        })
+    }
+
+    /// Marks a job running for one more attempt.
+    pub fn start(&self, id: &str) {
+        self.update(|jobs| {
+            if let Some(job) = jobs.iter_mut().find(|job| job.id == id) {
+                job.state = JobState::Running;
+                job.attempts += 1;
+                job.retry_at = None;
+            }
+        })
+    }
+
+    pub fn finish(&self, id: &str, result: Result<String, String>) {
+        self.update(|jobs| {
+            if let Some(job) = jobs.iter_mut().find(|job| job.id == id) {
+                let (state, result) = match result {
+                    Ok(result) => (JobState::Done, result),
+                    Err(e) => (JobState::Failed, e),
+                };
+                job.state = state;
+                job.result = Some(result);
+                job.task.strip();
+            }
+            for &(extrinsic, kept) in [(true, FINISHED_EXTRINSICS_KEPT), (false, FINISHED_KEPT)].iter() {
+                let finished = |job: &Job| job.is_finished() && job.is_extrinsic() == extrinsic;
+                let mut dropped = jobs.iter().filter(|job| finished(job)).count().saturating_sub(kept);
+                jobs.retain(|job| {
+                    let drop = dropped > 0 && finished(job);
+                    if drop {
+                        dropped -= 1;
+                    }
+                    !drop
+                });
+            }
+        })
+    }
+
+    /// Fails the unfinished jobs whose task is `stale`, scheduled retries included.
+    pub fn expire(&self, stale: impl Fn(&Task) -> bool, reason: &str) {
+        self.update(|jobs| {
+            for job in jobs.iter_mut().filter(|job| !job.is_finished() && stale(&job.task)) {
+                job.state = JobState::Failed;
+                job.result = Some(reason.into());
+                job.task.strip();
+            }
+        })
+    }
+
+    /// Puts a job whose attempt failed back to pending, to be attempted again at block `at`.
+    pub fn retry(&self, id: &str, at: u64, error: String) {
+        self.update(|jobs| {
+            if let Some(job) = jobs.iter_mut().find(|job| job.id == id) {
+                job.state = JobState::Pending;
+                job.retry_at = Some(at);
+                job.result = Some(error);
+            }
+        })
+    }
+
+    /// Pending jobs scheduled at or before block `now`.
+    pub fn due(&self, now: u64) -> Vec<Job> {
+        self.jobs.lock().iter()
+            .filter(|job| job.state == JobState::Pending && job.retry_at.map_or(false, |at| at <= now))
+            .cloned()
+            .collect()
+    }
+
+    pub fn get(&self, id: &str) -> Option<Job> {
+        self.jobs.lock().iter().find(|job| job.id == id).cloned()
+    }
+
+    /// Jobs not started or interrupted, in the order they were recorded.
+    /// Scheduled retries are left to `due`.
+    pub fn pending(&self) -> Vec<Job> {
+        self.jobs.lock().iter().filter(|job| job.state == JobState::Pending && job.retry_at.is_none()).cloned().collect()
+    }
+
+    fn update<R>(&self, f: impl FnOnce(&mut Vec<Job>) -> R) -> R {
+        let mut jobs = self.jobs.lock();
+        let result = f(&mut jobs);
+        if let Some(path) = &self.path {
+            if let Err(e) = store(path, &jobs) {
+                println!("failed to persist bridge jobs: {:?}", e);
+            }
+        }
+        result
+    }
+}
+
+fn store(path: &PathBuf, jobs: &[Job]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let content = serde_json::to_string(jobs)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    // Written aside and renamed, a crash must not leave a truncated file.
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, content)?;
+    fs::rename(tmp, path)
+}
+
+/// Bytes as a 0x prefixed hex string.
+mod hex {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("0x{}", super::hex_string(bytes)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        let hex = hex.trim_start_matches("0x");
+        if hex.len() % 2 != 0 {
+            return Err(D::Error::custom("odd number of hex digits"));
+        }
+        (0..hex.len()).step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(D::Error::custom))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn persists_and_resumes_jobs() {
+        let path = std::env::temp_dir().join(format!("bridge-jobs-{}", std::process::id())).join("jobs.json");
+        let jobs = JobQueue::new(Some(path.clone())).unwrap();
+        let deposit = Task::Extrinsic { call: vec![0x0a, 0xff], tss: true };
+        assert!(jobs.enqueue("extrinsic:1", deposit.clone()));
+        // Replayed while pending, it is not recorded twice.
+        assert!(!jobs.enqueue("extrinsic:1", deposit.clone()));
+        assert!(jobs.enqueue("keygen:filecoin.store", Task::KeyGen { url: "http://127.0.0.1:8001".into(), store: "filecoin.store".into() }));
+        jobs.start("extrinsic:1");
+        jobs.start("keygen:filecoin.store");
+        jobs.finish("keygen:filecoin.store", Ok("0x04aa".into()));
+
+        // The interrupted job is pending again after a restart, with its attempt.
+        let resumed = JobQueue::new(Some(path.clone())).unwrap();
+        let pending = resumed.pending();
+        assert_eq!(pending.len(), 1);
+        assert_eq!((pending[0].id.as_str(), &pending[0].task, pending[0].attempts), ("extrinsic:1", &deposit, 1));
+        assert_eq!(resumed.get("keygen:filecoin.store").unwrap().result, Some("0x04aa".into()));
+
+        // A finished job may be recorded again, its call is not kept.
+        resumed.finish("extrinsic:1", Err("invalid".into()));
+        assert_eq!(resumed.get("extrinsic:1").unwrap().task, Task::Extrinsic { call: Vec::new(), tss: true });
+        assert!(resumed.enqueue("extrinsic:1", deposit));
+        assert_eq!(resumed.get("extrinsic:1").unwrap().attempts, 0);
+
+        // Signing sessions are over after a restart.
+        assert!(resumed.enqueue("sign:01", Task::Sign { url: String::new(), message: vec![1], pubkey: vec![4], btc: false }));
+        resumed.expire(|task| matches!(task, Task::Sign { .. }), "stale");
+        assert_eq!(resumed.get("sign:01").unwrap().state, JobState::Failed);
+        assert_eq!(resumed.pending().len(), 1);
+
+        fs::write(&path, "[{\"id\": ").unwrap();
+        assert_eq!(JobQueue::new(Some(path.clone())).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn schedules_retries() {
+        let jobs = JobQueue::new(None).unwrap();
+        let sign = Task::Sign { url: "http://127.0.0.1:8001".into(), message: vec![1], pubkey: vec![4], btc: false };
+        assert!(jobs.enqueue("sign:01", sign));
+        jobs.start("sign:01");
+        jobs.retry("sign:01", 104, "late party".into());
+
+        // Neither resumed nor replayed before its block.
+        assert!(jobs.pending().is_empty());
+        assert!(!jobs.enqueue("sign:01", Task::KeyGen { url: String::new(), store: String::new() }));
+        assert!(jobs.due(103).is_empty());
+        assert_eq!(jobs.due(104)[0].attempts, 1);
+        jobs.start("sign:01");
+        assert!(jobs.due(104).is_empty());
+    }
+
+    #[test]
+    fn keeps_fewer_finished_extrinsics() {
+        let jobs = JobQueue::new(None).unwrap();
+        for i in 0..30 {
+            let id = format!("extrinsic:{}", i);
+            assert!(jobs.enqueue(&id, Task::Extrinsic { call: vec![i], tss: false }));
+            jobs.finish(&id, Ok(String::new()));
+        }
+        assert!(jobs.enqueue("keygen:a", Task::KeyGen { url: String::new(), store: "a".into() }));
+        jobs.finish("keygen:a", Ok(String::new()));
+
+        assert!(jobs.get("extrinsic:9").is_none());
+        assert!(jobs.get("extrinsic:10").is_some());
+        assert!(jobs.get("keygen:a").is_some());
+    }
+}
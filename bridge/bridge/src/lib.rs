@@ -2,26 +2,27 @@
 use std::{sync::Arc, u64, marker::PhantomData, thread};
 use futures::{prelude::*, executor::block_on, channel::mpsc};
 use parking_lot::Mutex;
-use log::{debug, info};
+use log::{debug, error, info};
 use codec::{Encode, Decode};
 
-use sp_runtime::{generic::{BlockId ,Era}, traits::{Block as BlockT, Zero}};
+use sp_runtime::{generic::Era, traits::{Block as BlockT, Zero}};
 use sp_api::{ProvideRuntimeApi, CallApiAt};
 use sp_core::{Pair, storage::{StorageKey, StorageData}, sr25519::Pair as edPair, sr25519, ecdsa, twox_128};
-use sc_client_api::{BlockchainEvents, backend, notifications::StorageEventStream};
+use sc_client_api::{BlockchainEvents, StorageProvider, backend};
+use sc_keystore::KeyStorePtr;
 use sp_blockchain::{HeaderBackend};
 use sp_transaction_pool::{TransactionPool, TransactionFor};
 use sc_block_builder::{BlockBuilderProvider};
 use node_primitives::Hash;
-use num_traits::cast::FromPrimitive;
+use num_traits::cast::{FromPrimitive, ToPrimitive};
 use frame_system::{Call as SystemCall, EventRecord};
-use pallet_tss::{Call as TssCall, RawEvent, WithdrawDetail };
+use pallet_tss::{Call as TssCall, RawEvent, TransferId };
 
 use filecoin_bridge_runtime::{UncheckedExtrinsic, apis::VendorApi ,Call, SignedPayload
 							  , Event, VERSION, Runtime, AccountId, Signature, Balance, Index};
 
 use tss_signer::{set_pubkey, sign_btc_hex_return_hex, sign_by_tss};
-use node_tss::{start_sm_manager, key_gen, push};
+use node_tss::{start_sm_manager, key_gen, push, set_key_dir};
 
 use lotus_api_forest::api::MpoolApi;
 use forest_message;
@@ -31,19 +32,46 @@ use forest_encoding::Cbor;
 use forest_crypto;
 
 use async_trait::async_trait;
-use bridge_primitives::{TssError,tss_error};
+use bridge_primitives::{retry_abort, RetryAbort};
+
+mod cursor;
+pub use cursor::ScanCursor;
 
 mod chainstate;
 pub use chainstate::ChainState;
 
 mod txsender;
-pub use txsender::{TxSender, TxMessage, TxType, SuperviseClient, PacketNonce, TokenType};
+pub use txsender::{TxSender, TxMessage, TxType, SuperviseClient, Signer, Submission, SubmitError, ExtrinsicStatus, FinalityStream};
+
+mod signer_nonce;
+pub use signer_nonce::PacketNonce;
 
 mod filecoinapi;
-pub use filecoinapi::{get_nonce, message_create, send_fc_message};
+pub use filecoinapi::{account_nonces, chain_head_epoch, check_gas, get_message, get_nonce, max_gas_limit, message_create,
+					  payout_message_create, prove_lookup, replace_message, replacement_raise, search_message, send_fc_message, FcNetwork, GasConfig, GasError,
+					  LotusConfig, Payout, DEFAULT_LOTUS_URL};
+
+mod proof;
+pub use proof::{build_message_proof, raw_headers, ProofBuildError};
+
+mod batch;
+pub use batch::{batch_send_params, INVOKE_CONTRACT_METHOD};
+
+mod nonce;
+pub use nonce::{InFlight, NonceManager};
+
+mod coordinator;
+pub use coordinator::{Coordinator, Turn};
 
 mod recover;
-pub use recover::recover;
+pub use recover::{Recover, RetryPolicy};
+
+mod jobs;
+pub use jobs::{job_id, Job, JobQueue, JobState, Task};
+use jobs::hex_string;
+
+mod relayer;
+pub use relayer::{insert_relayer_key, relayer_key, RelayerKeyError, RELAYER_KEY_TYPE};
 
 pub enum TssRole{
 	Manager,
@@ -79,6 +107,15 @@ pub struct TssSender<V,B> {
 	pub tss: u64,
     pub senderbool: FcPubkeySender,
 	pub senderfc: FcPubkeySender,
+	pub gas: GasConfig,
+	pub lotus: LotusConfig,
+	/// Manager the party joins instead of the one set on chain.
+	pub manager_url: Option<String>,
+	pub nonces: Arc<NonceManager>,
+	pub coordinator: Arc<Coordinator<TransferId, Payout>>,
+	/// Retries of key generations, by key store, and of signatures, by message.
+	pub recover: Arc<Recover<Vec<u8>>>,
+	pub jobs: Arc<JobQueue>,
 	pub a: std::marker::PhantomData<B>,
 }
 
@@ -86,16 +123,30 @@ impl <V,B>TssSender<V,B>
 	where   V: SuperviseClient<B> + Send + Sync + 'static,
 			B: BlockT,
 {
-	pub fn new(spv: V,senderb:FcPubkeySender,senderfc:FcPubkeySender) -> Self {
+	pub fn new(spv: V,senderb:FcPubkeySender,senderfc:FcPubkeySender,gas:GasConfig,lotus:LotusConfig,
+			   manager_url:Option<String>,nonces:NonceManager,coordinator:Coordinator<TransferId, Payout>,
+			   jobs:Arc<JobQueue>) -> Self {
 		TssSender {
 			spv: spv,
 			tss: 5,
 			senderbool: senderb,
 			senderfc: senderfc,
+			gas: gas,
+			lotus: lotus,
+			manager_url: manager_url,
+			nonces: Arc::new(nonces),
+			coordinator: Arc::new(coordinator),
+			recover: Arc::new(Recover::default()),
+			jobs: jobs,
 			a: PhantomData,
 		}
 	}
 
+	/// The TSS manager to join for a session announced at `url`.
+	fn manager(&self, url: &str) -> String {
+		self.manager_url.clone().unwrap_or_else(|| url.to_string())
+	}
+
 	fn submit_tx(&self,data:TxMessage) {
          self.spv.submit(data);
 	}
@@ -104,219 +155,696 @@ impl <V,B>TssSender<V,B>
 		self.spv.submit_fc_transfer_tss(TxMessage::new(TxType::System));
 	}
 
-	fn key_gen(&self,url:Vec<u8>,_store:Vec<u8>){
-		let str_url = core::str::from_utf8(&url).unwrap();
-		let store2 = "boolbtc.store";
-        match key_gen(str_url,store2){
+	fn key_gen(&self,url:Vec<u8>,_store:Vec<u8>) where Self: Clone {
+		self.key_gen_job(url, "boolbtc.store");
+	}
+
+	fn key_gen_bool(&self,url:Vec<u8>,_store:Vec<u8>) where Self: Clone {
+		self.key_gen_job(url, "bool.store");
+	}
+
+	fn key_gen_fc(&self,url:Vec<u8>,_store:Vec<u8>) where Self: Clone {
+		self.key_gen_job(url, "filecoin.store");
+	}
+
+	/// How the key of `store` is reported, and the key of `store` on chain.
+	fn key_store(&self, store: &str) -> Option<(fn(Vec<u8>, Vec<Vec<u8>>) -> TxType, Vec<u8>)> {
+		match store {
+			"boolbtc.store" => Some((TxType::TssKeyGen, self.spv.tss_pubkey())),
+			"bool.store" => Some((TxType::TssKeyGenBool, self.spv.tss_pubkey_bool())),
+			"filecoin.store" => Some((TxType::TssKeyGenFc, self.spv.tss_pubkey_fc())),
+			_ => None,
+		}
+	}
+
+	fn key_gen_job(&self, url: Vec<u8>, store: &'static str) where Self: Clone {
+		let url = String::from_utf8_lossy(&url).into_owned();
+		let id = format!("keygen:{}", store);
+		// Not while the key is being generated already.
+		if self.jobs.enqueue(&id, Task::KeyGen { url: url.clone(), store: store.into() }) {
+			self.in_session(move |sender| sender.run_key_gen(&id, &url, store));
+		}
+	}
+
+	/// Runs a TSS session on its own thread, as it blocks until the other
+	/// parties are done.
+	fn in_session(&self, session: impl FnOnce(Self) + Send + 'static) where Self: Clone {
+		let sender = self.clone();
+		thread::spawn(move || session(sender));
+	}
+
+	fn run_key_gen(&self, id: &str, url: &str, store: &str) {
+		let created = match self.key_store(store) {
+			Some((created, _)) => created,
+			None => return self.jobs.finish(id, Err(format!("unknown key store {}", store))),
+		};
+		self.jobs.start(id);
+		match key_gen(&self.manager(url),store){
 			Ok((pk,pk_vec)) => {
-				let data = TxMessage::new(TxType::TssKeyGen(pk.to_vec(),pk_vec));
-				println!("=========key_gen===submit_tx=1==");
-				self.submit_tx(data);
-				//println!("=========key_gen===submit_tx=2==");
-				//let data2 = TxMessage::new(TxType::BtcAddressSet(pk.to_vec()));
-				//self.submit_tx(data2);
-				//set_pubkey(pk.to_vec(),store2);
+				self.recover.succeeded(&store.as_bytes().to_vec());
+				self.jobs.finish(id, Ok(format!("0x{}", hex_string(&pk))));
+				self.submit_tx(TxMessage::new(created(pk.to_vec(),pk_vec)));
+			},
+			Err(e) => {
+				let class = retry_abort(&e);
+				self.retry_later(id, store.as_bytes().to_vec(), e, class);
 			},
-			_ => return ,
 		}
 	}
 
-	fn key_gen_bool(&self,url:Vec<u8>,_store:Vec<u8>){
-		let str_url = core::str::from_utf8(&url).unwrap();
-		let store2 = "bool.store";
-		match key_gen(str_url,store2){
-			Ok((pk,pk_vec)) => {
-				let data = TxMessage::new(TxType::TssKeyGenBool(pk.to_vec(),pk_vec));
-				self.submit_tx(data);
+	/// Schedules another attempt of a job that failed, or fails it once it is given up.
+	fn retry_later<E: std::fmt::Debug>(&self, id: &str, op: Vec<u8>, e: E, class: RetryAbort) {
+		match self.recover.retry_at(&op, &e, class, self.spv.best_number()) {
+			Some(at) => self.jobs.retry(id, at, format!("{:?}", e)),
+			None => self.jobs.finish(id, Err(format!("{:?}", e))),
+		}
+	}
+
+	/// Attempts the jobs whose retry is due at the best block.
+	fn retry_due(&self) {
+		for job in self.jobs.due(self.spv.best_number()) {
+			self.run_job(job);
+		}
+	}
+
+	/// Joins the signing session of `message`.
+	fn sign_job(&self, url: Vec<u8>, message: Vec<u8>, pubkey: Vec<u8>, btc: bool) where Self: Clone {
+		let id = job_id("sign", &message);
+		let task = Task::Sign { url: String::from_utf8_lossy(&url).into_owned(), message: message.clone(), pubkey: pubkey.clone(), btc };
+		if self.jobs.enqueue(&id, task) {
+			self.in_session(move |sender| sender.run_sign(&id, url, message, pubkey, btc));
+		}
+	}
+
+	fn run_sign(&self, id: &str, url: Vec<u8>, message: Vec<u8>, pubkey: Vec<u8>, btc: bool) {
+		self.jobs.start(id);
+		if btc {
+			match self.key_sign(url, message, pubkey, SignatureType::Btc) {
+				Some(sig) => self.jobs.finish(id, Ok(format!("0x{}", hex_string(&sig)))),
+				None => self.jobs.finish(id, Err("not signed".into())),
+			}
+			return;
+		}
+		let url = self.manager(&String::from_utf8_lossy(&url));
+		match sign_by_tss(message.clone(), &url, pubkey) {
+			Ok(sig) => {
+				self.recover.succeeded(&message);
+				self.jobs.finish(id, Ok(format!("0x{}", hex_string(&sig))));
+			},
+			Err(e) => {
+				let class = retry_abort(&e);
+				self.retry_later(id, message, e, class);
 			},
-			_ => return ,
 		}
 	}
 
-	fn key_gen_fc(&self,url:Vec<u8>,_store:Vec<u8>){
-		let str_url = core::str::from_utf8(&url).unwrap();
-		let store2 = "filecoin.store";
-		match key_gen(str_url,store2){
-			Ok((pk,pk_vec)) => {
-				let data = TxMessage::new(TxType::TssKeyGenFc(pk.to_vec(),pk_vec));
-				self.submit_tx(data);
+	/// Tracks a transfer to coordinate until it is settled.
+	fn request_transfer(&self, id: TransferId, payout: Payout) {
+		let job = job_id("transfer", &id.encode());
+		self.jobs.enqueue(&job, Task::Transfer { transfer: (id, payout.clone()).encode() });
+		self.jobs.start(&job);
+		self.coordinator.request(id, payout, self.spv.best_number());
+	}
+
+	fn settle_transfer(&self, id: TransferId, result: String) {
+		self.jobs.finish(&job_id("transfer", &id.encode()), Ok(result));
+		self.coordinator.forget(&id);
+	}
+
+	/// Resumes the jobs that were not finished when the node stopped.
+	pub fn resume(&self) {
+		self.jobs.expire(|task| matches!(task, Task::Sign { .. }), "signing session expired while the node was stopped");
+		for job in self.jobs.pending() {
+			println!("resuming bridge job {} after {} attempts", job.id, job.attempts);
+			self.run_job(job);
+		}
+	}
+
+	fn run_job(&self, job: Job) {
+		match job.task {
+			Task::KeyGen { url, store } => match self.key_store(&store) {
+				Some((_, on_chain)) if !on_chain.is_empty() => {
+					self.jobs.finish(&job.id, Ok("key already on chain".into()));
+				},
+				_ => self.run_key_gen(&job.id, &url, &store),
+			},
+			Task::Sign { url, message, pubkey, btc } => self.run_sign(&job.id, url.into_bytes(), message, pubkey, btc),
+			Task::Extrinsic { call, tss } => match Call::decode(&mut &call[..]) {
+				Ok(call) => {
+					self.spv.resume_call(&job.id, call, if tss { Signer::Tss } else { Signer::Relayer });
+				},
+				Err(e) => self.jobs.finish(&job.id, Err(format!("undecodable call: {:?}", e))),
+			},
+			Task::Transfer { transfer } => match <(TransferId, Payout)>::decode(&mut &transfer[..]) {
+				Ok((id, payout)) => self.request_transfer(id, payout),
+				Err(e) => self.jobs.finish(&job.id, Err(format!("undecodable transfer: {:?}", e))),
 			},
-			_ => return ,
 		}
 	}
 
 	fn key_sign(&self,url:Vec<u8>, message:Vec<u8>, pubkey:Vec<u8> ,sigtype:SignatureType) -> Option<Vec<u8>>{
-		let str_url = core::str::from_utf8(&url).unwrap();
+		let url = self.manager(&String::from_utf8_lossy(&url));
+		let str_url = url.as_str();
 		//let pubkey = self.spv.tss_pubkey();
 		debug!(target:"keysign", "pubkey {:?}", pubkey);
 		match sigtype {
+			// Transfer proposals are not retried here, once the deadline of
+			// their coordinator passes the next one proposes them to every party.
 			SignatureType::General => {
 				//let _str_message = core::str::from_utf8(&message).unwrap();
-				let res = sign_by_tss(message, str_url,pubkey);
-				if res.is_ok(){
-					return Some(res.unwrap());
+				match sign_by_tss(message, str_url, pubkey) {
+					Ok(sig) => Some(sig),
+					Err(e) => {
+						println!("failed to sign with the tss key: {:?}", e);
+						None
+					},
 				}
-				return None;
 			},
 			SignatureType::Btc => {
 				let pubkey = self.spv.tss_pubkey();
 				debug!(target:"keysign", "SignatureType::Btc");
-				let res = sign_btc_hex_return_hex(message,str_url,pubkey);
-				if res.is_ok(){
-					let signed_tx = res.unwrap();
-					let data = TxMessage::new(TxType::Signature(signed_tx.into()));
-					self.submit_tx(data);
-					return None
-				   }
-				}
-	        }
-		Some(vec![0u8]) // None?
+				// The runtime has no Bitcoin bridge to submit it to.
+				sign_btc_hex_return_hex(message,str_url,pubkey).ok().map(|signed| signed.into())
+			}
+		}
+	}
+
+	/// Acts on the transfers whose turn this node holds.
+	fn coordinate(&self) {
+		for (id, turn) in self.coordinator.turns(self.spv.best_number(), &self.spv.tss_members()) {
+			match turn {
+				Turn::Propose => self.propose_fc(id),
+				Turn::Broadcast(signed) => match forest_message::SignedMessage::unmarshal_cbor(&signed) {
+					Ok(signed) => self.broadcast_fc(id, signed),
+					Err(e) => println!("failed to decode signed transfer {:?}: {:?}", id, e),
+				},
+			}
+		}
 	}
 
-	fn withdraw_fc(&self, withdrawdetail:&WithdrawDetail<AccountId>){
-		let url = self.spv.tss_url();
+	/// Builds the message paying out a transfer and proposes it for signing.
+	fn propose_fc(&self, id: TransferId) {
+		let payout = match self.coordinator.expected(&id) {
+			Some(payout) => payout,
+			None => return,
+		};
 		let pubkey = self.spv.tss_pubkey();
-		let (message,cid) = message_create(
-			pubkey.clone(),
-			withdrawdetail.receiver.clone(),
-			withdrawdetail.value.clone(),
-		);
-		println!("cid {:?}",cid);
-		let message_to_sign = sp_io::hashing::blake2_256(&cid[..]);
-		let sig = self.key_sign(url, message_to_sign.to_vec(),
-								pubkey.clone(),SignatureType::General);
-		if sig.is_none(){
+		let nonce = match self.coordinator.reserved(&id) {
+			Some(nonce) => nonce,
+			None => {
+				let from_addr = forest_address::Address::new_secp256k1(&pubkey).unwrap();
+				match account_nonces(&self.lotus, &from_addr) {
+					Ok((chain_nonce, pooled)) => self.nonces.reserve(chain_nonce, &pooled),
+					Err(e) => {
+						println!("failed to look up the nonce of {}: {:?}", from_addr, e);
+						return;
+					},
+				}
+			},
+		};
+		let message = match payout_message_create(&self.lotus, pubkey, &payout, nonce, &self.gas) {
+			Ok((_, message)) => message,
+			Err(e) => {
+				println!("not proposing transfer {:?} of {:?}: {:?}", id, payout, e);
+				if self.coordinator.reserved(&id).is_none() {
+					self.nonces.release(nonce);
+				}
+				return;
+			},
+		};
+		println!("proposing transfer {:?} with nonce {}", id, nonce);
+		self.coordinator.propose(&id, message.clone(), Some(nonce));
+		self.submit_tx(TxMessage::new(TxType::FcTransferProposal(id, message)));
+	}
+
+	/// Joins the signing session of a proposed transfer after checking that the
+	/// message pays it out, and broadcasts the signed message when this node
+	/// coordinates it.
+	fn sign_proposal(&self, id: TransferId, bytes: Vec<u8>) where Self: Clone {
+		let payout = match self.coordinator.expected(&id) {
+			Some(payout) => payout,
+			None => {
+				println!("not signing proposal of unknown transfer {:?}", id);
+				return;
+			},
+		};
+		let message = match forest_message::UnsignedMessage::unmarshal_cbor(&bytes) {
+			Ok(message) => message,
+			Err(e) => {
+				println!("not signing undecodable proposal of transfer {:?}: {:?}", id, e);
+				return;
+			},
+		};
+		let previous = self.coordinator.proposal(&id)
+			.and_then(|previous| forest_message::UnsignedMessage::unmarshal_cbor(&previous).ok());
+		if let Err(e) = self.check_proposal(id, &message, &payout, previous.as_ref()) {
+			println!("not signing proposal of transfer {:?}: {}", id, e);
+			return;
+		}
+		if let Some(nonce) = self.coordinator.proposed(&id, bytes, self.spv.best_number()) {
+			self.nonces.release(nonce);
+		}
+
+		self.in_session(move |sender| {
+			let digest = sp_io::hashing::blake2_256(&message.to_signing_bytes());
+			let sig = match sender.key_sign(sender.spv.tss_url(), digest.to_vec(), sender.spv.tss_pubkey(), SignatureType::General) {
+				Some(sig) => sig,
+				None => {
+					println!("failed to sign transfer {:?}", id);
+					return;
+				},
+			};
+			let signed = forest_message::SignedMessage{
+				message:message,
+				signature:forest_crypto::Signature::new_secp256k1(sig),
+			};
+			let res = signed.verify();
+			println!("transfer fc signature verify result : {:?}",res);
+
+			if sender.coordinator.signed(&id, signed.marshal_cbor().unwrap_or_default()) {
+				sender.broadcast_fc(id, signed);
+			}
+		});
+	}
+
+	/// Whether a proposed message makes the payout from the bridge address,
+	/// with the gas this node would give it, under the nonce this node would
+	/// give it or raising the gas of the message it replaces under the same
+	/// nonce as much as Lotus requires.
+	fn check_proposal(&self, id: TransferId, message: &forest_message::UnsignedMessage, payout: &Payout,
+					  previous: Option<&forest_message::UnsignedMessage>) -> Result<(), String> {
+		let bridge = forest_address::Address::new_secp256k1(&self.spv.tss_pubkey()).map_err(|e| format!("{:?}", e))?;
+		let (to, value, method, params) = payout.call()?;
+		if message.from != bridge || message.to.to_bytes() != to || message.method_num != method
+			|| message.params.bytes() != &params[..] {
+			return Err("not the payout of the transfer".to_string());
+		}
+		if message.value.to_u128() != Some(value) {
+			return Err(format!("value {} instead of {}", message.value, value));
+		}
+		check_gas(message, &self.gas).map_err(|e| format!("{:?}", e))?;
+		let limit = max_gas_limit(&self.lotus, message, &self.gas).map_err(|e| format!("{:?}", e))?;
+		if message.gas_limit > limit {
+			return Err(format!("gas limit {} above the estimated {}", message.gas_limit, limit));
+		}
+		if let Some(previous) = previous {
+			if message.sequence != previous.sequence {
+				return Err("not a replacement of the previous proposal".to_string());
+			}
+			let (premium, fee_cap) = (replacement_raise(&previous.gas_premium), replacement_raise(&previous.gas_fee_cap));
+			if message.gas_premium.to_u128().map_or(true, |p| p < premium)
+				|| message.gas_fee_cap.to_u128().map_or(true, |cap| cap < fee_cap) {
+				return Err(format!("replacement gas below premium {} and fee cap {}", premium, fee_cap));
+			}
+		} else {
+			let nonce = self.expected_nonce(&id, &bridge)?;
+			if message.sequence != nonce {
+				return Err(format!("nonce {} instead of {}", message.sequence, nonce));
+			}
+		}
+		Ok(())
+	}
+
+	/// The nonce this node would give the first message of a transfer, skipping
+	/// the ones of the other proposed transfers.
+	fn expected_nonce(&self, id: &TransferId, bridge: &forest_address::Address) -> Result<u64, String> {
+		let (chain_nonce, mut used) = account_nonces(&self.lotus, bridge).map_err(|e| format!("{:?}", e))?;
+		used.extend(self.coordinator.other_proposals(id).iter()
+			.filter_map(|proposal| forest_message::UnsignedMessage::unmarshal_cbor(proposal).ok())
+			.map(|proposal| proposal.sequence));
+		Ok(self.nonces.expected(chain_nonce, &used, self.coordinator.reserved(id)))
+	}
+
+	/// Pushes a signed transfer and reports it sent.
+	fn broadcast_fc(&self, id: TransferId, signed: forest_message::SignedMessage) {
+		let nonce = signed.message.sequence;
+		let epoch = match chain_head_epoch(&self.lotus) {
+			Ok(epoch) => epoch,
+			Err(e) => {
+				println!("failed to send transfer {:?}: {:?}", id, e);
+				return;
+			},
+		};
+		let cid = match send_fc_message(&self.lotus, signed) {
+			Ok(cid) => cid,
+			Err(e) => {
+				println!("failed to send transfer {:?}: {}", id, e);
+				return;
+			},
+		};
+		println!("transfer fc result ----------> {:?} {:?}", id, cid);
+		if self.nonces.in_flight().iter().any(|(n, _)| *n == nonce) {
+			self.nonces.replace(nonce, cid.to_string(), epoch);
+		} else {
+			self.nonces.broadcast(nonce, cid.to_string(), epoch, Some(id));
+		}
+		self.submit_tx(TxMessage::new(TxType::FcTransferSent(id, cid.to_string().into_bytes())));
+	}
+
+	/// Proves the result of transfers once final on chain and replaces the ones
+	/// stuck in the pool.
+	fn check_transfers(&self) {
+		if self.nonces.in_flight().is_empty() {
 			return;
 		}
-		let signed_message = forest_message::SignedMessage{
-			message:message,
-			signature:forest_crypto::Signature::new_secp256k1(sig.unwrap()),
+		let from_addr = match forest_address::Address::new_secp256k1(&self.spv.tss_pubkey()) {
+			Ok(addr) => addr,
+			Err(_) => return,
+		};
+		let (chain_nonce, head, evicted) = match account_nonces(&self.lotus, &from_addr).and_then(|nonces| Ok((nonces, chain_head_epoch(&self.lotus)?))) {
+			Ok(((chain_nonce, pooled), head)) => {
+				let evicted = self.nonces.reconcile(chain_nonce, &pooled);
+				(chain_nonce, head, evicted)
+			},
+			Err(e) => {
+				println!("failed to check pending transfers: {:?}", e);
+				return;
+			},
+		};
+
+		for (nonce, flight) in self.nonces.in_flight() {
+			if nonce < chain_nonce {
+				self.settle_landed(nonce, &flight);
+			} else if evicted.contains(&nonce) || flight.epoch + self.gas.replace_after <= head {
+				self.replace_stuck(nonce, &flight);
+			}
+		}
+	}
+
+	/// Proves the result of a transfer that landed once the tipset executing it
+	/// is final for the relay. The message executed may be any of those sent
+	/// under the nonce.
+	fn settle_landed(&self, nonce: u64, flight: &InFlight) {
+		let mut lookup = None;
+		for cid in std::iter::once(&flight.cid).chain(flight.replaced.iter().rev()) {
+			match search_message(&self.lotus, cid) {
+				Ok(Some(found)) => {
+					lookup = Some(found);
+					break;
+				},
+				Ok(None) => continue,
+				Err(e) => {
+					println!("failed to search transfer {}: {:?}", cid, e);
+					return;
+				},
+			}
+		}
+		let lookup = match lookup {
+			Some(lookup) => lookup,
+			None => {
+				println!("transfer {} at nonce {} is not found on chain", flight.cid, nonce);
+				return;
+			},
 		};
+		if lookup.height < 0 || lookup.height as u64 > self.spv.fc_deposit_final_height() {
+			return;
+		}
 
-		let res = signed_message.verify();
-		println!("withdraw fc result signature verify result : {:?}",res);
+		let exit_code = lookup.receipt.exit_code;
+		println!("transfer {} at nonce {} executed with exit code {}", lookup.message, nonce, exit_code);
+		// The proven receipt settles the transfer, a batch with all its withdrawals.
+		if let Some(id) = flight.transfer {
+			let proof = match prove_lookup(&self.lotus, &lookup) {
+				Ok(proof) => proof,
+				Err(e) => {
+					println!("failed to prove transfer {}: {:?}", lookup.message, e);
+					return;
+				},
+			};
+			let tx_type = match (id, exit_code == 0) {
+				(TransferId::WithdrawBatch(batch), true) => TxType::FcWithdrawConfirmed(batch, proof),
+				(TransferId::WithdrawBatch(batch), false) => TxType::FcWithdrawFailed(batch, proof),
+				(TransferId::Refund(uid), true) => TxType::FcRefundConfirmed(uid, proof),
+				(TransferId::Refund(uid), false) => TxType::FcRefundFailed(uid, proof),
+			};
+			self.submit_tx(TxMessage::new(tx_type));
+		}
+		self.nonces.settle(nonce);
+	}
 
-		let cid = send_fc_message(signed_message);
-		println!("withdraw fc result ----------> {:?}",cid);
+	/// Proposes to replace a transfer evicted from the pool, or still in it
+	/// `replace_after` epochs after its broadcast, with a higher premium under
+	/// the same nonce.
+	fn replace_stuck(&self, nonce: u64, flight: &InFlight) {
+		// Waits for the replacement proposed before to be sent.
+		let id = match flight.transfer {
+			Some(id) if self.coordinator.is_sent(&id) => id,
+			_ => return,
+		};
+		let message = match get_message(&self.lotus, &flight.cid) {
+			Ok(message) => message,
+			Err(e) => {
+				println!("failed to fetch stuck transfer {}: {:?}", flight.cid, e);
+				return;
+			},
+		};
+		let message = match replace_message(&self.lotus, message, &self.gas) {
+			Ok(message) => message,
+			Err(e) => {
+				println!("not replacing stuck transfer {}: {:?}", flight.cid, e);
+				return;
+			},
+		};
+		println!("proposing replacement of stuck transfer {} at nonce {}", flight.cid, nonce);
+		let message = message.to_signing_bytes();
+		self.coordinator.propose(&id, message.clone(), None);
+		self.submit_tx(TxMessage::new(TxType::FcTransferProposal(id, message)));
 	}
 
-	fn get_stream(&self, events_key:StorageKey) -> StorageEventStream<B::Hash> {
-		self.spv.get_notification_stream(Some(&[events_key]), None)
+	/// Handles the events of the finalized block `hash`. The TSS sessions the
+	/// events start run on their own threads.
+	fn process_block(&self, hash: B::Hash, events_key: &StorageKey, enable_tss_message_intermediary: bool) -> Result<(), String>
+		where Self: Clone
+	{
+		let records: Vec<EventRecord<Event, Hash>> = match self.spv.storage_at(hash, events_key) {
+			Ok(Some(StorageData(data))) => Decode::decode(&mut &data[..])
+				.map_err(|e| format!("failed to decode the events of block {:?}: {:?}", hash, e))?,
+			Ok(None) => Vec::new(),
+			Err(e) => return Err(format!("failed to read the events of block {:?}: {:?}", hash, e)),
+		};
+		let events: Vec<Event> = records.into_iter().map(|r| r.event).collect();
+		events.iter().for_each(|event| {
+			debug!(target:"keysign", "Event {:?}", event);
+			if enable_tss_message_intermediary {
+				if let Event::pallet_tss(e) = event {
+					match e {
+						RawEvent::GenKey(_index, _id, _time, url) => {
+							self.key_gen(url.to_vec(), vec![0u8]);
+						},
+						RawEvent::GenerateTssKey(url, store) => {
+							self.key_gen(url.to_vec(), store.to_vec());
+						},
+						RawEvent::GenerateTssKeyBool(url, store) => {
+							self.key_gen_bool(url.to_vec(), store.to_vec());
+						},
+						RawEvent::GenerateTssKeyFc(url, store) => {
+							self.key_gen_fc(url.to_vec(), store.to_vec());
+						},
+						RawEvent::SignMessage(_index, _id, _time, url, message, pubkey) => {
+							self.sign_job(url.to_vec(), message.to_vec(), pubkey.to_vec(), false);
+						},
+						RawEvent::SignBtcMessage(_index, _time, url, message, pubkey) => {
+							self.sign_job(url.to_vec(), message.to_vec(), pubkey.to_vec(), true);
+						},
+						RawEvent::WithdrawBatch(batch, batch_actor, withdrawals) => {
+							let payout = Payout {
+								outputs: withdrawals.iter().map(|w| (w.receiver.clone(), w.value)).collect(),
+								batch_actor: batch_actor.clone(),
+							};
+							self.request_transfer(TransferId::WithdrawBatch(*batch), payout);
+   							    },
+						RawEvent::RefundToken(refunddetail) => {
+							println!("refund deposit {:?}", refunddetail.cid);
+							let payout = Payout {
+								outputs: vec![(refunddetail.receiver.clone(), refunddetail.value)],
+								batch_actor: Vec::new(),
+							};
+							self.request_transfer(TransferId::Refund(refunddetail.uid), payout);
+						},
+						RawEvent::TransferProposed(id, message) => {
+							self.sign_proposal(*id, message.to_vec());
+						},
+						RawEvent::TransferSent(id, _cid) => {
+							self.coordinator.sent(id);
+						},
+						RawEvent::RefundConfirmed(uid, cid) => {
+							self.settle_transfer(TransferId::Refund(*uid), format!("refunded by {}", String::from_utf8_lossy(cid)));
+						},
+						RawEvent::RefundFailed(uid, cid, exit_code) => {
+							// The refund is still pending, it is proposed again from scratch.
+							println!("refund {} failed in {} with exit code {}", uid, String::from_utf8_lossy(cid), exit_code);
+							let id = TransferId::Refund(*uid);
+							if let Some(payout) = self.coordinator.expected(&id) {
+								self.coordinator.forget(&id);
+								self.coordinator.request(id, payout, self.spv.best_number());
+							}
+						},
+						RawEvent::WithdrawBatchSettled(batch, exit_code) => {
+							self.settle_transfer(TransferId::WithdrawBatch(*batch), format!("settled with exit code {:?}", exit_code));
+						},
+						_ => {}
+					}
+				}
+			}
+		});
+		Ok(())
 	}
 
+	/// Follows GRANDPA finality, handling the events of every finalized block
+	/// once and in order. `cursor` keeps the last block handled, the blocks
+	/// finalized while the node was stopped are handled on the next finality
+	/// notification. A block whose events cannot be read ends the future, its
+	/// state has to be kept until it is handled.
 	pub fn start(self,
 				 _role: TssRole,
-				 enable_tss_message_intermediary: bool ) -> impl Future<Output=()> + 'static {
+				 enable_tss_message_intermediary: bool,
+				 cursor: ScanCursor) -> impl Future<Output=()> + 'static
+		where Self: Clone
+	{
 
 		let events_key = StorageKey(b"System Events".as_prefix_key());
-		let storage_stream: StorageEventStream<B::Hash> = self.get_stream(events_key);
-
-		let storage_stream = storage_stream
-			.for_each( move|(_blockhash,change_set)| {
-				let records: Vec<Vec<EventRecord<Event, Hash>>> = change_set
-					.iter()
-					.filter_map(|(_ , _, mbdata)| {
-						if let Some(StorageData(data)) = mbdata {
-							Decode::decode(&mut &data[..]).ok()
-						} else {
-							None
-						}
-					})
-					.collect();
-				let events: Vec<Event> = records.concat().iter().cloned().map(|r| r.event).collect();
-				events.iter().for_each(|event| {
-					debug!(target:"keysign", "Event {:?}", event);
-					if enable_tss_message_intermediary {
-						if let Event::pallet_tss(e) = event {
-							match e {
-								RawEvent::GenKey(_index, _id, _time, url) => {
-									self.key_gen(url.to_vec(), vec![0u8]);
-								},
-								RawEvent::GenerateTssKey(url, store) => {
-									self.key_gen(url.to_vec(), store.to_vec());
-								},
-								RawEvent::GenerateTssKeyBool(url, store) => {
-									self.key_gen_bool(url.to_vec(), store.to_vec());
-								},
-								RawEvent::GenerateTssKeyFc(url, store) => {
-									self.key_gen_fc(url.to_vec(), store.to_vec());
-								},
-								RawEvent::SignMessage(_index, _id, _time, url, message, pubkey) => {
-									self.key_sign(url.to_vec(), message.to_vec(), pubkey.to_vec() ,SignatureType::General);
-								},
-								RawEvent::SignBtcMessage(_index, _time, url, message, pubkey) => {
-									self.key_sign(url.to_vec(), message.to_vec(), pubkey.to_vec() ,SignatureType::Btc);
-								},
-								RawEvent::WithdrawToken(withdrawdetail) => {
-								    self.withdraw_fc(withdrawdetail);
-   							    },
-								_ => {}
-							}
-						}
+		let mut last = cursor.load();
+
+		self.spv.finality_stream()
+			.map(Ok::<_, String>)
+			.try_for_each(move |(finalized, _)| {
+				// Without a cursor the node starts from the block just finalized.
+				let from = last.map_or(finalized, |last| last + 1);
+				for number in from..=finalized {
+					let hash = match self.spv.block_hash(number) {
+						Some(hash) => hash,
+						None => {
+							println!("finalized block {} is not found", number);
+							break;
+						},
+					};
+					if let Err(e) = self.process_block(hash, &events_key, enable_tss_message_intermediary) {
+						return futures::future::ready(Err(e));
 					}
-				});
-				futures::future::ready(())
-			});
-
-		println!("=========== return storage_stream ===========");
-		storage_stream
+					last = Some(number);
+					if let Err(e) = cursor.store(number) {
+						println!("failed to persist the event cursor: {:?}", e);
+					}
+				}
+				if enable_tss_message_intermediary {
+					self.coordinate();
+				}
+				futures::future::ready(Ok(()))
+			})
+			.map(|result: Result<(), String>| if let Err(e) = result {
+				error!(target:"keysign", "stopped handling finalized blocks: {}", e);
+			})
 	}
 }
 
 
 type FcPubkeySender = mpsc::UnboundedSender<Vec<u8>>;
 
+/// Seconds between two checks of the sent transfers, about one Filecoin epoch.
+const TRANSFER_CHECK_INTERVAL: u64 = 30;
+
+/// Seconds between two checks of the scheduled retries, a block.
+const RETRY_CHECK_INTERVAL: u64 = 6;
+
+/// Blocks a node coordinates a transfer for before the next one takes over.
+pub const DEFAULT_COORDINATOR_DEADLINE: u64 = 20;
+
+/// Port the TSS manager listens on.
+pub const DEFAULT_TSS_MANAGER_PORT: u16 = node_tss::DEFAULT_MANAGER_PORT;
+
+#[derive(Clone, Debug)]
+pub struct TssConfig {
+	/// Runs the manager relaying the messages of the TSS parties instead of a party.
+	pub manager: bool,
+	pub manager_port: u16,
+	/// Manager the party joins instead of the one set on chain.
+	pub manager_url: Option<String>,
+	/// Index of this node among the TSS parties, from 1.
+	pub party: u64,
+	/// Directory the key shares of the party are kept in.
+	pub key_dir: std::path::PathBuf,
+	pub lotus: LotusConfig,
+	pub gas: GasConfig,
+	pub coordinator_deadline: u64,
+	/// File the Filecoin nonces of the bridge are kept in.
+	pub nonce_path: Option<std::path::PathBuf>,
+	/// File the last block whose events were handled is kept in.
+	pub event_cursor_path: Option<std::path::PathBuf>,
+}
+
 pub fn start_tss<A, B, C, Block>(
 	client: Arc<C>,
 	pool: Arc<A>,
-	_keystore: u64,
-	enable_tss_message_intermediary:bool,
+	keystore: KeyStorePtr,
+	nonces: Arc<Mutex<PacketNonce<Block::Hash, AccountId>>>,
+	jobs: Arc<JobQueue>,
 	senderbool: FcPubkeySender,
 	senderfc: FcPubkeySender,
-	num:u64,
-) -> impl Future<Output = ()> + 'static
+	config: TssConfig,
+) -> Result<impl Future<Output = ()> + 'static, RelayerKeyError>
 	where
 		A: TransactionPool<Block = Block> + 'static,
 		Block: BlockT,
 		B: backend::Backend<Block> + Send + Sync + 'static,
 		C: BlockBuilderProvider<B, Block, C> + HeaderBackend<Block> + ProvideRuntimeApi<Block> + BlockchainEvents<Block>
-		+ CallApiAt<Block> + Send + Sync + 'static,
+		+ CallApiAt<Block> + StorageProvider<Block, B> + Send + Sync + 'static,
 		C::Api: VendorApi<Block>,
 		Block::Hash: Into<sp_core::H256>
 {
+	let key = relayer_key(&keystore)?;
+	info!(target:"keysign", "Signing bridge extrinsics with relayer key {}", key.public());
 
-	let _sign_key = "2f2f416c69636508080808080808080808080808080808080808080808080808".to_string();
-	let key = sr25519::Pair::from_string(&format!("//{}", "Eve"), None)
-		.expect("static values are valid; qed");
-
-	//let key_seed = sr25519::Pair::from_seed_slice(&[0x25,0xb4,0xfd,0x88,0x81,0x3f,0x5e,0x16,0xd4,0xbe,0xa6,0x28
-	//	,0x39,0x02,0x89,0x57,0xf9,0xe3,0x40,0x10,0x8e,0x4e,0x93,0x73,0xd0,0x8b,0x31,0xb0,0xf6,0xe3,0x04,0x40]).unwrap();
-
-	let at = BlockId::Hash(client.info().best_hash);
 	let tx_sender = TxSender::new(
 		client,
 		pool,
 		key,
-		Arc::new(parking_lot::Mutex::new(PacketNonce {nonce:0,last_block:at})),
+		nonces,
+		jobs.clone(),
 	);
 
 	let tss_sender = TssSender::new(
 		tx_sender,
 		senderbool,
-		senderfc
+		senderfc,
+		config.gas,
+		config.lotus,
+		config.manager_url,
+		NonceManager::new(config.nonce_path),
+		// Nodes are numbered from 1 like the shares of the TSS key.
+		Coordinator::new(config.party.saturating_sub(1) as usize, config.coordinator_deadline),
+		jobs,
 	);
 
-	if enable_tss_message_intermediary{
+	let resumed = tss_sender.clone();
+	thread::spawn(move || {
+		resumed.resume();
+		loop {
+			thread::sleep(std::time::Duration::from_secs(RETRY_CHECK_INTERVAL));
+			resumed.retry_due();
+		}
+	});
+
+	let watcher = tss_sender.clone();
+	thread::spawn(move || {
+		loop {
+			thread::sleep(std::time::Duration::from_secs(TRANSFER_CHECK_INTERVAL));
+			watcher.check_transfers();
+		}
+	});
+
+	let party = config.party;
+	let port = config.manager_port;
+	if config.manager {
 		thread::spawn(move || {
-			start_sm_manager(num);
+			start_sm_manager(party, port);
 		});
 	}else{
-		push(num);
+		set_key_dir(config.key_dir);
+		push(party);
 	}
-	tss_sender.start(TssRole::Party ,!enable_tss_message_intermediary /*, on_exit*/)
+	Ok(tss_sender.start(TssRole::Party, !config.manager, ScanCursor::new(config.event_cursor_path)))
 }
 
-
 #[cfg(test)]
 mod tests {
 	use super::*;
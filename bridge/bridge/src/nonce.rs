@@ -0,0 +1,256 @@
+//! Hands out the nonces of the messages the bridge sends from its Filecoin address.
+//!
+//! Asking Lotus for the next nonce of every message gives the same nonce to
+//! messages signed before the previous one reached the pool. Nonces are
+//! reserved here instead, reconciled with the account's nonce on chain and its
+//! messages in the pool. A nonce whose signing failed is handed out again, so
+//! no gap stalls the messages after it. Broadcast messages are persisted,
+//! together with the messages they replaced, until their result is settled,
+//! and transfers evicted from the pool keep their nonce until they are sent again.
+
+use std::{collections::{BTreeMap, BTreeSet}, fs, io, path::PathBuf};
+use parking_lot::Mutex;
+use pallet_tss::TransferId;
+
+#[derive(Debug)]
+pub struct NonceManager {
+    path: Option<PathBuf>,
+    state: Mutex<NonceState>,
+}
+
+/// A broadcast message whose result is not settled yet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InFlight {
+    pub cid: String,
+    /// Filecoin epoch the message was broadcast at.
+    pub epoch: u64,
+    /// The transfer the message pays out.
+    pub transfer: Option<TransferId>,
+    /// CIDs of the messages it replaced, oldest first.
+    pub replaced: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+struct NonceState {
+    /// Nonces of messages being signed.
+    reserved: BTreeSet<u64>,
+    broadcast: BTreeMap<u64, InFlight>,
+}
+
+impl NonceManager {
+    /// Without a path nothing is persisted.
+    pub fn new(path: Option<PathBuf>) -> Self {
+        let broadcast = path.as_ref().map(load).unwrap_or_default();
+        NonceManager {
+            path,
+            state: Mutex::new(NonceState { reserved: BTreeSet::new(), broadcast }),
+        }
+    }
+
+    /// Reserves the lowest nonce not used on chain nor by a pending message.
+    ///
+    /// `chain_nonce` is the nonce of the account on chain and `pooled` the
+    /// nonces of its messages in the pool.
+    pub fn reserve(&self, chain_nonce: u64, pooled: &[u64]) -> u64 {
+        let mut state = self.state.lock();
+        self.reconcile_state(&mut state, chain_nonce, pooled);
+        let nonce = (chain_nonce..)
+            .find(|n| !state.reserved.contains(n) && !state.broadcast.contains_key(n) && !pooled.contains(n))
+            .expect("nonces are not exhausted; qed");
+        state.reserved.insert(nonce);
+        nonce
+    }
+
+    /// The nonce `reserve` would hand out when `used` are taken besides the
+    /// ones pending here, without reserving it. `ours` is reserved for the
+    /// message being checked and counts as free.
+    pub fn expected(&self, chain_nonce: u64, used: &[u64], ours: Option<u64>) -> u64 {
+        let state = self.state.lock();
+        (chain_nonce..)
+            .find(|n| Some(*n) == ours
+                || (!state.reserved.contains(n) && !state.broadcast.contains_key(n) && !used.contains(n)))
+            .expect("nonces are not exhausted; qed")
+    }
+
+    /// Forgets broadcast messages of no transfer that left the pool without
+    /// landing on chain, and returns the nonces of the transfers that did.
+    pub fn reconcile(&self, chain_nonce: u64, pooled: &[u64]) -> Vec<u64> {
+        let mut state = self.state.lock();
+        self.reconcile_state(&mut state, chain_nonce, pooled)
+    }
+
+    fn reconcile_state(&self, state: &mut NonceState, chain_nonce: u64, pooled: &[u64]) -> Vec<u64> {
+        // Dropped from the pool, the nonce is free to be used again. Messages
+        // below the chain nonce landed and are kept until they are settled,
+        // transfers are kept until they are sent again.
+        let before = state.broadcast.len();
+        state.broadcast.retain(|nonce, flight| *nonce < chain_nonce || pooled.contains(nonce) || flight.transfer.is_some());
+        if state.broadcast.len() != before {
+            self.store(state);
+        }
+        state.broadcast.keys()
+            .filter(|nonce| **nonce >= chain_nonce && !pooled.contains(nonce))
+            .cloned()
+            .collect()
+    }
+
+    /// Records the message broadcast at `epoch` under a reserved nonce.
+    pub fn broadcast(&self, nonce: u64, cid: String, epoch: u64, transfer: Option<TransferId>) {
+        let mut state = self.state.lock();
+        state.reserved.remove(&nonce);
+        state.broadcast.insert(nonce, InFlight { cid, epoch, transfer, replaced: Vec::new() });
+        self.store(&state);
+    }
+
+    /// Records a message replacing the one broadcast under `nonce`.
+    pub fn replace(&self, nonce: u64, cid: String, epoch: u64) {
+        let mut state = self.state.lock();
+        if let Some(flight) = state.broadcast.get_mut(&nonce) {
+            let previous = std::mem::replace(&mut flight.cid, cid);
+            flight.replaced.push(previous);
+            flight.epoch = epoch;
+            self.store(&state);
+        }
+    }
+
+    /// Forgets a message whose result has been reported.
+    pub fn settle(&self, nonce: u64) {
+        let mut state = self.state.lock();
+        if state.broadcast.remove(&nonce).is_some() {
+            self.store(&state);
+        }
+    }
+
+    /// Gives back a reserved nonce whose message was not broadcast.
+    pub fn release(&self, nonce: u64) {
+        self.state.lock().reserved.remove(&nonce);
+    }
+
+    /// Broadcast messages not settled yet, by nonce.
+    pub fn in_flight(&self) -> Vec<(u64, InFlight)> {
+        self.state.lock().broadcast.iter().map(|(nonce, flight)| (*nonce, flight.clone())).collect()
+    }
+
+    fn store(&self, state: &NonceState) {
+        if let Some(path) = &self.path {
+            if let Err(e) = store(path, &state.broadcast) {
+                println!("failed to persist filecoin nonces: {:?}", e);
+            }
+        }
+    }
+}
+
+/// One `<nonce> <epoch> <transfer> <cid> <replaced cids>...` line per broadcast
+/// message, the transfer being `w<id>` for a withdrawal batch, `r<uid>` for a
+/// refund or `-`.
+fn load(path: &PathBuf) -> BTreeMap<u64, InFlight> {
+    let content = fs::read_to_string(path).unwrap_or_default();
+    content.lines()
+        .filter_map(|line| {
+            let mut items = line.split_whitespace();
+            let nonce = items.next()?.parse().ok()?;
+            let epoch = items.next()?.parse().ok()?;
+            let transfer = match items.next()? {
+                "-" => None,
+                t if t.starts_with('w') => Some(TransferId::WithdrawBatch(t[1..].parse().ok()?)),
+                t if t.starts_with('r') => Some(TransferId::Refund(t[1..].parse().ok()?)),
+                _ => return None,
+            };
+            let cid = items.next()?.to_string();
+            Some((nonce, InFlight { cid, epoch, transfer, replaced: items.map(String::from).collect() }))
+        })
+        .collect()
+}
+
+fn store(path: &PathBuf, broadcast: &BTreeMap<u64, InFlight>) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let content: String = broadcast.iter()
+        .map(|(nonce, flight)| {
+            let transfer = match flight.transfer {
+                Some(TransferId::WithdrawBatch(uid)) => format!("w{}", uid),
+                Some(TransferId::Refund(uid)) => format!("r{}", uid),
+                None => "-".to_string(),
+            };
+            let mut line = format!("{} {} {} {}", nonce, flight.epoch, transfer, flight.cid);
+            for cid in &flight.replaced {
+                line.push(' ');
+                line.push_str(cid);
+            }
+            line.push('\n');
+            line
+        })
+        .collect();
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, content)?;
+    fs::rename(tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_gaps_and_skips_pending_nonces() {
+        let nonces = NonceManager::new(None);
+        assert_eq!(nonces.reserve(5, &[]), 5);
+        assert_eq!(nonces.reserve(5, &[]), 6);
+        nonces.broadcast(5, "a".into(), 100, Some(TransferId::WithdrawBatch(1)));
+        // Signing the message of 6 failed.
+        nonces.release(6);
+        assert_eq!(nonces.reserve(5, &[5]), 6);
+        // A message pushed by someone else takes 7.
+        assert_eq!(nonces.reserve(5, &[5, 7]), 8);
+        assert_eq!(nonces.expected(5, &[7], None), 9);
+        assert_eq!(nonces.expected(5, &[7], Some(6)), 6);
+    }
+
+    #[test]
+    fn reconciles_with_chain_and_pool() {
+        let path = std::env::temp_dir().join(format!("fc-nonces-{}", std::process::id())).join("nonces");
+        let nonces = NonceManager::new(Some(path.clone()));
+        let mut pooled = Vec::new();
+        for nonce in 0..3 {
+            assert_eq!(nonces.reserve(0, &pooled), nonce);
+            let transfer = match nonce {
+                0 => Some(TransferId::WithdrawBatch(1)),
+                1 => None,
+                _ => Some(TransferId::Refund(3)),
+            };
+            nonces.broadcast(nonce, format!("cid{}", nonce), 100, transfer);
+            pooled.push(nonce);
+        }
+        assert_eq!(NonceManager::new(Some(path.clone())).in_flight().len(), 3);
+
+        // 0 landed and 1 was dropped from the pool, 1 is handed out again.
+        assert_eq!(nonces.reserve(1, &[2]), 1);
+        nonces.release(1);
+        nonces.replace(2, "cid2b".into(), 110);
+        let replaced = InFlight { cid: "cid2b".into(), epoch: 110, transfer: Some(TransferId::Refund(3)), replaced: vec!["cid2".into()] };
+        let landed = InFlight { cid: "cid0".into(), epoch: 100, transfer: Some(TransferId::WithdrawBatch(1)), replaced: Vec::new() };
+        assert_eq!(nonces.in_flight(), vec![(0, landed), (2, replaced.clone())]);
+
+        // 0 is kept until its result is settled.
+        nonces.settle(0);
+        assert_eq!(NonceManager::new(Some(path.clone())).in_flight(), vec![(2, replaced)]);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn keeps_transfers_evicted_from_the_pool() {
+        let nonces = NonceManager::new(None);
+        for nonce in 0..2 {
+            assert_eq!(nonces.reserve(0, &[]), nonce);
+        }
+        nonces.broadcast(0, "a".into(), 100, Some(TransferId::Refund(1)));
+        nonces.broadcast(1, "b".into(), 100, None);
+
+        // Both left the pool without landing, only the transfer keeps its nonce.
+        assert_eq!(nonces.reconcile(0, &[]), vec![0]);
+        assert_eq!(nonces.in_flight().len(), 1);
+        assert_eq!(nonces.reserve(0, &[]), 1);
+        // Back in the pool once sent again.
+        assert!(nonces.reconcile(0, &[0]).is_empty());
+    }
+}
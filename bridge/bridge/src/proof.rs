@@ -0,0 +1,156 @@
+//! Builds the proofs `pallet-tss` verifies deposits and transfer results with.
+//!
+//! Everything is fetched as raw IPLD blocks through `ChainReadObj`, the runtime
+//! recomputes the CIDs and walks the same structures again.
+
+use std::convert::TryFrom;
+
+use forest_blocks::Tipset;
+use lotus_api_forest::{Http, api::ChainApi, error::Error as LotusError};
+use pallet_fc_relay::{amt::{self, BlockStore, Node, ProofError, Root}, cbor::{CborError, Decoder},
+                      message::{self, TxMeta}, tipset_hash, BlockHeader, MessageProof};
+
+#[derive(Debug)]
+pub enum ProofBuildError {
+    Lotus(LotusError),
+    Proof(ProofError),
+    InvalidCid,
+    /// The tipset including the message is no longer canonical.
+    Reorged,
+}
+
+impl From<LotusError> for ProofBuildError {
+    fn from(e: LotusError) -> Self {
+        ProofBuildError::Lotus(e)
+    }
+}
+
+impl From<ProofError> for ProofBuildError {
+    fn from(e: ProofError) -> Self {
+        ProofBuildError::Proof(e)
+    }
+}
+
+impl From<CborError> for ProofBuildError {
+    fn from(e: CborError) -> Self {
+        ProofBuildError::Proof(ProofError::Cbor(e))
+    }
+}
+
+/// Raw DAG-CBOR encoding of a tipset's headers, as `submit_tipset` expects them.
+pub async fn raw_headers(http: &Http, tipset: &Tipset) -> Result<Vec<Vec<u8>>, ProofBuildError> {
+    let mut headers = Vec::new();
+    for cid in tipset.cids() {
+        headers.push(http.chain_read_obj(cid).await?);
+    }
+    Ok(headers)
+}
+
+/// Proves `message` was executed in `tipset`, whose receipts are committed to by `child`.
+pub async fn build_message_proof(http: &Http, tipset: &Tipset, child: &Tipset, message: &[u8])
+    -> Result<MessageProof, ProofBuildError>
+{
+    let mut blocks = Vec::new();
+    let mut headers = Vec::new();
+    for raw in raw_headers(http, tipset).await? {
+        let header = BlockHeader::from_cbor(&raw)?;
+        let meta_raw = read(http, &header.messages).await?;
+        let meta = TxMeta::from_cbor(&meta_raw)?;
+        blocks.push(meta_raw);
+
+        let mut included = false;
+        for root in [&meta.bls_messages, &meta.secp_messages].iter() {
+            for cid in collect_amt(http, root, &mut blocks).await? {
+                included |= cid.as_slice() == message;
+                blocks.push(read(http, &cid).await?);
+            }
+        }
+        headers.push(header);
+        // Blocks after the one including the message do not affect its receipt index.
+        if included {
+            break;
+        }
+    }
+
+    let index = {
+        let store = BlockStore::new(&blocks);
+        message::execution_index(&store, &headers, message)?.0
+    };
+    let child_header = BlockHeader::from_cbor(&read(http, &child.cids()[0].to_bytes()).await?)?;
+    collect_amt_path(http, &child_header.parent_message_receipts, index, &mut blocks).await?;
+
+    let key: Vec<Vec<u8>> = tipset.cids().iter().map(|cid| cid.to_bytes()).collect();
+    Ok(MessageProof {
+        tipset: tipset_hash(&key),
+        message: message.to_vec(),
+        blocks,
+    })
+}
+
+async fn read(http: &Http, cid: &[u8]) -> Result<Vec<u8>, ProofBuildError> {
+    let cid = forest_cid::Cid::try_from(cid).map_err(|_| ProofBuildError::InvalidCid)?;
+    Ok(http.chain_read_obj(&cid).await?)
+}
+
+/// Fetches every node of an AMT of links and returns the linked CIDs.
+async fn collect_amt(http: &Http, root: &[u8], blocks: &mut Vec<Vec<u8>>) -> Result<Vec<Vec<u8>>, ProofBuildError> {
+    let raw = read(http, root).await?;
+    let (height, mut pending, mut links) = {
+        let root = Root::from_cbor(&raw)?;
+        (root.height, children(&root.node), values(&root.node)?)
+    };
+    blocks.push(raw);
+
+    // Children are fetched level by level, `height` counts down to the leaves.
+    let mut level = height;
+    while level > 0 {
+        level -= 1;
+        let mut next = Vec::new();
+        for cid in pending {
+            let raw = read(http, &cid).await?;
+            {
+                let node = Node::from_cbor(&raw)?;
+                next.extend(children(&node));
+                links.extend(values(&node)?);
+            }
+            blocks.push(raw);
+        }
+        pending = next;
+    }
+    Ok(links)
+}
+
+/// Fetches the nodes on the path to `index`.
+async fn collect_amt_path(http: &Http, root: &[u8], index: u64, blocks: &mut Vec<Vec<u8>>) -> Result<(), ProofBuildError> {
+    let raw = read(http, root).await?;
+    let (mut height, mut link) = {
+        let root = Root::from_cbor(&raw)?;
+        let size = amt::slot_size(root.height);
+        (root.height, root.node.link(index / size % amt::WIDTH).map(|l| l.to_vec()))
+    };
+    blocks.push(raw);
+
+    let mut index = index;
+    while height > 0 {
+        let cid = link.ok_or(ProofError::MissingBlock)?;
+        index %= amt::slot_size(height);
+        height -= 1;
+        let raw = read(http, &cid).await?;
+        link = {
+            let node = Node::from_cbor(&raw)?;
+            node.link(index / amt::slot_size(height)).map(|l| l.to_vec())
+        };
+        blocks.push(raw);
+    }
+    Ok(())
+}
+
+fn children(node: &Node) -> Vec<Vec<u8>> {
+    node.links.clone()
+}
+
+fn values(node: &Node) -> Result<Vec<Vec<u8>>, ProofBuildError> {
+    node.values.iter()
+        .map(|v| Decoder::new(v).cid().map_err(Into::into))
+        .collect()
+}
@@ -1,4 +1,143 @@
+//! Retries of failed TSS operations.
+//!
+//! A keygen or signing session fails when a party is late or the manager is
+//! briefly unreachable. Such failures are retried with an exponential backoff
+//! up to `RetryPolicy::max_attempts`, the ones a retry cannot fix are given up
+//! at once. Attempts are counted per operation, so that one failing again after
+//! a resubmission does not start over.
+//!
+//! Nothing waits here. A retry is scheduled at a block number, a multiple of
+//! the backoff, so that the parties that saw a session fail restart it at the
+//! same block instead of each after its own wait.
 
-pub fn recover(){
+use std::{collections::BTreeMap, fmt::Debug};
+use parking_lot::Mutex;
+use log::info;
+use bridge_primitives::RetryAbort;
 
-}
\ No newline at end of file
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Attempts an operation gets, the first one included.
+    pub max_attempts: u32,
+    /// Blocks before the first retry, doubled after every failed one.
+    pub initial_backoff: u64,
+    pub max_backoff: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: 2,
+            max_backoff: 32,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Blocks before the retry following `failures` failed attempts.
+    pub fn backoff(&self, failures: u32) -> u64 {
+        let doublings = failures.saturating_sub(1).min(63);
+        self.initial_backoff
+            .checked_mul(1 << doublings)
+            .map_or(self.max_backoff, |wait| wait.min(self.max_backoff))
+            .max(1)
+    }
+}
+
+#[derive(Debug)]
+pub struct Recover<K> {
+    policy: RetryPolicy,
+    /// Failed attempts of the operations being retried.
+    failures: Mutex<BTreeMap<K, u32>>,
+}
+
+impl<K: Ord + Clone + Debug> Recover<K> {
+    pub fn new(policy: RetryPolicy) -> Self {
+        Recover {
+            policy,
+            failures: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Records a failed attempt of `op`. Returns the blocks to wait before
+    /// retrying it, or `None` when it is given up.
+    pub fn failed(&self, op: &K, class: RetryAbort) -> Option<u64> {
+        let mut failures = self.failures.lock();
+        if class == RetryAbort::Abort {
+            failures.remove(op);
+            return None;
+        }
+        let count = failures.entry(op.clone()).or_insert(0);
+        *count += 1;
+        if *count >= self.policy.max_attempts {
+            failures.remove(op);
+            return None;
+        }
+        Some(self.policy.backoff(*count))
+    }
+
+    /// Records a failed attempt of `op` at block `now`. Returns the block to
+    /// retry it at, the next multiple of the backoff, or `None` when it is given up.
+    pub fn retry_at<E: Debug>(&self, op: &K, error: &E, class: RetryAbort, now: u64) -> Option<u64> {
+        match self.failed(op, class) {
+            Some(wait) => {
+                let at = (now / wait + 1) * wait;
+                info!(target:"keysign", "retrying {:?} at block {} after {:?}", op, at, error);
+                Some(at)
+            },
+            None => {
+                info!(target:"keysign", "giving up {:?} after {:?}", op, error);
+                None
+            },
+        }
+    }
+
+    pub fn succeeded(&self, op: &K) {
+        self.failures.lock().remove(op);
+    }
+}
+
+impl<K: Ord + Clone + Debug> Default for Recover<K> {
+    fn default() -> Self {
+        Self::new(RetryPolicy::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backs_off_and_gives_up_per_operation() {
+        let recover = Recover::new(RetryPolicy {
+            max_attempts: 4,
+            initial_backoff: 2,
+            max_backoff: 5,
+        });
+        assert_eq!(recover.failed(&1u8, RetryAbort::Retry), Some(2));
+        assert_eq!(recover.failed(&1u8, RetryAbort::Retry), Some(4));
+        // Another operation has its own attempts.
+        assert_eq!(recover.failed(&2u8, RetryAbort::Retry), Some(2));
+        assert_eq!(recover.failed(&1u8, RetryAbort::Retry), Some(5));
+        assert_eq!(recover.failed(&1u8, RetryAbort::Retry), None);
+        // Given up operations start over.
+        assert_eq!(recover.failed(&1u8, RetryAbort::Retry), Some(2));
+
+        assert_eq!(recover.failed(&2u8, RetryAbort::Abort), None);
+        recover.succeeded(&1u8);
+        assert_eq!(recover.failed(&1u8, RetryAbort::Retry), Some(2));
+    }
+
+    #[test]
+    fn parties_restart_at_the_same_block() {
+        let policy = RetryPolicy { max_attempts: 3, initial_backoff: 4, max_backoff: 8 };
+        let (first, second) = (Recover::new(policy.clone()), Recover::new(policy));
+        // The session failed for both, noticed a few blocks apart.
+        assert_eq!(first.retry_at(&1u8, &"late party", RetryAbort::Retry, 101), Some(104));
+        assert_eq!(second.retry_at(&1u8, &"timeout", RetryAbort::Retry, 103), Some(104));
+        assert_eq!(first.retry_at(&1u8, &"late party", RetryAbort::Retry, 104), Some(112));
+        assert_eq!(first.retry_at(&1u8, &"late party", RetryAbort::Retry, 112), None);
+        assert_eq!(second.retry_at(&1u8, &"signed by the others", RetryAbort::Abort, 104), None);
+    }
+}
@@ -0,0 +1,45 @@
+//! The account the bridge services sign their extrinsics with.
+//!
+//! It is an sr25519 key kept in the node keystore under `RELAYER_KEY_TYPE`,
+//! inserted with `author_insertKey` or the node's `--relayer-key` flag.
+
+use sc_keystore::KeyStorePtr;
+use sp_core::{crypto::KeyTypeId, sr25519, traits::BareCryptoStore, Pair};
+
+/// Key type of the relayer account.
+pub const RELAYER_KEY_TYPE: KeyTypeId = KeyTypeId(*b"fcrl");
+
+#[derive(Debug, derive_more::Display)]
+pub enum RelayerKeyError {
+    #[display(fmt = "No bridge relayer key in the keystore, insert an sr25519 key of type `fcrl` \
+        with `author_insertKey` or start the node with `--relayer-key`")]
+    Missing,
+    #[display(fmt = "Invalid bridge relayer key: {}", _0)]
+    Invalid(String),
+    #[display(fmt = "Keystore error: {}", _0)]
+    Keystore(String),
+}
+
+impl std::error::Error for RelayerKeyError {}
+
+/// Stores the key of `suri` as a relayer key.
+pub fn insert_relayer_key(keystore: &KeyStorePtr, suri: &str) -> Result<sr25519::Public, RelayerKeyError> {
+    let pair = sr25519::Pair::from_string(suri, None)
+        .map_err(|e| RelayerKeyError::Invalid(format!("{:?}", e)))?;
+    keystore.write()
+        .insert_unknown(RELAYER_KEY_TYPE, suri, pair.public().as_ref())
+        .map_err(|_| RelayerKeyError::Keystore("the relayer key could not be stored".into()))?;
+    Ok(pair.public())
+}
+
+/// The relayer key. When several are stored the lowest public key is used, so
+/// that every service of the node signs with the same account.
+pub fn relayer_key(keystore: &KeyStorePtr) -> Result<sr25519::Pair, RelayerKeyError> {
+    let store = keystore.read();
+    let public = store.sr25519_public_keys(RELAYER_KEY_TYPE)
+        .into_iter()
+        .min()
+        .ok_or(RelayerKeyError::Missing)?;
+    store.key_pair_by_type::<sr25519::Pair>(&public, RELAYER_KEY_TYPE)
+        .map_err(|e| RelayerKeyError::Keystore(e.to_string()))
+}
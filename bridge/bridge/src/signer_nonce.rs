@@ -0,0 +1,117 @@
+//! Nonces of the extrinsics this node signs, tracked per signer.
+//!
+//! The runtime only knows the nonce of the extrinsics included at the best
+//! block, so two extrinsics submitted before the next block would get the same
+//! one. The next nonce of each signer is kept here instead: it advances when
+//! the pool accepts an extrinsic, catches up with the runtime's nonce at every
+//! new best block and is read from the runtime again after the pool rejected
+//! an extrinsic as stale or from the future, or dropped one, so that the
+//! nonce of a dropped extrinsic does not stay a gap before the next ones.
+
+use std::{collections::BTreeMap, sync::Arc};
+use parking_lot::Mutex;
+
+#[derive(Debug)]
+struct Signer<H> {
+    next: u64,
+    /// Best block the nonce was last reconciled at.
+    best: H,
+}
+
+#[derive(Debug)]
+pub struct PacketNonce<H, K> {
+    signers: BTreeMap<K, Signer<H>>,
+}
+
+impl<H: PartialEq + Clone, K: Ord + Clone> Default for PacketNonce<H, K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<H: PartialEq + Clone, K: Ord + Clone> PacketNonce<H, K> {
+    pub fn new() -> Self {
+        PacketNonce { signers: BTreeMap::new() }
+    }
+
+    /// A tracker shared by the services signing with the same accounts.
+    pub fn shared() -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(Self::new()))
+    }
+
+    /// The nonce of the next extrinsic of `signer`. `chain_nonce` is the
+    /// runtime's nonce at `best`, only read when `best` is a new best block.
+    pub fn next(&mut self, signer: &K, best: &H, chain_nonce: impl FnOnce() -> u64) -> u64 {
+        match self.signers.get_mut(signer) {
+            Some(s) if s.best == *best => s.next,
+            Some(s) => {
+                s.next = s.next.max(chain_nonce());
+                s.best = best.clone();
+                s.next
+            },
+            None => {
+                let next = chain_nonce();
+                self.signers.insert(signer.clone(), Signer { next, best: best.clone() });
+                next
+            },
+        }
+    }
+
+    /// Records an extrinsic of `signer` the pool accepted.
+    pub fn submitted(&mut self, signer: &K, nonce: u64) {
+        if let Some(s) = self.signers.get_mut(signer) {
+            s.next = s.next.max(nonce + 1);
+        }
+    }
+
+    /// Drops the nonce of `signer` after the pool rejected or dropped one of its
+    /// extrinsics, the next one is read from the runtime even if it is lower.
+    pub fn resync(&mut self, signer: &K) {
+        self.signers.remove(signer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_submissions_and_reconciles_with_the_chain() {
+        let mut nonces = PacketNonce::<u32, u8>::new();
+        assert_eq!(nonces.next(&1, &10, || 5), 5);
+        nonces.submitted(&1, 5);
+        // Same block, the runtime is not asked again.
+        assert_eq!(nonces.next(&1, &10, || unreachable!()), 6);
+        nonces.submitted(&1, 6);
+        // Signers are independent.
+        assert_eq!(nonces.next(&2, &10, || 0), 0);
+
+        // A new block with our extrinsics still in the pool keeps the local nonce,
+        // one where the account moved on takes the runtime's.
+        assert_eq!(nonces.next(&1, &11, || 6), 7);
+        assert_eq!(nonces.next(&1, &12, || 9), 9);
+
+        // A nonce the pool rejected is read again.
+        nonces.submitted(&1, 9);
+        nonces.resync(&1);
+        assert_eq!(nonces.next(&1, &12, || 9), 9);
+    }
+
+    #[test]
+    fn hands_out_the_nonce_of_a_dropped_extrinsic_again() {
+        let mut nonces = PacketNonce::<u32, u8>::new();
+        for nonce in 5..8 {
+            assert_eq!(nonces.next(&1, &10, || 5), nonce);
+            nonces.submitted(&1, nonce);
+        }
+        // 5 was dropped, the ones after it wait for it in the pool.
+        assert_eq!(nonces.next(&1, &11, || 5), 8);
+        nonces.resync(&1);
+        assert_eq!(nonces.next(&1, &11, || 5), 5);
+        nonces.submitted(&1, 5);
+        // The pool still holds 6, taken when it says so.
+        assert_eq!(nonces.next(&1, &11, || unreachable!()), 6);
+        nonces.submitted(&1, 6);
+        assert_eq!(nonces.next(&1, &11, || unreachable!()), 7);
+    }
+}
@@ -1,59 +1,52 @@
 #[warn(dead_code)]
-use std::{sync::Arc, u64, marker::PhantomData, thread};
+use std::{sync::Arc, u64, marker::PhantomData, thread, pin::Pin, task::{Context, Poll}};
 use futures::{prelude::*, executor::block_on, channel::mpsc};
 use parking_lot::Mutex;
-use log::{debug, info};
+use log::{debug, info, warn};
 use codec::{Encode, Decode};
 
-use sp_runtime::{generic::{BlockId ,Era}, traits::{Block as BlockT, Zero}};
+use sp_runtime::{generic::{BlockId ,Era}, traits::{Block as BlockT, Header as HeaderT, Zero, SaturatedConversion}};
 use sp_api::{ProvideRuntimeApi, CallApiAt};
 use sp_core::{Pair, storage::{StorageKey, StorageData}, sr25519::Pair as edPair, sr25519, ecdsa, twox_128};
-use sc_client_api::{BlockchainEvents, backend, notifications::StorageEventStream};
+use sc_client_api::{BlockchainEvents, StorageProvider, backend, notifications::StorageEventStream};
 use sp_blockchain::{HeaderBackend};
-use sp_transaction_pool::{TransactionPool, TransactionFor};
+use sp_transaction_pool::{TransactionPool, TransactionFor, TransactionStatus, TransactionStatusStreamFor,
+                          error::{Error as PoolError, IntoPoolError}};
+use sp_runtime::transaction_validity::InvalidTransaction;
 use sc_block_builder::{BlockBuilderProvider};
 use node_primitives::Hash;
 use num_traits::cast::FromPrimitive;
 use frame_system::{Call as SystemCall, EventRecord};
-use pallet_tss::{Call as TssCall, RawEvent, WithdrawDetail };
+use pallet_tss::{Call as TssCall, RawEvent, WithdrawDetail, TransferId };
+use pallet_fc_relay::{Call as FcRelayCall, MessageProof, f3::FinalityCertificate};
 
 use filecoin_bridge_runtime::{UncheckedExtrinsic, apis::VendorApi ,Call, SignedPayload
                               , Event, VERSION, Runtime, AccountId, Signature, Balance, Index};
 
 use tss_signer::{set_pubkey, sign_btc_hex_return_hex, sign_by_tss};
 use node_tss::{start_sm_manager, key_gen, push};
-use bridge_primitives::{TssError,tss_error};
 use async_trait::async_trait;
 
-use crate::recover::recover;
+use crate::jobs::{job_id, JobQueue, Task};
+use crate::signer_nonce::PacketNonce;
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum TokenType {
-    FC,
-    Bool,
-}
-
-impl TokenType{
-    fn to_vec(&self) -> Vec<u8>{
-        match self {
-            TokenType::FC => vec![01u8,02u8,03u8],
-            TokenType::Bool => vec![21u8,02u8,03u8],
-        }
-    }
-}
 #[derive(Debug, Clone, PartialEq)]
 pub enum TxType {
-    Spv,
     System,
     TssKeyGen(Vec<u8>,Vec<Vec<u8>>),
     TssKeyGenBool(Vec<u8>,Vec<Vec<u8>>),
     TssKeyGenFc(Vec<u8>,Vec<Vec<u8>>),
 
-    BtcAddressSet(Vec<u8>),
-    Signature(Vec<u8>),
-
-    BoolDeposit(Vec<u8>,TokenType,u64), //who tokentype amount
-    FCDeposit(Vec<u8>,TokenType,u128, Vec<u8>), //who tokentype amount from
+    FCDeposit(MessageProof),
+    FcTipset(Vec<Vec<u8>>), // raw block headers
+    FcF3Certificate(FinalityCertificate),
+    FcWithdrawConfirmed(u64,MessageProof), // batch
+    FcWithdrawFailed(u64,MessageProof), // batch
+    FcRefundConfirmed(u64,MessageProof), // uid
+    FcRefundFailed(u64,MessageProof), // uid
+    FcTransferProposal(TransferId,Vec<u8>), // unsigned message
+    FcTransferSent(TransferId,Vec<u8>), // message_cid
+    FcBridgeIdAddress(Vec<u8>),
 
     // TssKeyActive
     TssKeyGenActive(Vec<u8>,Vec<u8>),
@@ -61,6 +54,33 @@ pub enum TxType {
     TssKeyGenFcActive(Vec<u8>,Vec<u8>),
 }
 
+impl TxType {
+    /// The runtime call submitting this message.
+    pub fn into_call(self) -> Call {
+        match self {
+            TxType::System => Call::System(SystemCall::remark(vec![1u8])),
+            TxType::TssKeyGen(tss_pubkey,pk_vec) => Call::Tss(TssCall::key_created_result_is(tss_pubkey,pk_vec,vec![0u8])),
+            TxType::TssKeyGenBool(tss_pubkey,pk_vec) => Call::Tss(TssCall::key_created_result_is_bool(tss_pubkey,pk_vec,vec![0u8])),
+            TxType::TssKeyGenFc(tss_pubkey,pk_vec) => Call::Tss(TssCall::key_created_result_is_fc(tss_pubkey,pk_vec,vec![0u8])),
+            //active
+            TxType::TssKeyGenActive(url,store) => Call::Tss(TssCall::key_gen(url,store)),
+            TxType::TssKeyGenBoolActive(url,store) => Call::Tss(TssCall::key_gen_bool(url,store)),
+            TxType::TssKeyGenFcActive(url,store) => Call::Tss(TssCall::key_gen_fc(url,store)),
+
+            TxType::FCDeposit(proof) => Call::Tss(TssCall::deposit_token(proof)),
+            TxType::FcTipset(headers) => Call::FcRelay(FcRelayCall::submit_tipset(headers)),
+            TxType::FcF3Certificate(certificate) => Call::FcRelay(FcRelayCall::submit_f3_certificate(certificate)),
+            TxType::FcWithdrawConfirmed(batch,proof) => Call::Tss(TssCall::confirm_withdraw(batch,proof)),
+            TxType::FcWithdrawFailed(batch,proof) => Call::Tss(TssCall::fail_withdraw(batch,proof)),
+            TxType::FcRefundConfirmed(uid,proof) => Call::Tss(TssCall::confirm_refund(uid,proof)),
+            TxType::FcRefundFailed(uid,proof) => Call::Tss(TssCall::fail_refund(uid,proof)),
+            TxType::FcTransferProposal(id,message) => Call::Tss(TssCall::propose_transfer(id,message)),
+            TxType::FcTransferSent(id,cid) => Call::Tss(TssCall::transfer_sent(id,cid)),
+            TxType::FcBridgeIdAddress(address) => Call::Tss(TssCall::set_bridge_id_address(address)),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct TxMessage {
     /// The type of Message.
@@ -74,6 +94,8 @@ impl TxMessage{
         }
     }
 }
+pub type FinalityStream<H> = Pin<Box<dyn Stream<Item = (u64, H)> + Send>>;
+
 #[async_trait]
 pub trait SuperviseClient<B>
     where
@@ -81,40 +103,111 @@ pub trait SuperviseClient<B>
 {
     fn get_notification_stream(&self,filter_keys: Option<&[StorageKey]>,
                                child_filter_keys: Option<&[(StorageKey, Option<Vec<StorageKey>>)]>) -> StorageEventStream<B::Hash>;
+    /// Number and hash of the blocks GRANDPA finalizes. Blocks finalized
+    /// together are notified once, by the last of them.
+    fn finality_stream(&self) -> FinalityStream<B::Hash>;
+    /// Hash of the finalized or best chain block at `number`.
+    fn block_hash(&self, number: u64) -> Option<B::Hash>;
+    /// Storage of `key` at block `hash`, an error when the state of the block is pruned.
+    fn storage_at(&self, hash: B::Hash, key: &StorageKey) -> sp_blockchain::Result<Option<StorageData>>;
     fn is_tss_party(&self) -> bool;
 
     fn tss_pubkey(&self) -> Vec<u8>;
     fn tss_pubkey_bool(&self) -> Vec<u8>;
     fn tss_pubkey_fc(&self) -> Vec<u8>;
     fn tss_url(&self) -> Vec<u8>;
+    fn tss_members(&self) -> Vec<Vec<u8>>;
+    fn best_number(&self) -> u64;
+    /// Filecoin height up to which the relay proves executed messages.
+    fn fc_deposit_final_height(&self) -> u64;
 
-    fn submit(&self, message: TxMessage);
-    fn submit_fc_transfer_tss(&self, message: TxMessage);
+    /// Submits `call` signed by `signer`, unless the same call is still being submitted.
+    fn submit_call(&self, call: Call, signer: Signer) -> Submission<B::Hash>;
+
+    /// Submits the call of the unfinished job `job` again.
+    fn resume_call(&self, job: &str, call: Call, signer: Signer) -> Submission<B::Hash>;
+
+    fn submit(&self, message: TxMessage) -> Submission<B::Hash> {
+        self.submit_call(message.tx_type.into_call(), Signer::Relayer)
+    }
+
+    fn submit_fc_transfer_tss(&self, message: TxMessage) -> Submission<B::Hash> {
+        self.submit_call(message.tx_type.into_call(), Signer::Tss)
+    }
 
     fn submit_key_gen_bool_tss(&self);
 }
 
-pub struct PacketNonce<B>
-    where
-        B: BlockT,
-{
-    pub nonce: u64, // to control nonce.
-pub last_block: BlockId<B>,
+/// Blocks a bridge extrinsic is valid for.
+const MORTAL_PERIOD: u64 = 64;
+/// Times an extrinsic is submitted again after it left the pool without being included.
+const MAX_RESUBMISSIONS: usize = 8;
+
+/// Progress of a submitted extrinsic.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExtrinsicStatus<H> {
+    InBlock(H),
+    Finalized(H),
+    /// It left the pool without being included. It is submitted again unless
+    /// this was the last attempt or this node could not sign it.
+    Dropped,
+    Invalid,
 }
 
-impl <B>PacketNonce<B>
-    where
-        B: BlockT,
-{
-    pub fn new() -> PacketNonce<B>{
-        PacketNonce{
-            nonce:0,
-            last_block: BlockId::number(0.into()),
+/// Statuses of a submitted extrinsic and of its resubmissions. The stream ends
+/// after `Finalized`, `Invalid` or the last `Dropped`.
+pub struct Submission<H>(mpsc::UnboundedReceiver<ExtrinsicStatus<H>>);
+
+/// Why a submitted extrinsic was not finalized.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubmitError<H> {
+    Dropped,
+    Invalid,
+    /// It is in this block, whose finality was not followed.
+    Unfinalized(H),
+}
+
+impl<H> Submission<H> {
+    /// Waits for the extrinsic to be finalized, returns the block it is in.
+    pub async fn finalized(mut self) -> Result<H, SubmitError<H>> {
+        let mut error = SubmitError::Dropped;
+        while let Some(status) = self.next().await {
+            error = match status {
+                ExtrinsicStatus::Finalized(hash) => return Ok(hash),
+                ExtrinsicStatus::InBlock(hash) => SubmitError::Unfinalized(hash),
+                ExtrinsicStatus::Dropped => SubmitError::Dropped,
+                ExtrinsicStatus::Invalid => SubmitError::Invalid,
+            };
         }
+        Err(error)
+    }
+}
+
+impl<H> Stream for Submission<H> {
+    type Item = ExtrinsicStatus<H>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.0).poll_next(cx)
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Signer {
+    /// The relayer key of this node.
+    Relayer,
+    /// The TSS key, signed with the other parties.
+    Tss,
+}
+
+/// Why an extrinsic did not make it into the pool.
+enum Rejected {
+    /// This node did not take part in signing it.
+    Unsigned,
+    /// Its nonce was stale, from the future or taken by an extrinsic in the pool.
+    Nonce,
+    Invalid,
+}
+
 pub struct TxSender<A,Block,B,C>
     where
         A: TransactionPool<Block = Block> + 'static,
@@ -128,10 +221,36 @@ pub struct TxSender<A,Block,B,C>
     pub client: Arc<C>,
     pub tx_pool: Arc<A>,
     pub ed_key: edPair,
-    pub packet_nonce: Arc<Mutex<PacketNonce<Block>>>,
+    pub packet_nonce: Arc<Mutex<PacketNonce<Block::Hash, AccountId>>>,
+    /// Watches the submitted extrinsics.
+    runtime: Arc<tokio::runtime::Runtime>,
+    pub jobs: Arc<JobQueue>,
     _phantom: PhantomData<B>,
 }
 
+impl<A,Block,B,C> Clone for TxSender<A,Block,B,C>
+    where
+        A: TransactionPool<Block = Block> + 'static,
+        Block: BlockT,
+        B: backend::Backend<Block> + Send + Sync + 'static,
+        C: BlockBuilderProvider<B, Block, C> + HeaderBackend<Block> + ProvideRuntimeApi<Block> + BlockchainEvents<Block>
+        + CallApiAt<Block> + Send + Sync + 'static,
+        C::Api: VendorApi<Block>,
+        Block::Hash: Into<sp_core::H256>
+{
+    fn clone(&self) -> Self {
+        TxSender {
+            client: self.client.clone(),
+            tx_pool: self.tx_pool.clone(),
+            ed_key: self.ed_key.clone(),
+            packet_nonce: self.packet_nonce.clone(),
+            runtime: self.runtime.clone(),
+            jobs: self.jobs.clone(),
+            _phantom: PhantomData,
+        }
+    }
+}
+
 impl<A,Block,B,C> TxSender<A,Block,B,C>
     where
         A: TransactionPool<Block = Block> + 'static,
@@ -143,41 +262,206 @@ impl<A,Block,B,C> TxSender<A,Block,B,C>
         Block::Hash: Into<sp_core::H256>
 {
     pub fn new(client:Arc<C>,tx_pool:Arc<A> /*,key:KeyStorePtr*/
-               ,ed_key:edPair,packet_nonce:Arc<Mutex<PacketNonce<Block>>>) -> Self{
+               ,ed_key:edPair,packet_nonce:Arc<Mutex<PacketNonce<Block::Hash, AccountId>>>,jobs:Arc<JobQueue>) -> Self{
         TxSender{
             client:client,
             tx_pool:tx_pool,
             ed_key: ed_key,
             packet_nonce:packet_nonce,
+            runtime: Arc::new(tokio::runtime::Runtime::new().expect("the extrinsic watcher starts; qed")),
+            jobs: jobs,
             _phantom: PhantomData,
         }
     }
 
-    fn get_nonce(&self) -> u64 {
-        let mut p_nonce = self.packet_nonce.lock();
-        let info = self.client.info();
-        let at: BlockId<Block> = BlockId::Hash(info.best_hash);
+    /// The nonce of the next extrinsic of `who` at the best block.
+    fn next_nonce(&self, nonces: &mut PacketNonce<Block::Hash, AccountId>, who: &AccountId) -> u64 {
+        let best = self.client.info().best_hash;
+        nonces.next(who, &best, || {
+            self.client.runtime_api().account_nonce(&BlockId::Hash(best), who).unwrap()
+        })
+    }
 
-        if p_nonce.last_block == at {
-            p_nonce.nonce = p_nonce.nonce + 1;
-        } else {
-            p_nonce.nonce = self
-                .client
-                .runtime_api()
-                .account_nonce(&at, &self.ed_key.public().0.into())
-                .unwrap();
-            p_nonce.last_block = at;
+    fn account(&self, signer: Signer) -> AccountId {
+        match signer {
+            Signer::Relayer => self.ed_key.public().0.into(),
+            Signer::Tss => {
+                let publickey = secp256k1::PublicKey::parse_slice(&self.tss_pubkey_bool(),None).unwrap();
+                sp_io::hashing::blake2_256(&publickey.serialize_compressed()[..]).into()
+            },
         }
+    }
 
-        p_nonce.nonce
+    /// Signs `payload` of the operation `op` with the TSS key, `None` when this
+    /// node is not part of the signing session or it failed.
+    fn tss_sign(&self, op: Vec<u8>, payload: &[u8]) -> Option<Signature> {
+        let message = sp_io::hashing::blake2_256(payload);
+        let url = self.tss_url();
+        let str_url = core::str::from_utf8(&url).unwrap();
+        let pubkey = self.tss_pubkey_bool();
+        match sign_by_tss(message.to_vec(),str_url,pubkey) {
+            Ok(sig) => Some(ecdsa::Signature::from_slice(&sig).into()),
+            Err(e) => {
+                info!(target:"keysign", "failed to sign extrinsic {:?} with the tss key: {:?}", op, e);
+                None
+            },
+        }
     }
 
-    fn get_nonce_any(&self,accountid:[u8;32]) -> u64 {
+    /// Signs `function` as the extrinsic of `signer` with `nonce`, valid during `era` from block `best`.
+    fn sign(&self, signer: Signer, who: &AccountId, function: Call, nonce: u64, era: Era, best: u64) -> Option<TransactionFor<A>> {
+        let extra = (
+            frame_system::CheckSpecVersion::<Runtime>::new(),
+            frame_system::CheckTxVersion::<Runtime>::new(),
+            frame_system::CheckGenesis::<Runtime>::new(),
+            frame_system::CheckEra::<Runtime>::from(era),
+            frame_system::CheckNonce::<Runtime>::from(nonce as Index),
+            frame_system::CheckWeight::<Runtime>::new(),
+            pallet_transaction_payment::ChargeTransactionPayment::<Runtime>::from(0 as Balance),
+        );
+        let op = sp_io::hashing::blake2_256(&function.encode()).to_vec();
+        let genesis_hash = self.client.hash(Zero::zero())
+            .expect("Genesis block always exists; qed").unwrap().into();
+        let birth_hash = self.client.hash(era.birth(best).saturated_into()).ok().flatten()?.into();
+        let raw_payload = SignedPayload::from_raw(
+            function,
+            extra,
+            (
+                VERSION.spec_version,
+                VERSION.transaction_version,
+                genesis_hash,
+                birth_hash,
+                (),
+                (),
+                (),
+            ),
+        );
+        let signature: Signature = match signer {
+            Signer::Relayer => raw_payload.using_encoded(|payload| self.ed_key.sign(payload)).into(),
+            Signer::Tss => raw_payload.using_encoded(|payload| self.tss_sign(op, payload))?,
+        };
+        let (function, extra, _) = raw_payload.deconstruct();
+        let extrinsic =
+            UncheckedExtrinsic::new_signed(function, who.clone().into(), signature, extra);
+        debug!(target:"witness", "extrinsic {:?}", extrinsic);
+        Some(Decode::decode(&mut &extrinsic.encode()[..]).unwrap())
+    }
+
+    /// Signs and submits `function`, waiting for the pool to accept it. Returns
+    /// the status stream of the extrinsic and the block its era ends at.
+    fn push(&self, signer: Signer, function: Call) -> Result<(Box<TransactionStatusStreamFor<A>>, u64), Rejected> {
+        let who = self.account(signer);
         let info = self.client.info();
-        let at: BlockId<Block> = BlockId::Hash(info.best_hash);
-        self.client.runtime_api().account_nonce(&at, &accountid.into()).unwrap()
+        let best: u64 = info.best_number.saturated_into();
+        let at = BlockId::Hash(info.best_hash);
+        let era = Era::mortal(MORTAL_PERIOD, best);
+
+        let (nonce, xt, mut nonces) = match signer {
+            // Held until the pool answers so that concurrent submissions get consecutive nonces.
+            Signer::Relayer => {
+                let mut nonces = self.packet_nonce.lock();
+                let nonce = self.next_nonce(&mut nonces, &who);
+                let xt = self.sign(signer, &who, function, nonce, era, best);
+                (nonce, xt, nonces)
+            },
+            // Not held while the TSS signs, deposits of the TSS account are relayed one at a time.
+            Signer::Tss => {
+                let nonce = self.next_nonce(&mut self.packet_nonce.lock(), &who);
+                let xt = self.sign(signer, &who, function, nonce, era, best);
+                (nonce, xt, self.packet_nonce.lock())
+            },
+        };
+        let xt = xt.ok_or(Rejected::Unsigned)?;
+
+        let source = sp_runtime::transaction_validity::TransactionSource::External;
+        let submission = self.tx_pool.submit_and_watch(&at, source, xt);
+        // Waited for on its own thread, the caller may already be inside an executor.
+        let result = thread::spawn(move || block_on(submission))
+            .join().expect("pool submission does not panic; qed");
+        match result {
+            Ok(status) => {
+                debug!(target:"witness", "extrinsic of {} submitted with nonce {}", who, nonce);
+                nonces.submitted(&who, nonce);
+                Ok((status, era.death(best)))
+            },
+            Err(e) => {
+                info!(target:"witness", "extrinsic of {} with nonce {} rejected: {:?}", who, nonce, e);
+                match e.into_pool_error() {
+                    Ok(PoolError::InvalidTransaction(InvalidTransaction::Stale))
+                    | Ok(PoolError::InvalidTransaction(InvalidTransaction::Future)) => {
+                        nonces.resync(&who);
+                        Err(Rejected::Nonce)
+                    },
+                    // Another extrinsic of ours holds the nonce, after a resync below it.
+                    Ok(PoolError::TooLowPriority { .. }) => {
+                        nonces.submitted(&who, nonce);
+                        Err(Rejected::Nonce)
+                    },
+                    _ => Err(Rejected::Invalid),
+                }
+            },
+        }
     }
 
+    /// Submits `function` until it is finalized, reporting its statuses and the result of `job`.
+    fn watch(&self, signer: Signer, function: Call, job: String,
+             report: mpsc::UnboundedSender<ExtrinsicStatus<Block::Hash>>, attempt: usize) {
+        if attempt > MAX_RESUBMISSIONS {
+            warn!(target:"witness", "{:?} was not included after {} submissions", function, attempt);
+            self.jobs.finish(&job, Err(format!("not included after {} submissions", attempt)));
+            return;
+        }
+        self.jobs.start(&job);
+        let (mut status, death) = match self.push(signer, function.clone()) {
+            Ok(pushed) => pushed,
+            Err(Rejected::Nonce) => return self.watch(signer, function, job, report, attempt + 1),
+            Err(Rejected::Unsigned) => {
+                self.jobs.finish(&job, Err("not signed by this node".into()));
+                let _ = report.unbounded_send(ExtrinsicStatus::Dropped);
+                return;
+            },
+            Err(Rejected::Invalid) => {
+                self.jobs.finish(&job, Err("rejected by the pool".into()));
+                let _ = report.unbounded_send(ExtrinsicStatus::Invalid);
+                return;
+            },
+        };
+        let sender = self.clone();
+        self.runtime.spawn(async move {
+            while let Some(s) = status.next().await {
+                match s {
+                    TransactionStatus::InBlock(hash) => {
+                        let _ = report.unbounded_send(ExtrinsicStatus::InBlock(hash));
+                    },
+                    TransactionStatus::Finalized(hash) => {
+                        sender.jobs.finish(&job, Ok(format!("finalized in {:?}", hash)));
+                        let _ = report.unbounded_send(ExtrinsicStatus::Finalized(hash));
+                        return;
+                    },
+                    // It is in a block, only its finality is not followed anymore.
+                    TransactionStatus::FinalityTimeout(hash) => {
+                        sender.jobs.finish(&job, Ok(format!("in {:?}", hash)));
+                        return;
+                    },
+                    TransactionStatus::Invalid if sender.best_number() < death => {
+                        warn!(target:"witness", "{:?} became invalid", function);
+                        sender.jobs.finish(&job, Err("invalid".into()));
+                        let _ = report.unbounded_send(ExtrinsicStatus::Invalid);
+                        return;
+                    },
+                    // Dropped, replaced or invalid because its era ended. Its nonce
+                    // is free again unless it is on chain already.
+                    TransactionStatus::Dropped | TransactionStatus::Usurped(_) | TransactionStatus::Invalid => {
+                        sender.packet_nonce.lock().resync(&sender.account(signer));
+                        let _ = report.unbounded_send(ExtrinsicStatus::Dropped);
+                        thread::spawn(move || sender.watch(signer, function, job, report, attempt + 1));
+                        return;
+                    },
+                    _ => {},
+                }
+            }
+        });
+    }
 }
 
 impl<A,Block,B,C> SuperviseClient<Block> for TxSender<A,Block,B,C>
@@ -186,7 +470,7 @@ impl<A,Block,B,C> SuperviseClient<Block> for TxSender<A,Block,B,C>
         Block: BlockT,
         B: backend::Backend<Block> + Send + Sync + 'static,
         C: BlockBuilderProvider<B, Block, C> + HeaderBackend<Block> + ProvideRuntimeApi<Block> + BlockchainEvents<Block>
-        + CallApiAt<Block> + Send + Sync + 'static,
+        + CallApiAt<Block> + StorageProvider<Block, B> + Send + Sync + 'static,
         C::Api: VendorApi<Block>,
         Block::Hash: Into<sp_core::H256>
 {
@@ -196,6 +480,19 @@ impl<A,Block,B,C> SuperviseClient<Block> for TxSender<A,Block,B,C>
             .unwrap()
     }
 
+    fn finality_stream(&self) -> FinalityStream<Block::Hash> {
+        Box::pin(self.client.finality_notification_stream()
+            .map(|notification| ((*notification.header.number()).saturated_into(), notification.hash)))
+    }
+
+    fn block_hash(&self, number: u64) -> Option<Block::Hash> {
+        self.client.hash(number.saturated_into()).ok().flatten()
+    }
+
+    fn storage_at(&self, hash: Block::Hash, key: &StorageKey) -> sp_blockchain::Result<Option<StorageData>> {
+        self.client.storage(&BlockId::Hash(hash), key)
+    }
+
     fn is_tss_party(&self) -> bool {
         let info = self.client.info();
         let at: BlockId<Block> = BlockId::Hash(info.best_hash);
@@ -246,76 +543,48 @@ impl<A,Block,B,C> SuperviseClient<Block> for TxSender<A,Block,B,C>
             .unwrap()
     }
 
-    fn submit(&self, relay_message: TxMessage) {
-        let local_id: AccountId = self.ed_key.public().0.into();
+    fn tss_members(&self) -> Vec<Vec<u8>> {
         let info = self.client.info();
-        let at = BlockId::Hash(info.best_hash);
-        {
-            let nonce = self.get_nonce();
-
-            let function = match relay_message.tx_type {
-                TxType::System => Call::System(SystemCall::remark(vec![1u8])),
-                TxType::TssKeyGen(tss_pubkey,pk_vec) => Call::Tss(TssCall::key_created_result_is(tss_pubkey,pk_vec,vec![0u8])),
-                TxType::TssKeyGenBool(tss_pubkey,pk_vec) => {
-                    let tss_gen_pubkey = tss_pubkey.clone(); // u8 65
-                    let publickey = secp256k1::PublicKey::parse_slice(&tss_gen_pubkey,None).unwrap();
-                    let compressed_pubkey = publickey.serialize_compressed();
-                    let pubkey_blake = sp_io::hashing::blake2_256(&compressed_pubkey[..]);
-                    let local_id:AccountId = pubkey_blake.into();
-                    println!("========bool_accountid========{:?}",local_id);
-                    Call::Tss(TssCall::key_created_result_is_bool(tss_pubkey,pk_vec,vec![0u8]))},
-                TxType::TssKeyGenFc(tss_pubkey,pk_vec) => Call::Tss(TssCall::key_created_result_is_fc(tss_pubkey,pk_vec,vec![0u8])),
-                //active
-                TxType::TssKeyGenActive(url,store) => Call::Tss(TssCall::key_gen(url,store)),
-                TxType::TssKeyGenBoolActive(url,store) => Call::Tss(TssCall::key_gen_bool(url,store)),
-                TxType::TssKeyGenFcActive(url,store) => Call::Tss(TssCall::key_gen_fc(url,store)),
-
-                //TxType::BtcAddressSet(tss_pubkey) => Call::BtcBridge(BtcBridgeCall::set_tss_revice_address(tss_pubkey)),
-                //TxType::Signature(signed_btc_tx) => Call::BtcBridge(BtcBridgeCall::put_signedbtctxproposal(signed_btc_tx)),
-                _ => Call::System(SystemCall::remark(vec![1u8])),
-            };
+        let at: BlockId<Block> = BlockId::Hash(info.best_hash);
 
-            let extra = |i: Index, f: Balance| {
-                (
-                    frame_system::CheckSpecVersion::<Runtime>::new(),
-                    frame_system::CheckTxVersion::<Runtime>::new(),
-                    frame_system::CheckGenesis::<Runtime>::new(),
-                    frame_system::CheckEra::<Runtime>::from(Era::Immortal),
-                    frame_system::CheckNonce::<Runtime>::from(i),
-                    frame_system::CheckWeight::<Runtime>::new(),
-                    pallet_transaction_payment::ChargeTransactionPayment::<Runtime>::from(f),
-                )
-            };
-            let _version = self.client.runtime_version_at(&at).unwrap().spec_version;
-            let genesis_hash = self.client.hash(Zero::zero())
-                .expect("Genesis block always exists; qed").unwrap().into();
-            let raw_payload = SignedPayload::from_raw(
-                function,
-                extra(nonce as u32 , 0),
-                (
-                    VERSION.spec_version,
-                    VERSION.transaction_version,
-                    genesis_hash,
-                    genesis_hash,
-                    (),
-                    (),
-                    (),
-                ),
-            );
-            let signature = raw_payload.using_encoded(|payload| self.ed_key.sign(payload));
-            let (function, extra, _) = raw_payload.deconstruct();
-            let extrinsic =
-                UncheckedExtrinsic::new_signed(function, local_id.into(), signature.into(), extra);
-            let xt: TransactionFor<A> = Decode::decode(&mut &extrinsic.encode()[..]).unwrap();
-            debug!(target:"witness", "extrinsic {:?}", xt);
-            let source = sp_runtime::transaction_validity::TransactionSource::External;
-            let result2 = self.tx_pool.submit_one(&at, source, xt);
-            std::thread::spawn(|| {
-                let mut rt = tokio::runtime::Runtime::new().unwrap();
-                let res = rt.block_on(result2);
-                println!("======submit===result==={:?}",res);
-            });
+        self.client
+            .runtime_api()
+            .tss_members(&at)
+            .unwrap()
+    }
+
+    fn best_number(&self) -> u64 {
+        self.client.info().best_number.saturated_into()
+    }
+
+    fn fc_deposit_final_height(&self) -> u64 {
+        let info = self.client.info();
+        let at: BlockId<Block> = BlockId::Hash(info.best_hash);
+
+        self.client
+            .runtime_api()
+            .fc_deposit_final_height(&at)
+            .unwrap()
+    }
+
+    /// The extrinsic is submitted again, with a new nonce and era, whenever it
+    /// leaves the pool without being included.
+    fn submit_call(&self, call: Call, signer: Signer) -> Submission<Block::Hash> {
+        let (report, status) = mpsc::unbounded();
+        let encoded = call.encode();
+        let job = job_id("extrinsic", &sp_io::hashing::blake2_256(&encoded));
+        if !self.jobs.enqueue(&job, Task::Extrinsic { call: encoded, tss: signer == Signer::Tss }) {
+            debug!(target:"witness", "{:?} is already being submitted", call);
+            return Submission(status);
         }
+        self.watch(signer, call, job, report, 0);
+        Submission(status)
+    }
+
+    fn resume_call(&self, job: &str, call: Call, signer: Signer) -> Submission<Block::Hash> {
+        let (report, status) = mpsc::unbounded();
+        self.watch(signer, call, job.to_string(), report, 0);
+        Submission(status)
     }
 
     fn submit_key_gen_bool_tss(&self){
@@ -326,87 +595,4 @@ impl<A,Block,B,C> SuperviseClient<Block> for TxSender<A,Block,B,C>
         let data3:TxMessage = TxMessage::new(TxType::TssKeyGenFcActive(url.clone(),store.clone()));
         self.submit(data1);
     }
-
-    fn submit_fc_transfer_tss(&self, relay_message: TxMessage) {
-        let tss_gen_pubkey = self.tss_pubkey_bool(); // u8 65
-        let publickey = secp256k1::PublicKey::parse_slice(&tss_gen_pubkey,None).unwrap();
-        let compressed_pubkey = publickey.serialize_compressed();
-        let pubkey_blake = sp_io::hashing::blake2_256(&compressed_pubkey[..]);
-        let local_id:AccountId = pubkey_blake.into();
-        println!("local_id={:?}",local_id);
-
-        let info = self.client.info();
-        let at = BlockId::Hash(info.best_hash);
-        {
-            let nonce = self.get_nonce_any(pubkey_blake.into());
-
-            let function = match relay_message.tx_type {
-                TxType::FCDeposit(who,_tokentype, value, from) =>
-                    Call::Tss(TssCall::deposit_token(who, value, from)),
-                _ => Call::System(SystemCall::remark(vec![1u8])),
-            };
-
-            let extra = |i: Index, f: Balance| {
-                (
-                    frame_system::CheckSpecVersion::<Runtime>::new(),
-                    frame_system::CheckTxVersion::<Runtime>::new(),
-                    frame_system::CheckGenesis::<Runtime>::new(),
-                    frame_system::CheckEra::<Runtime>::from(Era::Immortal),
-                    frame_system::CheckNonce::<Runtime>::from(i),
-                    frame_system::CheckWeight::<Runtime>::new(),
-                    pallet_transaction_payment::ChargeTransactionPayment::<Runtime>::from(f),
-                )
-            };
-            let _version = self.client.runtime_version_at(&at).unwrap().spec_version;
-            let genesis_hash = self.client.hash(Zero::zero())
-                .expect("Genesis block always exists; qed").unwrap().into();
-            let raw_payload = SignedPayload::from_raw(
-                function,
-                extra(nonce as u32 , 0),
-                (
-                    VERSION.spec_version,
-                    VERSION.transaction_version,
-                    genesis_hash,
-                    genesis_hash,
-                    (),
-                    (),
-                    (),
-                ),
-            );
-//			let key = ecdsa::Pair::from_string(&format!("//{}", "Eve"), None).expect("static values are valid; qed");
-
-            let signature:Signature = raw_payload.using_encoded(|payload|
-                {
-                    let message = sp_io::hashing::blake2_256(&payload[..]);
-                    let url = self.tss_url();
-                    let str_url = core::str::from_utf8(&url).unwrap();
-                    let sig:Signature = match sign_by_tss(message.to_vec(),str_url,tss_gen_pubkey){
-                        Ok(sig) => { ecdsa::Signature::from_slice(&sig).into() },
-                        Err(e) => {
-                            match tss_error(e){
-                                TssError::SignUp() => {}, // do nothing
-                                _ => {  recover();  }, // retry
-                            }
-                            ecdsa::Signature::default().into()
-                        },
-                    };
-                    sig
-                });
-            if signature == ecdsa::Signature::default().into(){
-                return;
-            }
-            let (function, extra, _) = raw_payload.deconstruct();
-
-            let extrinsic =
-                UncheckedExtrinsic::new_signed(function, local_id.into(), signature.into(), extra);
-            let xt: TransactionFor<A> = Decode::decode(&mut &extrinsic.encode()[..]).unwrap();
-            let source = sp_runtime::transaction_validity::TransactionSource::External;
-            let result2 = self.tx_pool.submit_one(&at, source, xt);
-            std::thread::spawn(|| {
-                let mut rt = tokio::runtime::Runtime::new().unwrap();
-                let res = rt.block_on(result2);
-                info!("==========FileCoin Deposit========== {:?}", res);
-            });
-        }
-    }
 }
@@ -0,0 +1,159 @@
+//! Fetches F3 finality certificates and converts them for `pallet-fc-relay`.
+//!
+//! Certificates come from Lotus, or from a directory of recorded certificates
+//! named `<instance>.json` so the relay can be driven without a node.
+
+use std::{fs, io, path::PathBuf};
+
+use num_traits::cast::ToPrimitive;
+use lotus_api_forest::{Http, api::F3Api, error::Error as LotusError, types::f3 as json};
+use pallet_fc_relay::f3::{ECTipset, FinalityCertificate, PowerEntry, PowerTableDelta};
+
+#[derive(Debug)]
+pub enum F3Error {
+    Lotus(LotusError),
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// The certificate does not fit the relay's types.
+    Invalid(&'static str),
+}
+
+impl From<LotusError> for F3Error {
+    fn from(e: LotusError) -> Self {
+        F3Error::Lotus(e)
+    }
+}
+
+impl From<io::Error> for F3Error {
+    fn from(e: io::Error) -> Self {
+        F3Error::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for F3Error {
+    fn from(e: serde_json::Error) -> Self {
+        F3Error::Json(e)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum CertificateSource {
+    Lotus,
+    Recorded(PathBuf),
+}
+
+impl CertificateSource {
+    /// The certificate of `instance`, `None` if it has not been issued yet.
+    pub async fn certificate(&self, http: &Http, instance: u64) -> Result<Option<FinalityCertificate>, F3Error> {
+        let cert = match self {
+            CertificateSource::Lotus => {
+                if http.f3_get_latest_certificate().await?.gpbft_instance < instance {
+                    return Ok(None);
+                }
+                http.f3_get_certificate(instance).await?
+            },
+            CertificateSource::Recorded(dir) => {
+                let path = dir.join(format!("{}.json", instance));
+                if !path.exists() {
+                    return Ok(None);
+                }
+                serde_json::from_str(&fs::read_to_string(path)?)?
+            },
+        };
+        convert(&cert).map(Some)
+    }
+}
+
+/// Converts a certificate from its Lotus JSON form.
+pub fn convert(cert: &json::FinalityCertificate) -> Result<FinalityCertificate, F3Error> {
+    let ec_chain = cert.ec_chain.iter()
+        .map(|tipset| Ok(ECTipset {
+            epoch: tipset.epoch.to_u64().ok_or(F3Error::Invalid("negative epoch"))?,
+            key: tipset.key.iter().flat_map(|cid| cid.to_bytes()).collect(),
+            power_table: tipset.power_table.to_bytes(),
+            commitments: tipset.commitments,
+        }))
+        .collect::<Result<Vec<_>, F3Error>>()?;
+    let power_table_delta = cert.power_table_delta.iter().flatten()
+        .map(|delta| Ok(PowerTableDelta {
+            participant: delta.participant_id,
+            power_delta: delta.power_delta.to_i128().ok_or(F3Error::Invalid("power delta out of range"))?,
+            signing_key: delta.signing_key.clone(),
+        }))
+        .collect::<Result<Vec<_>, F3Error>>()?;
+
+    Ok(FinalityCertificate {
+        instance: cert.gpbft_instance,
+        ec_chain,
+        supplemental_commitments: cert.supplemental_data.commitments,
+        supplemental_power_table: cert.supplemental_data.power_table.to_bytes(),
+        signers: signer_indices(&cert.signers)?,
+        signature: cert.signature.clone(),
+        power_table_delta,
+    })
+}
+
+/// Converts a power table from its Lotus JSON form, for `set_f3_power_table`.
+pub fn convert_power_table(table: &[json::PowerEntry]) -> Result<Vec<PowerEntry>, F3Error> {
+    table.iter()
+        .map(|entry| Ok(PowerEntry {
+            id: entry.id,
+            power: entry.power.to_u128().ok_or(F3Error::Invalid("power out of range"))?,
+            public_key: entry.pub_key.clone(),
+        }))
+        .collect()
+}
+
+/// Expands the run lengths of an RLE+ bitfield, the first run being unset bits.
+fn signer_indices(runs: &[u64]) -> Result<Vec<u32>, F3Error> {
+    let mut indices = Vec::new();
+    let mut position = 0u64;
+    for (i, run) in runs.iter().enumerate() {
+        let end = position.checked_add(*run)
+            .filter(|end| *end <= u32::MAX as u64 + 1)
+            .ok_or(F3Error::Invalid("signers out of range"))?;
+        if i % 2 == 1 {
+            indices.extend((position..end).map(|index| index as u32));
+        }
+        position = end;
+    }
+    Ok(indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::runtime::Runtime;
+
+    fn recorded() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("res/f3")
+    }
+
+    // The certificate and power table under `res/f3` are signed by made up
+    // participants, they check the conversion and BDN aggregation end to end
+    // but were not recorded from a Lotus node.
+    #[test]
+    fn verifies_stored_certificate() {
+        let table: Vec<json::PowerEntry> =
+            serde_json::from_str(&fs::read_to_string(recorded().join("power_table_0.json")).unwrap()).unwrap();
+        let table = convert_power_table(&table).unwrap();
+
+        let mut rt = Runtime::new().unwrap();
+        let http = Http::new("http://127.0.0.1:1234/rpc/v0");
+        let source = CertificateSource::Recorded(recorded());
+        let cert = rt.block_on(source.certificate(&http, 0)).unwrap().unwrap();
+        assert_eq!(cert.signers, vec![0, 1, 2]);
+        assert_eq!(cert.head().unwrap().epoch, 12);
+
+        let next = pallet_fc_relay::f3::verify_certificate(b"filecoin", &table, &cert).unwrap();
+        assert_eq!(next.len(), 4);
+        assert!(rt.block_on(source.certificate(&http, 1)).unwrap().is_none());
+    }
+
+    #[test]
+    fn expands_signer_runs() {
+        assert_eq!(signer_indices(&[0, 3]).unwrap(), vec![0, 1, 2]);
+        assert_eq!(signer_indices(&[1, 2, 3, 1]).unwrap(), vec![1, 2, 6]);
+        assert!(signer_indices(&[]).unwrap().is_empty());
+    }
+}
@@ -4,33 +4,44 @@
 extern crate lazy_static;
 
 use parking_lot::Mutex;
-use std::{sync::Arc, thread, time, marker::PhantomData, collections::HashMap};
+use std::{sync::Arc, thread, time, marker::PhantomData, collections::HashMap, path::PathBuf};
 use futures::{channel::mpsc, prelude::*};
 use tokio::runtime::Runtime;
 
-use num_traits::cast::ToPrimitive;
-use bridge::{PacketNonce, SuperviseClient, TokenType, TxMessage, TxSender, TxType, ChainState};
+use bridge::{relayer_key, JobQueue, LotusConfig, PacketNonce, RelayerKeyError, SuperviseClient, TxMessage, TxSender, TxType, ChainState};
 use sp_transaction_pool::{TransactionPool};
-use filecoin_bridge_runtime::apis::VendorApi;
+use filecoin_bridge_runtime::{apis::VendorApi, AccountId, pallet_tss::memo::Memo};
 use sc_block_builder::BlockBuilderProvider;
-use sc_client_api::{backend, BlockchainEvents};
+use sc_client_api::{backend, BlockchainEvents, StorageProvider};
+use sc_keystore::KeyStorePtr;
 use sp_api::{CallApiAt, ProvideRuntimeApi};
 use sp_blockchain::HeaderBackend;
-use sp_core::{sr25519, Pair};
-use sp_runtime::{generic::{BlockId}, traits::{Block as BlockT}};
+use sp_runtime::traits::{Block as BlockT};
 
-use lotus_api_forest::{self, Http as filecoin_http, api::ChainApi};
+use lotus_api_forest::{self, Http as filecoin_http, api::{ChainApi, StateApi}, types::HeadChangeType};
 use interpreter::{self, BlockMessages};
-use forest_blocks::{self, Tipset, tipset::tipset_json::TipsetJson};
-use forest_message::{self, UnsignedMessage};
+use forest_blocks::{self, Tipset, TipsetKeys};
 use forest_address::{self, Address};
-use forest_encoding::Cbor;
-use rpc::BlockMessages as forest_BlockMessages;
 use serde_json::*;
 use serde::*;
 use std::collections::BTreeSet;
 
 use std::time::Duration;
+
+mod f3;
+mod scanner;
+mod watcher;
+pub use bridge::{build_message_proof, raw_headers, ProofBuildError, ScanCursor};
+pub use f3::{CertificateSource, F3Error};
+pub use scanner::{DepositScanner, ScannedTipset, DEFAULT_CONFIRMATIONS};
+pub use watcher::{ChainSource, ChainWatcher};
+
+/// Upper bound of tipsets or certificates relayed per poll, so that catching up does not flood the pool.
+const MAX_RELAYED_PER_ROUND: u64 = 20;
+
+/// Polls waited for relayed items to be imported before they are sent again.
+const RESEND_AFTER_ROUNDS: u32 = 6;
+
 lazy_static! {
     pub static ref STORE_LIST: Mutex<Vec<&'static str>> = Mutex::new(vec!["test"]);
 }
@@ -40,33 +51,19 @@ type FCMessageCidBytes = Vec<u8>;
 type SubTargetAccountId = Vec<u8>;
 type FCFromAddress = Vec<u8>;
 
-type StreamData<V> = (SubTargetAccountId, V, FCFromAddress);
-type MessageStreamR<V> = mpsc::UnboundedReceiver<StreamData<V>>;
-type MessageStreamS<V> = mpsc::UnboundedSender<StreamData<V>>;
+type MessageStreamR = mpsc::UnboundedReceiver<TxType>;
+type MessageStreamS = mpsc::UnboundedSender<TxType>;
 type DepositData<V> = (SubTargetAccountId, V, FCFromAddress);
-type ExtractMessage<V> = (FCMessageCidBytes, Address, SubTargetAccountId, V, FCFromAddress);
-
-fn extract_message(message: UnsignedMessage) -> ExtractMessage<FCValue> {
-
-    let revice_address = message.to.clone();
-    let from_address = message.from.to_bytes();
-    let deposit_boolid = message.params.bytes().to_vec();
-    let deposit_amount = message.value.clone().to_u128().unwrap();
-    let cid = message.cid().unwrap().to_bytes();
-    println!("extract_message deposit_boolid={:?} deposit_amount={:?}",deposit_boolid,deposit_amount);
 
-    (cid, revice_address, deposit_boolid, deposit_amount, from_address)
-}
-
-pub fn get_fc_message_parse_channel() -> (MessageStreamS<FCValue>, MessageStreamR<FCValue>) {
-    let (sender, reciver) = mpsc::unbounded::<(Vec<u8>, FCValue, Vec<u8>)>();
+pub fn get_fc_message_parse_channel() -> (MessageStreamS, MessageStreamR) {
+    let (sender, reciver) = mpsc::unbounded::<TxType>();
     (sender, reciver)
 }
 
 #[derive(Debug)]
 pub struct FCMessageForward<V, B> {
     pub spv: Arc<V>,
-    pub reciver: MessageStreamR<FCValue>,
+    pub reciver: MessageStreamR,
     pub a: std::marker::PhantomData<B>,
 }
 
@@ -75,7 +72,7 @@ where
     V: SuperviseClient<B> + Send + Sync + 'static,
     B: BlockT,
 {
-    pub fn new(spv: Arc<V>, rec: MessageStreamR<FCValue>) -> Self {
+    pub fn new(spv: Arc<V>, rec: MessageStreamR) -> Self {
         FCMessageForward {
             spv: spv,
             reciver: rec,
@@ -90,13 +87,9 @@ where
     fn start_sign_push_fc_message(self) -> impl Future<Output = ()> + 'static {
         let spv = self.spv;
         let stream = {
-            self.reciver.for_each(move |(who, value, from)| {
-                spv.submit_fc_transfer_tss(TxMessage::new(TxType::FCDeposit(
-                    who,
-                    TokenType::FC,
-                    value,
-                    from,
-                )));
+            // Headers and deposits are verified on chain, any key may submit them.
+            self.reciver.for_each(move |tx| {
+                spv.submit(TxMessage::new(tx));
                 futures::future::ready(())
             })
         };
@@ -106,11 +99,37 @@ where
 
 type FcPubkeySender = mpsc::UnboundedReceiver<Vec<u8>>;
 
+#[derive(Clone, Debug)]
+pub struct FcServiceConfig {
+    /// Epochs a deposit has to be buried under the Lotus head before it is forwarded.
+    pub confirmations: u64,
+    /// File the last scanned epoch is kept in, nothing is persisted without one.
+    pub cursor_path: Option<PathBuf>,
+    pub lotus: LotusConfig,
+    /// Where the F3 finality certificates are read from.
+    pub certificates: CertificateSource,
+}
+
+impl Default for FcServiceConfig {
+    fn default() -> Self {
+        FcServiceConfig {
+            confirmations: DEFAULT_CONFIRMATIONS,
+            cursor_path: None,
+            lotus: LotusConfig::default(),
+            certificates: CertificateSource::Lotus,
+        }
+    }
+}
+
 pub fn start_fc_service<A, B, C, Block>(
     client: Arc<C>,
     pool: Arc<A>,
+    keystore: KeyStorePtr,
+    nonces: Arc<Mutex<PacketNonce<Block::Hash, AccountId>>>,
+    jobs: Arc<JobQueue>,
     mut reciver: FcPubkeySender,
-) -> impl Future<Output = ()> + 'static
+    config: FcServiceConfig,
+) -> Result<impl Future<Output = ()> + 'static, RelayerKeyError>
 where
     A: TransactionPool<Block = Block> + 'static,
     Block: BlockT,
@@ -120,26 +139,21 @@ where
         + ProvideRuntimeApi<Block>
         + BlockchainEvents<Block>
         + CallApiAt<Block>
+        + StorageProvider<Block, B>
         + Send
         + Sync
         + 'static,
     C::Api: VendorApi<Block>,
     Block::Hash: Into<sp_core::H256>,
 {
-    let key = sr25519::Pair::from_string(&format!("//{}", "Dave"), None)
-        .expect("static values are valid; qed");
-
-    let info = client.info();
-    let at = BlockId::Hash(info.best_hash);
+    let key = relayer_key(&keystore)?;
 
     let tx_sender = TxSender::new(
         client.clone(),
         pool,
         key,
-        Arc::new(Mutex::new(PacketNonce {
-            nonce: 0,
-            last_block: at,
-        })),
+        nonces,
+        jobs,
     );
 
     let tx_sender_arc = Arc::new(tx_sender);
@@ -151,12 +165,13 @@ where
         FCMessageForward::new(tx_sender_arc, fc_parse_recvier);
 
     // to fetch Message from FileCoin & send to fc_sender
-    fc_message_fetch_parse(fc_parse_sender, reciver,ChainState::new(client));
+    fc_message_fetch_parse(fc_parse_sender, reciver,ChainState::new(client), config);
     // to revice & parse FileCoin Message and submit to filecoin
-    fc_message_forward.start_sign_push_fc_message()
+    Ok(fc_message_forward.start_sign_push_fc_message())
 }
 
-pub fn fc_message_fetch_parse<Block,B,C>(sender: MessageStreamS<FCValue>, _reciver: FcPubkeySender, state: ChainState<Block,B,C>)
+pub fn fc_message_fetch_parse<Block,B,C>(sender: MessageStreamS, _reciver: FcPubkeySender, state: ChainState<Block,B,C>,
+                                        config: FcServiceConfig)
     where
         Block: BlockT,
         B: backend::Backend<Block> + Send + Sync + 'static,
@@ -165,7 +180,14 @@ pub fn fc_message_fetch_parse<Block,B,C>(sender: MessageStreamS<FCValue>, _reciv
         C::Api: VendorApi<Block>,
 {
     thread::spawn(move || {
-        let mut height = 0u64;
+        let cursor = ScanCursor::new(config.cursor_path.clone());
+        // Epochs above the cursor are scanned again after a restart.
+        let mut watcher = ChainWatcher::new(config.confirmations, cursor.load());
+        let mut tipsets = Relayed::default();
+        let certificates = config.certificates.clone();
+        let mut certified = Relayed::default();
+        // deposits waiting for the tipset holding their receipts to become final in the relay
+        let mut pending: Vec<(Tipset, FCMessageCidBytes)> = Vec::new();
 
         let mut recv_addr: Vec<u8> = Vec::new();
         loop {
@@ -178,51 +200,291 @@ pub fn fc_message_fetch_parse<Block,B,C>(sender: MessageStreamS<FCValue>, _reciv
                 break;
             }
         }
-        let addr = Address::new_secp256k1(&recv_addr).unwrap();
-        println!("token recvice address in Filecoin is {}",addr );
+        let mut addr = BridgeAddress::new(config.lotus.address(Address::new_secp256k1(&recv_addr).unwrap()));
+        println!("token recvice address in Filecoin is {}",addr.robust );
+        // polls until the ID address is submitted again while it is not recorded
+        let mut id_wait = 0u32;
 
         loop {
             thread::sleep(time::Duration::new(1, 0));
             let mut rt = Runtime::new().unwrap();
-            let http = filecoin_http::new("http://127.0.0.1:1234/rpc/v0");
-            let ret_json: TipsetJson = rt.block_on(http.chain_head()).unwrap();
-            let ret: Tipset = ret_json.into();
+            let http = config.lotus.http();
+            addr.resolve(&mut rt, &http);
+            if let Some(id) = addr.id.as_ref() {
+                let recorded = state.fc_bridge_id_address();
+                if recorded.is_empty() {
+                    if id_wait == 0 {
+                        sender.unbounded_send(TxType::FcBridgeIdAddress(id.to_bytes())).ok();
+                        id_wait = RESEND_AFTER_ROUNDS;
+                    } else {
+                        id_wait -= 1;
+                    }
+                } else if recorded != id.to_bytes() {
+                    println!("bridge ID address recorded on chain differs from {}", id);
+                }
+            }
 
-            let new_height = ret.epoch() as u64;
-            if new_height == height {
-                continue;
+            let previous = watcher.head().map(|head| head.id());
+            let confirmed = {
+                let mut source = LotusSource { rt: &mut rt, http: &http, addr: &addr };
+                match watcher.poll(&mut source) {
+                    Ok(confirmed) => confirmed,
+                    Err(e) => {
+                        println!("failed to scan the filecoin chain: {:?}", e);
+                        continue;
+                    },
+                }
+            };
+            let ret = match watcher.head() {
+                Some(head) if Some(head.id()) != previous => head.clone(),
+                _ => continue,
+            };
+            pending.extend(confirmed);
+
+            // Always from the height imported on chain, submissions may have been rejected.
+            let best = state.fc_best_height();
+            if best > 0 && tipsets.resend(best) {
+                tipsets.sent = relay_tipsets(&mut rt, &http, &sender, &ret, best);
             }
-            height = new_height;
-            let mut message_set = HashMap::<Vec<u8>, DepositData<FCValue>>::new();
-            let mut message_cid_set = BTreeSet::new();
-            let cids = ret.cids();
-            for cid in cids {
-                println!("[filecoin block] cids = {:?} height={:?}", cid, height);
-                let block_messages: forest_BlockMessages =
-                    rt.block_on(http.chain_get_block_messages(&cid)).unwrap();
-                let signed_bls_messages = block_messages.bls_msg.clone();
-                for message in signed_bls_messages {
-                    let (cid, revice_addr, who, val, from) = extract_message(message.clone());
-                    if message_cid_set.contains(&cid){}else{
-                        if revice_addr == Address::new_secp256k1(&recv_addr).unwrap() {
-                            message_cid_set.insert(cid.clone());
-                            message_set.insert(cid, (who, val, from));
-                        }
-                    }
+            // Only once root has set up F3 in the relay.
+            if let Some(instance) = state.fc_f3_instance() {
+                if certified.resend(instance) {
+                    certified.sent = relay_certificates(&mut rt, &http, &certificates, &sender, instance);
                 }
             }
-            for (_cid, (who, val, from)) in message_set {
-                sender.unbounded_send((who, val, from));
+
+            let finalized = state.fc_deposit_final_height();
+            pending.retain(|(tipset, cid)| {
+                match prove_when_final(&mut rt, &http, &ret, tipset, cid, finalized) {
+                    Ok(Some(proof)) => {
+                        sender.unbounded_send(TxType::FCDeposit(proof)).ok();
+                        false
+                    },
+                    Ok(None) => true,
+                    Err(ProofBuildError::Reorged) => false,
+                    Err(e) => {
+                        println!("failed to prove deposit {:?}: {:?}", cid, e);
+                        true
+                    },
+                }
+            });
+
+            // Everything below the oldest deposit still in flight is done with.
+            let done = watcher.oldest_unconfirmed().into_iter()
+                .chain(pending.iter().map(|(tipset, _)| tipset.epoch() as u64))
+                .min()
+                .map_or(ret.epoch() as u64, |oldest| oldest.saturating_sub(1));
+            if let Err(e) = cursor.store(done) {
+                println!("failed to persist the scan cursor: {:?}", e);
             }
         }
     });
 }
 
+/// How far the relay was fed, so that what is still in flight is not sent twice.
+#[derive(Default)]
+struct Relayed {
+    /// Position the last submissions lead to.
+    sent: u64,
+    /// Polls since then that did not see it imported.
+    waited: u32,
+}
+
+impl Relayed {
+    /// Whether to relay from `imported`, the position on chain, which is the
+    /// case once the last submissions landed or they had time to and did not.
+    fn resend(&mut self, imported: u64) -> bool {
+        if imported >= self.sent || self.waited >= RESEND_AFTER_ROUNDS {
+            self.waited = 0;
+            true
+        } else {
+            self.waited += 1;
+            false
+        }
+    }
+}
+
+/// The forms of the bridge address deposits may be sent to.
+struct BridgeAddress {
+    /// The secp256k1 address of the TSS key.
+    robust: Address,
+    /// Assigned once the first transfer to the bridge created its account actor.
+    id: Option<Address>,
+}
+
+impl BridgeAddress {
+    fn new(robust: Address) -> Self {
+        BridgeAddress { robust, id: None }
+    }
+
+    /// Compared without the network, which the addresses read from messages do not carry.
+    fn matches(&self, to: &Address) -> bool {
+        to.to_bytes() == self.robust.to_bytes() || self.id.as_ref().map(|id| id.to_bytes()) == Some(to.to_bytes())
+    }
+
+    /// Looks up the ID address until Lotus knows it.
+    fn resolve(&mut self, rt: &mut Runtime, http: &filecoin_http) {
+        if self.id.is_some() {
+            return;
+        }
+        let head = TipsetKeys::new(Vec::new());
+        let id = match rt.block_on(http.state_lookup_id(&self.robust, &head)) {
+            Ok(id) => id,
+            // No actor yet.
+            Err(_) => return,
+        };
+        // Only trusted if it maps back to the TSS key.
+        match rt.block_on(http.state_account_key(&id, &head)) {
+            Ok(robust) if robust.to_bytes() == self.robust.to_bytes() => {
+                println!("bridge ID address in Filecoin is {}", id);
+                self.id = Some(id);
+            },
+            Ok(robust) => println!("ID address {} belongs to {}, not to the bridge", id, robust),
+            Err(e) => println!("failed to look up the account key of {}: {:?}", id, e),
+        }
+    }
+}
+
+/// Reads the chain from the local Lotus node.
+struct LotusSource<'a> {
+    rt: &'a mut Runtime,
+    http: &'a filecoin_http,
+    /// Bridge address deposits are sent to.
+    addr: &'a BridgeAddress,
+}
+
+impl<'a> ChainSource for LotusSource<'a> {
+    type Tipset = Tipset;
+    type Error = lotus_api_forest::error::Error;
+
+    fn head(&mut self) -> Result<Tipset, Self::Error> {
+        Ok(self.rt.block_on(self.http.chain_head())?.into())
+    }
+
+    fn tipset_by_height(&mut self, height: u64, head: &Tipset) -> Result<Tipset, Self::Error> {
+        Ok(self.rt.block_on(self.http.chain_get_tipset_by_height(height as i64, head.key()))?.into())
+    }
+
+    fn reverted(&mut self, from: &Tipset, to: &Tipset) -> Result<Vec<Tipset>, Self::Error> {
+        let changes = self.rt.block_on(self.http.chain_get_path(from.key(), to.key()))?;
+        Ok(changes.into_iter()
+            .filter(|change| matches!(change.r#type, HeadChangeType::Revert))
+            .map(|change| change.val.into())
+            .collect())
+    }
+
+    fn deposits(&mut self, tipset: &Tipset) -> Result<Vec<Vec<u8>>, Self::Error> {
+        scan_deposits(self.rt, self.http, tipset, self.addr)
+    }
+}
+
+/// CIDs of the messages executed in the parent of `tipset` that sent FIL to
+/// the bridge address and succeeded.
+///
+/// Lotus lists every message executed in the parent once, BLS and secp256k1
+/// alike, with the receipts committed to by `tipset` in the same order.
+fn scan_deposits(rt: &mut Runtime, http: &filecoin_http, tipset: &Tipset, addr: &BridgeAddress)
+    -> Result<Vec<FCMessageCidBytes>, lotus_api_forest::error::Error>
+{
+    let block = &tipset.cids()[0];
+    let messages = rt.block_on(http.chain_get_parent_messages(block))?;
+    let receipts = rt.block_on(http.chain_get_parent_receipts(block))?;
+    if messages.len() != receipts.len() {
+        println!("{} parent messages but {} receipts at {}", messages.len(), receipts.len(), tipset.epoch());
+        return Err(lotus_api_forest::error::Error::Internal);
+    }
+
+    let mut deposits = BTreeSet::new();
+    for (parent, receipt) in messages.into_iter().zip(receipts) {
+        if !addr.matches(&parent.message.to) {
+            continue;
+        }
+        if receipt.exit_code != 0 {
+            println!("skipping deposit {} that failed with exit code {}", parent.cid, receipt.exit_code);
+            continue;
+        }
+        // Still proven, the runtime records it as unattributed instead of crediting it.
+        if let Err(reason) = Memo::<AccountId>::parse(parent.message.params.bytes()) {
+            println!("deposit {} carries no valid memo: {:?}", parent.cid, reason);
+        }
+        deposits.insert(parent.cid.to_bytes());
+    }
+    Ok(deposits.into_iter().collect())
+}
+
+/// Submits the tipsets above `relayed` up to `head` and returns the new relayed height.
+fn relay_tipsets(rt: &mut Runtime, http: &filecoin_http, sender: &MessageStreamS, head: &Tipset, relayed: u64) -> u64 {
+    let target = (head.epoch() as u64).min(relayed + MAX_RELAYED_PER_ROUND);
+    for h in relayed + 1..=target {
+        let tipset: Tipset = match rt.block_on(http.chain_get_tipset_by_height(h as i64, head.key())) {
+            Ok(json) => json.into(),
+            Err(_) => return h - 1,
+        };
+        // null round
+        if tipset.epoch() as u64 != h {
+            continue;
+        }
+        match rt.block_on(raw_headers(http, &tipset)) {
+            Ok(headers) => { sender.unbounded_send(TxType::FcTipset(headers)).ok(); },
+            Err(_) => return h - 1,
+        }
+    }
+    target
+}
+
+/// Submits the certificates issued from `instance` on and returns the next instance to submit.
+fn relay_certificates(rt: &mut Runtime, http: &filecoin_http, source: &CertificateSource, sender: &MessageStreamS, instance: u64) -> u64 {
+    for next in instance..instance + MAX_RELAYED_PER_ROUND {
+        match rt.block_on(source.certificate(http, next)) {
+            Ok(Some(cert)) => { sender.unbounded_send(TxType::FcF3Certificate(cert)).ok(); },
+            Ok(None) => return next,
+            Err(e) => {
+                println!("failed to fetch f3 certificate {}: {:?}", next, e);
+                return next;
+            },
+        }
+    }
+    instance + MAX_RELAYED_PER_ROUND
+}
+
+/// Builds the proof of a deposit found in the parent messages of `child` once
+/// `child` is final, which with F3 means covered by a certificate.
+///
+/// Returns `None` while that is not the case yet.
+fn prove_when_final(rt: &mut Runtime, http: &filecoin_http, head: &Tipset, child: &Tipset, cid: &[u8], finalized: u64)
+    -> Result<Option<pallet_fc_relay::MessageProof>, ProofBuildError>
+{
+    if child.epoch() as u64 > finalized {
+        return Ok(None);
+    }
+    let canonical: Tipset = rt.block_on(http.chain_get_tipset_by_height(child.epoch(), head.key()))?.into();
+    if canonical.key() != child.key() {
+        // The tipset is no longer on the chain Lotus follows.
+        return Err(ProofBuildError::Reorged);
+    }
+    let tipset: Tipset = rt.block_on(http.chain_get_tipset(child.parents()))?.into();
+    rt.block_on(build_message_proof(http, &tipset, child, cid)).map(Some)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use rpc::BlockMessages as forest_BlockMessages;
     use lotus_api_forest::api::MpoolApi;
+    #[test]
+    fn relays_again_from_the_imported_position() {
+        let mut relayed = Relayed::default();
+        assert!(relayed.resend(10));
+        relayed.sent = 30;
+        for _ in 0..RESEND_AFTER_ROUNDS {
+            assert!(!relayed.resend(10));
+        }
+        // Nothing landed, the same heights are sent again.
+        assert!(relayed.resend(10));
+        assert!(!relayed.resend(25));
+        assert!(relayed.resend(30));
+    }
+
     #[test]
     fn test() {
         //cargo test --color=always --package fc-signer --lib tests::test -- --exact --nocapture
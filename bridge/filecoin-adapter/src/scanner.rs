@@ -0,0 +1,130 @@
+//! Tracks candidate deposits until they are buried deep enough to be forwarded.
+//!
+//! Deposits are kept by the tipset committing to their receipts. When a head
+//! change reverts that tipset its deposits are dropped; should the messages be
+//! executed again on another branch they are picked up when it is applied.
+
+use std::collections::HashMap;
+
+use sp_core::H256;
+
+/// Epochs a deposit has to be buried under the head by default, Filecoin's finality.
+pub const DEFAULT_CONFIRMATIONS: u64 = 900;
+
+/// What the scanner needs to know of a tipset.
+pub trait ScannedTipset: Clone {
+    /// Hash of the tipset key, as the relay identifies tipsets.
+    fn id(&self) -> H256;
+    fn epoch(&self) -> u64;
+}
+
+impl ScannedTipset for forest_blocks::Tipset {
+    fn id(&self) -> H256 {
+        let key: Vec<Vec<u8>> = self.cids().iter().map(|cid| cid.to_bytes()).collect();
+        pallet_fc_relay::tipset_hash(&key)
+    }
+
+    fn epoch(&self) -> u64 {
+        forest_blocks::Tipset::epoch(self) as u64
+    }
+}
+
+pub struct DepositScanner<T> {
+    confirmations: u64,
+    /// Candidate deposits by the tipset committing to their receipts.
+    candidates: HashMap<H256, (T, Vec<Vec<u8>>)>,
+}
+
+impl<T: ScannedTipset> DepositScanner<T> {
+    pub fn new(confirmations: u64) -> Self {
+        DepositScanner {
+            confirmations,
+            candidates: HashMap::new(),
+        }
+    }
+
+    /// Records the deposits of a tipset that became part of the chain.
+    pub fn apply(&mut self, tipset: T, deposits: Vec<Vec<u8>>) {
+        if !deposits.is_empty() {
+            self.candidates.insert(tipset.id(), (tipset, deposits));
+        }
+    }
+
+    /// Drops the deposits of a tipset that left the chain and returns how many there were.
+    pub fn revert(&mut self, tipset: &T) -> usize {
+        self.candidates.remove(&tipset.id()).map_or(0, |(_, deposits)| deposits.len())
+    }
+
+    /// Removes and returns the deposits buried at least `confirmations` epochs under `head`.
+    pub fn confirmed(&mut self, head: u64) -> Vec<(T, Vec<u8>)> {
+        let depth = self.confirmations;
+        let ready: Vec<H256> = self.candidates.iter()
+            .filter(|(_, (tipset, _))| tipset.epoch() + depth <= head)
+            .map(|(id, _)| *id)
+            .collect();
+
+        let mut out: Vec<(T, Vec<u8>)> = Vec::new();
+        for id in ready {
+            if let Some((tipset, deposits)) = self.candidates.remove(&id) {
+                out.extend(deposits.into_iter().map(|cid| (tipset.clone(), cid)));
+            }
+        }
+        out.sort_by_key(|(tipset, _)| tipset.epoch());
+        out
+    }
+
+    /// Epoch of the oldest tipset with deposits still waiting.
+    pub fn oldest(&self) -> Option<u64> {
+        self.candidates.values().map(|(tipset, _)| tipset.epoch()).min()
+    }
+
+    pub fn pending(&self) -> usize {
+        self.candidates.values().map(|(_, deposits)| deposits.len()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct MockTipset(u64, u8);
+
+    impl ScannedTipset for MockTipset {
+        fn id(&self) -> H256 {
+            H256::repeat_byte(self.1)
+        }
+
+        fn epoch(&self) -> u64 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn forwards_buried_deposits_only() {
+        let mut scanner = DepositScanner::new(3);
+        scanner.apply(MockTipset(10, 1), vec![b"a".to_vec()]);
+        scanner.apply(MockTipset(11, 2), vec![]);
+        scanner.apply(MockTipset(12, 3), vec![b"b".to_vec(), b"c".to_vec()]);
+
+        assert!(scanner.confirmed(12).is_empty());
+        assert_eq!(scanner.confirmed(13), vec![(MockTipset(10, 1), b"a".to_vec())]);
+        assert!(scanner.confirmed(14).is_empty());
+        assert_eq!(scanner.confirmed(15).len(), 2);
+        assert_eq!(scanner.pending(), 0);
+    }
+
+    #[test]
+    fn drops_reverted_deposits() {
+        let mut scanner = DepositScanner::new(3);
+        scanner.apply(MockTipset(10, 1), vec![b"a".to_vec()]);
+        scanner.apply(MockTipset(11, 2), vec![b"b".to_vec()]);
+
+        // The tipset at 11 is replaced by a fork that includes the deposit again at 12.
+        assert_eq!(scanner.revert(&MockTipset(11, 2)), 1);
+        scanner.apply(MockTipset(12, 4), vec![b"b".to_vec()]);
+
+        assert_eq!(scanner.confirmed(14), vec![(MockTipset(10, 1), b"a".to_vec())]);
+        assert_eq!(scanner.confirmed(15), vec![(MockTipset(12, 4), b"b".to_vec())]);
+    }
+}
@@ -0,0 +1,227 @@
+//! Walks the Filecoin chain epoch by epoch and feeds the deposit scanner.
+//!
+//! Every epoch between the last scanned head and the new one is visited, so
+//! nothing is missed when several tipsets land between two polls. Null rounds
+//! have no tipset and are skipped. Tipsets reverted by a reorg are dropped
+//! before the new branch is walked from the fork point.
+
+use crate::scanner::{DepositScanner, ScannedTipset};
+
+/// Where the watcher reads the chain from.
+pub trait ChainSource {
+    type Tipset: ScannedTipset;
+    type Error: std::fmt::Debug;
+
+    fn head(&mut self) -> Result<Self::Tipset, Self::Error>;
+
+    /// The tipset at `height` on the chain of `head`, or the one below it if
+    /// `height` is a null round.
+    fn tipset_by_height(&mut self, height: u64, head: &Self::Tipset) -> Result<Self::Tipset, Self::Error>;
+
+    /// Tipsets reverted when moving the head from `from` to `to`, newest first.
+    fn reverted(&mut self, from: &Self::Tipset, to: &Self::Tipset) -> Result<Vec<Self::Tipset>, Self::Error>;
+
+    /// Successful deposits to the bridge whose receipts `tipset` commits to,
+    /// that is the ones executed in its parent.
+    fn deposits(&mut self, tipset: &Self::Tipset) -> Result<Vec<Vec<u8>>, Self::Error>;
+}
+
+pub struct ChainWatcher<T> {
+    scanner: DepositScanner<T>,
+    /// The last head whose epochs have all been scanned.
+    head: Option<T>,
+    /// Epoch to resume scanning above when there is no head yet.
+    resume_from: Option<u64>,
+}
+
+impl<T: ScannedTipset> ChainWatcher<T> {
+    pub fn new(confirmations: u64, resume_from: Option<u64>) -> Self {
+        ChainWatcher {
+            scanner: DepositScanner::new(confirmations),
+            head: None,
+            resume_from,
+        }
+    }
+
+    pub fn head(&self) -> Option<&T> {
+        self.head.as_ref()
+    }
+
+    /// Epoch of the oldest tipset with deposits that are not confirmed yet.
+    pub fn oldest_unconfirmed(&self) -> Option<u64> {
+        self.scanner.oldest()
+    }
+
+    /// Scans up to the source's head and returns the deposits that became confirmed.
+    ///
+    /// On error the head is kept, the epochs are visited again by the next poll.
+    pub fn poll<S: ChainSource<Tipset = T>>(&mut self, source: &mut S) -> Result<Vec<(T, Vec<u8>)>, S::Error> {
+        let head = source.head()?;
+        let from = match &self.head {
+            Some(prev) if prev.id() == head.id() => return Ok(Vec::new()),
+            Some(prev) => {
+                let mut from = prev.epoch();
+                for tipset in source.reverted(prev, &head)? {
+                    let dropped = self.scanner.revert(&tipset);
+                    if dropped > 0 {
+                        println!("dropped {} deposits of reverted tipset at {}", dropped, tipset.epoch());
+                    }
+                    from = from.min(tipset.epoch().saturating_sub(1));
+                }
+                from
+            },
+            None => match self.resume_from {
+                Some(from) => from,
+                // Without a cursor only the head is scanned.
+                None => head.epoch().saturating_sub(1),
+            },
+        };
+
+        for height in from + 1..=head.epoch() {
+            let tipset = if height == head.epoch() {
+                head.clone()
+            } else {
+                source.tipset_by_height(height, &head)?
+            };
+            if tipset.epoch() != height {
+                continue;
+            }
+            let deposits = source.deposits(&tipset)?;
+            self.scanner.apply(tipset, deposits);
+        }
+
+        let confirmed = self.scanner.confirmed(head.epoch());
+        self.head = Some(head);
+        Ok(confirmed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use sp_core::H256;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct MockTipset {
+        epoch: u64,
+        fork: u8,
+    }
+
+    impl ScannedTipset for MockTipset {
+        fn id(&self) -> H256 {
+            let mut id = [self.fork; 32];
+            id[..8].copy_from_slice(&self.epoch.to_le_bytes());
+            H256(id)
+        }
+
+        fn epoch(&self) -> u64 {
+            self.epoch
+        }
+    }
+
+    /// A chain with optional null rounds and a deposit in some tipsets.
+    #[derive(Default)]
+    struct MockChain {
+        tipsets: BTreeMap<u64, (MockTipset, Vec<Vec<u8>>)>,
+        /// Epochs requested through `tipset_by_height`.
+        visited: Vec<u64>,
+    }
+
+    impl MockChain {
+        fn push(&mut self, epoch: u64, fork: u8, deposit: Option<&[u8]>) {
+            let deposits = deposit.map(|d| vec![d.to_vec()]).unwrap_or_default();
+            self.tipsets.insert(epoch, (MockTipset { epoch, fork }, deposits));
+        }
+
+        /// Replaces everything from `epoch` on with a fork.
+        fn reorg(&mut self, epoch: u64) {
+            self.tipsets.split_off(&epoch);
+        }
+    }
+
+    impl ChainSource for MockChain {
+        type Tipset = MockTipset;
+        type Error = ();
+
+        fn head(&mut self) -> Result<MockTipset, ()> {
+            self.tipsets.values().last().map(|(t, _)| t.clone()).ok_or(())
+        }
+
+        fn tipset_by_height(&mut self, height: u64, _: &MockTipset) -> Result<MockTipset, ()> {
+            self.visited.push(height);
+            self.tipsets.range(..=height).last().map(|(_, (t, _))| t.clone()).ok_or(())
+        }
+
+        fn reverted(&mut self, from: &MockTipset, _: &MockTipset) -> Result<Vec<MockTipset>, ()> {
+            // Every tipset of the old branch above the last one still on the chain.
+            let mut reverted = Vec::new();
+            for epoch in (0..=from.epoch).rev() {
+                match self.tipsets.get(&epoch) {
+                    Some((t, _)) if t.fork == from.fork => break,
+                    _ => reverted.push(MockTipset { epoch, fork: from.fork }),
+                }
+            }
+            Ok(reverted)
+        }
+
+        fn deposits(&mut self, tipset: &MockTipset) -> Result<Vec<Vec<u8>>, ()> {
+            Ok(self.tipsets.get(&tipset.epoch).filter(|(t, _)| t == tipset).map(|(_, d)| d.clone()).unwrap_or_default())
+        }
+    }
+
+    fn cids(confirmed: Vec<(MockTipset, Vec<u8>)>) -> Vec<(u64, Vec<u8>)> {
+        confirmed.into_iter().map(|(t, cid)| (t.epoch, cid)).collect()
+    }
+
+    #[test]
+    fn scans_every_epoch_between_polls() {
+        let mut chain = MockChain::default();
+        chain.push(10, 0, None);
+        let mut watcher = ChainWatcher::new(2, None);
+        assert!(watcher.poll(&mut chain).unwrap().is_empty());
+
+        // Several epochs land between two polls, 12 is a null round.
+        chain.push(11, 0, Some(b"a"));
+        chain.push(13, 0, Some(b"b"));
+        chain.push(14, 0, None);
+        assert_eq!(cids(watcher.poll(&mut chain).unwrap()), vec![(11, b"a".to_vec())]);
+        assert_eq!(chain.visited, vec![11, 12, 13]);
+
+        chain.push(15, 0, None);
+        assert_eq!(cids(watcher.poll(&mut chain).unwrap()), vec![(13, b"b".to_vec())]);
+        assert_eq!(watcher.oldest_unconfirmed(), None);
+    }
+
+    #[test]
+    fn drops_deposits_of_reverted_tipsets() {
+        let mut chain = MockChain::default();
+        chain.push(10, 0, None);
+        let mut watcher = ChainWatcher::new(3, None);
+        watcher.poll(&mut chain).unwrap();
+
+        chain.push(11, 0, Some(b"a"));
+        chain.push(12, 0, Some(b"b"));
+        assert!(watcher.poll(&mut chain).unwrap().is_empty());
+
+        // 12 is orphaned, the fork has a null round at 12 and includes `b` again at 13.
+        chain.reorg(12);
+        chain.push(13, 1, Some(b"b"));
+        chain.push(14, 1, None);
+        assert_eq!(cids(watcher.poll(&mut chain).unwrap()), vec![(11, b"a".to_vec())]);
+
+        chain.push(16, 1, None);
+        assert_eq!(cids(watcher.poll(&mut chain).unwrap()), vec![(13, b"b".to_vec())]);
+    }
+
+    #[test]
+    fn resumes_from_cursor() {
+        let mut chain = MockChain::default();
+        for epoch in 5..=20 {
+            chain.push(epoch, 0, if epoch == 8 { Some(b"missed") } else { None });
+        }
+        let mut watcher = ChainWatcher::new(10, Some(6));
+        assert_eq!(cids(watcher.poll(&mut chain).unwrap()), vec![(8, b"missed".to_vec())]);
+        assert_eq!(chain.visited, (7..20).collect::<Vec<_>>());
+    }
+}
@@ -2,4 +2,4 @@ pub mod tsserror;
 pub mod tssstate;
 
 pub use tsserror::{TssError, tss_error};
-pub use tssstate::RetryAbort;
\ No newline at end of file
+pub use tssstate::{RetryAbort, retry_abort};
\ No newline at end of file
@@ -1,4 +1,23 @@
+use crate::tsserror::TssError;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum RetryAbort{
     Retry,
     Abort,
-}
\ No newline at end of file
+}
+
+impl TssError {
+    /// Whether the operation that failed with this error is worth retrying.
+    pub fn retry_abort(&self) -> RetryAbort {
+        match self {
+            // Enough parties joined the signing session, the others complete it.
+            TssError::SignUp() => RetryAbort::Abort,
+            TssError::KeyGenError(_) | TssError::KeySignError(_) | TssError::CommonError(_) => RetryAbort::Retry,
+        }
+    }
+}
+
+/// Classifies an error of the TSS client, the ones it did not raise itself are taken as transient.
+pub fn retry_abort(x: &anyhow::Error) -> RetryAbort {
+    x.downcast_ref::<TssError>().map_or(RetryAbort::Retry, TssError::retry_abort)
+}
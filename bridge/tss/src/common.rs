@@ -1,4 +1,4 @@
-use std::{ iter::repeat, thread, time, time::Duration};
+use std::{ iter::repeat, thread, time, time::Duration, path::PathBuf};
 
 use crypto::{
     aead::{AeadDecryptor, AeadEncryptor},
@@ -54,10 +54,23 @@ impl UrlConfig{
 
 use std::sync::Mutex;
 
+/// Port the manager listens on unless another one is given.
+pub const DEFAULT_MANAGER_PORT: u16 = 8001;
+
 lazy_static!{
-    pub static ref TEST_URL: Mutex<String> = Mutex::new("http://127.0.0.1:8001".to_string());
+    pub static ref TEST_URL: Mutex<String> = Mutex::new(local_manager_url(DEFAULT_MANAGER_PORT));
     pub static ref STORE_MAP: Mutex<String> = Mutex::new("store_map".to_string());
     pub static ref SAVE_STORE: Mutex<Vec<u64>> =  Mutex::new(vec![]);
+    pub static ref KEY_DIR: Mutex<PathBuf> = Mutex::new(PathBuf::from("."));
+}
+
+/// Keeps the key shares and their map in `dir`.
+pub fn set_key_dir(dir: PathBuf){
+    *KEY_DIR.lock().unwrap() = dir;
+}
+
+fn key_path(name: &str) -> String{
+    KEY_DIR.lock().unwrap().join(name).to_string_lossy().into_owned()
 }
 
 pub fn push(x:u64){
@@ -68,7 +81,7 @@ pub fn push(x:u64){
 pub fn get() -> String{
     let num = SAVE_STORE.lock().unwrap()[0];
     let filename = format!("{}-{}{}","test",num,".ron");
-    filename
+    key_path(&filename)
 }
 
 const SAVE_FILE: &str = "test02.ron";
@@ -82,8 +95,13 @@ pub fn get_url() -> String {
     TEST_URL.lock().unwrap().clone()
 }
 
+/// URL of a manager listening on `port` of this host.
+pub fn local_manager_url(port: u16) -> String {
+    format!("http://127.0.0.1:{}", port)
+}
+
 pub fn get_store_file() -> String{
-    STORE_MAP.lock().unwrap().clone()
+    key_path(&STORE_MAP.lock().unwrap())
 }
 //========================================================================
 pub fn random_tss_store(origin_store:&str) -> String{
@@ -105,7 +123,7 @@ pub fn save(pubkey:Vec<u8>, origin_store:&str) -> String{
         db.insert(pubkey,new_store_name.clone().into());
     }).unwrap();
     db.save().unwrap();
-    new_store_name
+    key_path(&new_store_name)
 }
 
 pub fn find_store(pubkey:Vec<u8>) -> String{
@@ -117,7 +135,7 @@ pub fn find_store(pubkey:Vec<u8>) -> String{
         let x = map.get(&pubkey).unwrap();
         x.clone()
     }).unwrap();
-    a.clone()
+    key_path(&a)
 }
 
 pub fn init(){
@@ -8,6 +8,7 @@ use uuid::Uuid;
 
 mod common;
 use common::{Entry, Index, Key, PartySignup, Message};
+pub use common::DEFAULT_MANAGER_PORT;
 
 #[macro_use]
 extern crate lazy_static;
@@ -144,7 +145,12 @@ pub fn push(x:u64){
     common::push(x);
 }
 
-pub fn start_sm_manager(num:u64) {
+pub fn set_key_dir(dir: std::path::PathBuf){
+    common::set_key_dir(dir);
+}
+
+pub fn start_sm_manager(num:u64, port:u16) {
+    common::save_url(common::local_manager_url(port));
     let db:LruCache<Key,u64> = LruCache::new(2500);
     let db_mtx = RwLock::new(db);
 
@@ -163,7 +169,7 @@ pub fn start_sm_manager(num:u64) {
     }
 
     let mut config = Config::new(Environment::Production);
-    config.set_port(8001);
+    config.set_port(port);
 
     rocket::custom(config)
         .mount("/", routes![get, set, signup_keygen, signup_sign, message])
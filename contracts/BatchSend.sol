@@ -0,0 +1,37 @@
+// SPDX-License-Identifier: Unlicense
+pragma solidity ^0.8.17;
+
+/// Batch actor of the bridge, pays several withdrawals out with one Filecoin
+/// message and reverts when any send fails, so one receipt holds for the batch.
+///
+/// The bridge invokes it with `InvokeContract`, see `bridge/bridge/src/batch.rs`.
+/// Root sets its address with `set_batch_actor` once it is deployed.
+contract BatchSend {
+    /// FEVM precompile calling a Filecoin actor by address.
+    address constant CALL_ACTOR_BY_ADDRESS = 0xfe00000000000000000000000000000000000003;
+    uint64 constant METHOD_SEND = 0;
+    uint64 constant DEFAULT_FLAG = 0;
+    uint64 constant NO_CODEC = 0;
+
+    /// Sends `amounts[i]` attoFIL to the Filecoin address whose bytes are `recipients[i]`.
+    function batchSend(bytes[] calldata recipients, uint256[] calldata amounts) external payable {
+        require(recipients.length == amounts.length, "lengths differ");
+        uint256 total;
+        for (uint256 i = 0; i < amounts.length; i++) {
+            total += amounts[i];
+        }
+        require(total == msg.value, "value is not the sum of the amounts");
+
+        for (uint256 i = 0; i < recipients.length; i++) {
+            send(recipients[i], amounts[i]);
+        }
+    }
+
+    function send(bytes calldata recipient, uint256 amount) internal {
+        bytes memory request = abi.encode(METHOD_SEND, amount, DEFAULT_FLAG, NO_CODEC, bytes(""), recipient);
+        (bool success, bytes memory result) = CALL_ACTOR_BY_ADDRESS.delegatecall(request);
+        require(success, "call actor failed");
+        (int256 exitCode, , ) = abi.decode(result, (int256, uint64, bytes));
+        require(exitCode == 0, "send failed");
+    }
+}
@@ -1,5 +1,7 @@
+use std::{fs, path::PathBuf};
 use structopt::StructOpt;
 use sc_cli::RunCmd;
+use bridge::{FcNetwork, GasConfig, LotusConfig, DEFAULT_LOTUS_URL};
 
 #[derive(Debug, StructOpt)]
 pub struct Cli {
@@ -8,6 +10,168 @@ pub struct Cli {
 
 	#[structopt(flatten)]
 	pub run: RunCmd,
+
+	#[structopt(flatten)]
+	pub bridge: BridgeParams,
+}
+
+/// Configuration of the bridge services.
+#[derive(Debug, Clone, StructOpt)]
+pub struct BridgeParams {
+	/// Secret URI of the key the bridge services sign extrinsics with, stored
+	/// in the keystore on startup.
+	#[structopt(long = "relayer-key", value_name = "SURI")]
+	pub relayer_key: Option<String>,
+
+	/// URL of the Lotus API.
+	#[structopt(long = "lotus-url", value_name = "URL", default_value = DEFAULT_LOTUS_URL)]
+	pub lotus_url: String,
+
+	/// File holding the token of the Lotus API, Filecoin transfers cannot be
+	/// pushed without one.
+	#[structopt(long = "lotus-token-file", value_name = "PATH", parse(from_os_str))]
+	pub lotus_token_file: Option<PathBuf>,
+
+	/// Filecoin network of the bridge addresses, `mainnet` or `testnet`.
+	#[structopt(long = "fc-network", value_name = "NETWORK", default_value = "testnet")]
+	pub fc_network: FcNetwork,
+
+	/// Filecoin epochs a deposit has to be buried under before the bridge acts
+	/// on it.
+	#[structopt(long = "fc-confirmations", value_name = "EPOCHS")]
+	pub fc_confirmations: Option<u64>,
+
+	/// Directory of recorded F3 finality certificates, named by instance, to
+	/// relay instead of the ones Lotus serves.
+	#[structopt(long = "fc-f3-certificates", value_name = "PATH", parse(from_os_str))]
+	pub fc_f3_certificates: Option<PathBuf>,
+
+	/// Highest fee cap in attoFIL per gas unit the bridge signs Filecoin
+	/// messages with.
+	#[structopt(long = "fc-max-fee-cap", value_name = "ATTOFIL")]
+	pub fc_max_fee_cap: Option<u128>,
+
+	/// Highest fee in attoFIL, gas limit times fee cap, of the Filecoin
+	/// messages the bridge signs.
+	#[structopt(long = "fc-max-fee", value_name = "ATTOFIL")]
+	pub fc_max_fee: Option<u128>,
+
+	/// Percent added to the estimated gas limit of Filecoin messages.
+	#[structopt(long = "fc-gas-margin", value_name = "PERCENT")]
+	pub fc_gas_margin: Option<u64>,
+
+	/// Filecoin epochs a message may stay in the pool before it is replaced.
+	#[structopt(long = "fc-replace-after", value_name = "EPOCHS")]
+	pub fc_replace_after: Option<u64>,
+
+	/// Blocks a node coordinates a transfer for before the next one takes over.
+	#[structopt(long = "fc-coordinator-deadline", value_name = "BLOCKS")]
+	pub fc_coordinator_deadline: Option<u64>,
+
+	/// Run the manager relaying the messages of the TSS parties instead of a
+	/// party.
+	#[structopt(long = "tss-manager")]
+	pub tss_manager: bool,
+
+	/// Port the TSS manager listens on, 8001 by default.
+	#[structopt(long = "tss-manager-port", value_name = "PORT")]
+	pub tss_manager_port: Option<u16>,
+
+	/// URL of the TSS manager to join instead of the one set on chain.
+	#[structopt(long = "tss-manager-url", value_name = "URL")]
+	pub tss_manager_url: Option<String>,
+
+	/// Index of this node among the TSS parties, from 1.
+	#[structopt(long = "tss-party", value_name = "INDEX")]
+	pub tss_party: Option<u64>,
+
+	/// Directory of the TSS key shares, the working directory by default.
+	#[structopt(long = "tss-key-dir", value_name = "PATH", parse(from_os_str))]
+	pub tss_key_dir: Option<PathBuf>,
+}
+
+impl BridgeParams {
+	/// Rejects the flags that do not go together.
+	pub fn check(&self) -> Result<(), String> {
+		if self.tss_manager {
+			let party_flags = [
+				("--tss-party", self.tss_party.is_some()),
+				("--tss-manager-url", self.tss_manager_url.is_some()),
+				("--tss-key-dir", self.tss_key_dir.is_some()),
+			];
+			if let Some((flag, _)) = party_flags.iter().find(|(_, set)| *set) {
+				return Err(format!("{} is for TSS parties and cannot be used with --tss-manager", flag));
+			}
+		} else if self.tss_manager_port.is_some() {
+			return Err("--tss-manager-port needs --tss-manager".into());
+		} else if self.tss_party.is_none() {
+			return Err("--tss-party is needed unless the node runs --tss-manager".into());
+		}
+		if self.tss_party == Some(0) {
+			return Err("--tss-party starts from 1".into());
+		}
+		let counts = [
+			("--fc-confirmations", self.fc_confirmations),
+			("--fc-replace-after", self.fc_replace_after),
+			("--fc-coordinator-deadline", self.fc_coordinator_deadline),
+		];
+		if let Some((flag, _)) = counts.iter().find(|(_, count)| *count == Some(0)) {
+			return Err(format!("{} must be at least 1", flag));
+		}
+		let maxima = [("--fc-max-fee-cap", self.fc_max_fee_cap), ("--fc-max-fee", self.fc_max_fee)];
+		if let Some((flag, _)) = maxima.iter().find(|(_, max)| *max == Some(0)) {
+			return Err(format!("{} must be above 0", flag));
+		}
+		if let Some(dir) = &self.fc_f3_certificates {
+			if !dir.is_dir() {
+				return Err(format!("--fc-f3-certificates {} is not a directory", dir.display()));
+			}
+		}
+		let urls = [("--lotus-url", Some(&self.lotus_url)), ("--tss-manager-url", self.tss_manager_url.as_ref())];
+		for (flag, url) in urls.iter() {
+			if let Some(url) = url {
+				if !url.starts_with("http://") && !url.starts_with("https://") {
+					return Err(format!("{} must be an http or https URL, got {}", flag, url));
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// The gas settings of the Filecoin messages, the defaults where no flag is given.
+	pub fn gas(&self) -> GasConfig {
+		let mut gas = GasConfig::default();
+		if let Some(max_fee_cap) = self.fc_max_fee_cap {
+			gas.max_fee_cap = max_fee_cap;
+		}
+		if let Some(max_fee) = self.fc_max_fee {
+			gas.max_fee = max_fee;
+		}
+		if let Some(margin) = self.fc_gas_margin {
+			gas.limit_margin = margin;
+		}
+		if let Some(epochs) = self.fc_replace_after {
+			gas.replace_after = epochs;
+		}
+		gas
+	}
+
+	/// The Lotus API, with the token read from `--lotus-token-file`.
+	pub fn lotus(&self) -> Result<LotusConfig, String> {
+		let token = match &self.lotus_token_file {
+			Some(path) => {
+				let token = fs::read_to_string(path)
+					.map_err(|e| format!("cannot read the Lotus token file {}: {}", path.display(), e))?;
+				let token = token.trim();
+				if token.is_empty() {
+					return Err(format!("the Lotus token file {} is empty", path.display()));
+				}
+				Some(token.to_string())
+			},
+			None => None,
+		};
+		Ok(LotusConfig { url: self.lotus_url.clone(), token, network: self.fc_network })
+	}
 }
 
 #[derive(Debug, StructOpt)]
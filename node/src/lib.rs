@@ -1,3 +1,4 @@
 pub mod chain_spec;
+pub mod cli;
 pub mod service;
 pub mod rpc;
@@ -4,17 +4,19 @@ use std::sync::Arc;
 use std::time::Duration;
 use sc_client_api::{ExecutorProvider, RemoteBackend};
 use filecoin_bridge_runtime::{self, opaque::Block, RuntimeApi};
-use sc_service::{error::Error as ServiceError, Configuration, TaskManager};
+use sc_service::{error::Error as ServiceError, config::PruningMode, Configuration, TaskManager};
 use sp_inherents::InherentDataProviders;
 use sc_executor::native_executor_instance;
 pub use sc_executor::NativeExecutor;
 use sp_consensus_aura::sr25519::{AuthorityPair as AuraPair};
 use sc_finality_grandpa::{FinalityProofProvider as GrandpaFinalityProofProvider, SharedVoterState};
 
-use bridge::{start_tss};
-use fc_adapter::{start_fc_service,};
+use bridge::{insert_relayer_key, start_tss, JobQueue, PacketNonce, TssConfig, DEFAULT_COORDINATOR_DEADLINE,
+			 DEFAULT_TSS_MANAGER_PORT};
+use fc_adapter::{start_fc_service, CertificateSource, FcServiceConfig};
 use futures::{channel::mpsc};
-use std::env;
+use std::{fs, path::PathBuf};
+use crate::cli::BridgeParams;
 // Our native executor instance.
 native_executor_instance!(
 	pub Executor,
@@ -83,8 +85,18 @@ pub fn new_partial(config: &Configuration) -> Result<sc_service::PartialComponen
 	})
 }
 
-/// Builds a new service for a full client.
-pub fn new_full(config: Configuration) -> Result<TaskManager, ServiceError> {
+/// Builds a new service for a full client, with the bridge services configured by `bridge`.
+pub fn new_full(config: Configuration, bridge: BridgeParams) -> Result<TaskManager, ServiceError> {
+	bridge.check().map_err(ServiceError::Other)?;
+	// The events of the blocks finalized while the node was stopped are read on restart.
+	if let PruningMode::Constrained(_) = config.pruning {
+		return Err(ServiceError::Other("the bridge needs the state of every finalized block, run with --pruning archive".into()));
+	}
+	let lotus = bridge.lotus().map_err(ServiceError::Other)?;
+	let tss_key_dir = bridge.tss_key_dir.clone().unwrap_or_else(|| PathBuf::from("."));
+	fs::create_dir_all(&tss_key_dir)
+		.map_err(|e| ServiceError::Other(format!("cannot create the TSS key directory {}: {}", tss_key_dir.display(), e)))?;
+
 	let sc_service::PartialComponents {
 		client, backend, mut task_manager, import_queue, keystore, select_chain, transaction_pool,
 		inherent_data_providers,
@@ -118,6 +130,18 @@ pub fn new_full(config: Configuration) -> Result<TaskManager, ServiceError> {
 	let name = config.network.node_name.clone();
 	let enable_grandpa = !config.disable_grandpa;
 	let prometheus_registry = config.prometheus_registry().cloned();
+	let fc_cursor_path = config.database.path()
+		.and_then(|db| db.parent())
+		.map(|dir| dir.join("fc_scan_cursor"));
+	let fc_nonce_path = config.database.path()
+		.and_then(|db| db.parent())
+		.map(|dir| dir.join("fc_nonces"));
+	let bridge_cursor_path = config.database.path()
+		.and_then(|db| db.parent())
+		.map(|dir| dir.join("bridge_event_cursor"));
+	let bridge_jobs_path = config.database.path()
+		.and_then(|db| db.parent())
+		.map(|dir| dir.join("bridge_jobs.json"));
 	let telemetry_connection_sinks = sc_service::TelemetryConnectionSinks::default();
 
 	let rpc_extensions_builder = {
@@ -176,6 +200,11 @@ pub fn new_full(config: Configuration) -> Result<TaskManager, ServiceError> {
 		task_manager.spawn_essential_handle().spawn_blocking("aura", aura);
 	}
 
+	let bridge_keystore = keystore.clone();
+	if let Some(suri) = &bridge.relayer_key {
+		insert_relayer_key(&bridge_keystore, suri).map_err(|e| ServiceError::Other(e.to_string()))?;
+	}
+
 	// if the node isn't actively participating in consensus then it doesn't
 	// need a keystore, regardless of which protocol we use below.
 	let keystore = if role.is_authority() {
@@ -187,36 +216,54 @@ pub fn new_full(config: Configuration) -> Result<TaskManager, ServiceError> {
 	let (senderbool, reciverbool) = mpsc::unbounded::<(Vec<u8>)>();
 	let (senderfc, reciverfc) = mpsc::unbounded::<(Vec<u8>)>();
 
-	let mut is_rocket = false;
-	let mut num:u64 = 0;
-	if env::var("Rocket").is_ok(){
-		is_rocket = true;
-	}else{
-		if env::var("NodeNum").is_ok(){
-			let node = env::var("NodeNum").unwrap();
-			num = node.parse().unwrap();
-		}
+	let mut fc_config = FcServiceConfig { cursor_path: fc_cursor_path, lotus: lotus.clone(), ..Default::default() };
+	if let Some(confirmations) = bridge.fc_confirmations {
+		fc_config.confirmations = confirmations;
 	}
+	if let Some(dir) = &bridge.fc_f3_certificates {
+		fc_config.certificates = CertificateSource::Recorded(dir.clone());
+	}
+
+	// Both services sign with the relayer key, so they share its nonce.
+	let extrinsic_nonces = PacketNonce::shared();
+	let bridge_jobs = Arc::new(JobQueue::new(bridge_jobs_path)
+		.map_err(|e| ServiceError::Other(format!("cannot load the bridge jobs: {}", e)))?);
 
 	let tss = start_tss(
 		client.clone(),
 		transaction_pool.clone(),
-		0u64/*keystore.clone()*/,
-		is_rocket,
+		bridge_keystore.clone(),
+		extrinsic_nonces.clone(),
+		bridge_jobs.clone(),
 		senderbool,
 		senderfc,
-		num,
-	);
+		TssConfig {
+			manager: bridge.tss_manager,
+			manager_port: bridge.tss_manager_port.unwrap_or(DEFAULT_TSS_MANAGER_PORT),
+			manager_url: bridge.tss_manager_url.clone(),
+			party: bridge.tss_party.unwrap_or(0),
+			key_dir: tss_key_dir,
+			lotus,
+			gas: bridge.gas(),
+			coordinator_deadline: bridge.fc_coordinator_deadline.unwrap_or(DEFAULT_COORDINATOR_DEADLINE),
+			nonce_path: fc_nonce_path,
+			event_cursor_path: bridge_cursor_path,
+		},
+	).map_err(|e| ServiceError::Other(e.to_string()))?;
 
 	task_manager.spawn_essential_handle().spawn_blocking("tss", tss);
 
-	if !is_rocket {
+	if !bridge.tss_manager {
 		let fc_service = start_fc_service(
 			client.clone(),
 			transaction_pool.clone(),
+			bridge_keystore,
+			extrinsic_nonces,
+			bridge_jobs,
 			reciverbool,
 			//reciverfc
-		);
+			fc_config,
+		).map_err(|e| ServiceError::Other(e.to_string()))?;
 
 		task_manager.spawn_essential_handle().spawn_blocking("fc_service", fc_service);
 	}
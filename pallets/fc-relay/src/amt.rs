@@ -0,0 +1,202 @@
+//! Array Mapped Trie as laid out by go-amt-ipld v2, the layout Filecoin uses
+//! for the message arrays of a block and the receipts of a tipset.
+//!
+//! A proof is the set of raw IPLD blocks needed to walk from a root CID down
+//! to the entries of interest. Blocks are addressed by the CID computed from
+//! their bytes, so a proof cannot substitute any node.
+
+use sp_std::{prelude::*, collections::btree_map::BTreeMap};
+use sp_runtime::RuntimeDebug;
+
+use crate::cbor::{self, CborError, Decoder};
+
+/// Number of slots of every node.
+pub const WIDTH: u64 = 8;
+
+/// Deeper tries than this cannot occur for Filecoin blocks and are rejected
+/// to bound the work done walking a proof.
+const MAX_HEIGHT: u64 = 8;
+
+#[derive(Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+pub enum ProofError {
+	/// A block of the proof could not be decoded.
+	Cbor(CborError),
+	/// A block the walk needs is not part of the proof.
+	MissingBlock,
+	/// A node is inconsistent with its bitmap or height.
+	Malformed,
+	/// The message is not included in the tipset.
+	NotIncluded,
+	/// The message is included but was skipped by the VM because of its nonce.
+	NotExecuted,
+}
+
+impl From<CborError> for ProofError {
+	fn from(e: CborError) -> Self {
+		ProofError::Cbor(e)
+	}
+}
+
+/// Raw IPLD blocks by CID.
+pub struct BlockStore<'a> {
+	blocks: BTreeMap<Vec<u8>, &'a [u8]>,
+}
+
+impl<'a> BlockStore<'a> {
+	pub fn new(raw: &'a [Vec<u8>]) -> Self {
+		BlockStore {
+			blocks: raw.iter().map(|b| (cbor::cid_of(b), b.as_slice())).collect(),
+		}
+	}
+
+	pub fn get(&self, cid: &[u8]) -> Result<&'a [u8], ProofError> {
+		self.blocks.get(cid).copied().ok_or(ProofError::MissingBlock)
+	}
+}
+
+/// The root of a trie.
+pub struct Root<'a> {
+	pub height: u64,
+	pub count: u64,
+	pub node: Node<'a>,
+}
+
+impl<'a> Root<'a> {
+	pub fn from_cbor(raw: &'a [u8]) -> Result<Self, ProofError> {
+		let mut d = Decoder::new(raw);
+		if d.array()? != 3 {
+			return Err(ProofError::Malformed);
+		}
+		let height = d.uint()?;
+		let count = d.uint()?;
+		let node = Node::decode(&mut d)?;
+		d.finish()?;
+		if height > MAX_HEIGHT {
+			return Err(ProofError::Malformed);
+		}
+		Ok(Root { height, count, node })
+	}
+}
+
+/// An inner node holds links to children, a leaf node holds the values.
+pub struct Node<'a> {
+	pub bitmap: u8,
+	pub links: Vec<Vec<u8>>,
+	pub values: Vec<&'a [u8]>,
+}
+
+impl<'a> Node<'a> {
+	pub fn from_cbor(raw: &'a [u8]) -> Result<Self, ProofError> {
+		let mut d = Decoder::new(raw);
+		let node = Self::decode(&mut d)?;
+		d.finish()?;
+		Ok(node)
+	}
+
+	fn decode(d: &mut Decoder<'a>) -> Result<Self, ProofError> {
+		if d.array()? != 3 {
+			return Err(ProofError::Malformed);
+		}
+		let bitmap = match d.bytes()? {
+			[] => 0,
+			[b] => *b,
+			_ => return Err(ProofError::Malformed),
+		};
+		let mut links = Vec::new();
+		for _ in 0..d.array()? {
+			links.push(d.cid()?);
+		}
+		let mut values = Vec::new();
+		for _ in 0..d.array()? {
+			values.push(d.raw()?);
+		}
+		let set = bitmap.count_ones() as usize;
+		if links.len() + values.len() != set || (!links.is_empty() && !values.is_empty()) {
+			return Err(ProofError::Malformed);
+		}
+		Ok(Node { bitmap, links, values })
+	}
+
+	fn has(&self, slot: u64) -> bool {
+		self.bitmap & (1 << slot) != 0
+	}
+
+	/// Position of a slot among the occupied ones.
+	fn position(&self, slot: u64) -> usize {
+		(self.bitmap & ((1u16 << slot) - 1) as u8).count_ones() as usize
+	}
+
+	/// Link of an occupied slot of an inner node.
+	pub fn link(&self, slot: u64) -> Option<&[u8]> {
+		if !self.has(slot) {
+			return None;
+		}
+		self.links.get(self.position(slot)).map(|l| l.as_slice())
+	}
+
+	/// Value of an occupied slot of a leaf node.
+	pub fn value(&self, slot: u64) -> Option<&'a [u8]> {
+		if !self.has(slot) {
+			return None;
+		}
+		self.values.get(self.position(slot)).copied()
+	}
+}
+
+/// Number of entries covered by each slot of a node at `height`.
+pub fn slot_size(height: u64) -> u64 {
+	WIDTH.pow(height as u32)
+}
+
+/// Looks up the raw value stored at `index`.
+pub fn get<'a>(store: &BlockStore<'a>, root: &[u8], index: u64) -> Result<Option<&'a [u8]>, ProofError> {
+	let root = Root::from_cbor(store.get(root)?)?;
+	if index >= slot_size(root.height + 1) {
+		return Ok(None);
+	}
+	let (mut node, mut height, mut index) = (root.node, root.height, index);
+	loop {
+		let size = slot_size(height);
+		let slot = index / size;
+		if height == 0 {
+			return Ok(node.value(slot));
+		}
+		let link = match node.link(slot) {
+			Some(link) => link,
+			None => return Ok(None),
+		};
+		node = Node::from_cbor(store.get(link)?)?;
+		index %= size;
+		height -= 1;
+	}
+}
+
+/// Calls `f` with every index and raw value in order, until it returns `false`.
+pub fn for_each<'a, F>(store: &BlockStore<'a>, root: &[u8], mut f: F) -> Result<(), ProofError>
+	where F: FnMut(u64, &'a [u8]) -> Result<bool, ProofError>
+{
+	let root = Root::from_cbor(store.get(root)?)?;
+	walk(store, &root.node, root.height, 0, &mut f).map(|_| ())
+}
+
+fn walk<'a, F>(store: &BlockStore<'a>, node: &Node<'a>, height: u64, offset: u64, f: &mut F) -> Result<bool, ProofError>
+	where F: FnMut(u64, &'a [u8]) -> Result<bool, ProofError>
+{
+	let size = slot_size(height);
+	for slot in 0..WIDTH {
+		let index = offset + slot * size;
+		if height == 0 {
+			if let Some(value) = node.value(slot) {
+				if !f(index, value)? {
+					return Ok(false);
+				}
+			}
+		} else if let Some(link) = node.link(slot) {
+			let child = Node::from_cbor(store.get(link)?)?;
+			if !walk(store, &child, height - 1, index, f)? {
+				return Ok(false);
+			}
+		}
+	}
+	Ok(true)
+}
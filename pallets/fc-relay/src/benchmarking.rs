@@ -0,0 +1,38 @@
+//! Benchmarks of the relay's header checks.
+
+#![cfg(feature = "runtime-benchmarks")]
+
+use super::*;
+use frame_benchmarking::benchmarks;
+use bls12_381::{G1Affine, G2Affine, G2Projective, Scalar, hash_to_curve::{ExpandMsgXmd, HashToCurve}};
+use sha2::Sha256;
+
+benchmarks! {
+	_ { }
+
+	// The check `weights::BLS_VERIFY` stands for, run three times per block
+	// and once per beacon entry.
+	bls_verify {
+		let secret = Scalar::from(0x5eed_u64);
+		let public_key = G1Affine::from(G1Affine::generator() * secret).to_compressed();
+		let message = b"filecoin block header".to_vec();
+		let hash = <G2Projective as HashToCurve<ExpandMsgXmd<Sha256>>>::hash_to_curve(&message, verify::BLS_DST);
+		let signature = G2Affine::from(hash * secret).to_compressed();
+	}: {
+		assert!(verify::bls_verify(&public_key, &message, &signature));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::{new_test_ext, Test};
+	use frame_support::assert_ok;
+
+	#[test]
+	fn test_benchmarks() {
+		new_test_ext().execute_with(|| {
+			assert_ok!(test_benchmark_bls_verify::<Test>());
+		});
+	}
+}
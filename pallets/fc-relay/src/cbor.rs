@@ -0,0 +1,213 @@
+//! A minimal DAG-CBOR reader.
+//!
+//! Filecoin objects are IPLD blocks encoded as DAG-CBOR and addressed by the
+//! blake2b-256 CID of their raw bytes. The relay only ever needs to walk a few
+//! well known layouts (block headers, AMT nodes, messages and receipts), so
+//! this reader exposes typed accessors instead of a generic value tree.
+
+use sp_std::prelude::*;
+use sp_runtime::RuntimeDebug;
+
+/// Prefix of a CIDv1 using the DAG-CBOR codec and a 32 byte blake2b-256 multihash.
+pub const DAG_CBOR_BLAKE2B_PREFIX: [u8; 6] = [0x01, 0x71, 0xa0, 0xe4, 0x02, 0x20];
+
+/// CBOR tag marking an IPLD link.
+const CID_TAG: u64 = 42;
+
+const MAJOR_UINT: u8 = 0;
+const MAJOR_NEGINT: u8 = 1;
+const MAJOR_BYTES: u8 = 2;
+const MAJOR_TEXT: u8 = 3;
+const MAJOR_ARRAY: u8 = 4;
+const MAJOR_MAP: u8 = 5;
+const MAJOR_TAG: u8 = 6;
+const MAJOR_SIMPLE: u8 = 7;
+
+const NULL: u8 = 0xf6;
+
+#[derive(Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+pub enum CborError {
+	/// The input ended in the middle of a data item.
+	UnexpectedEof,
+	/// A data item had a different major type than the layout requires.
+	UnexpectedType,
+	/// Indefinite lengths and other encodings DAG-CBOR forbids.
+	Unsupported,
+	/// A link was not a well formed binary CID.
+	InvalidCid,
+	/// An integer did not fit the target type.
+	Overflow,
+	/// An object had fewer fields than the layout requires.
+	MissingField,
+	/// Bytes were left over after the top level item.
+	TrailingData,
+}
+
+/// Cursor over a single encoded IPLD block.
+pub struct Decoder<'a> {
+	data: &'a [u8],
+	pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+	pub fn new(data: &'a [u8]) -> Self {
+		Decoder { data, pos: 0 }
+	}
+
+	pub fn position(&self) -> usize {
+		self.pos
+	}
+
+	/// Checks that the whole input has been consumed.
+	pub fn finish(self) -> Result<(), CborError> {
+		if self.pos == self.data.len() {
+			Ok(())
+		} else {
+			Err(CborError::TrailingData)
+		}
+	}
+
+	fn byte(&mut self) -> Result<u8, CborError> {
+		let b = *self.data.get(self.pos).ok_or(CborError::UnexpectedEof)?;
+		self.pos += 1;
+		Ok(b)
+	}
+
+	fn take(&mut self, len: u64) -> Result<&'a [u8], CborError> {
+		let len = len as usize;
+		let end = self.pos.checked_add(len).ok_or(CborError::UnexpectedEof)?;
+		let slice = self.data.get(self.pos..end).ok_or(CborError::UnexpectedEof)?;
+		self.pos = end;
+		Ok(slice)
+	}
+
+	/// Reads the initial byte and argument of the next data item.
+	fn header(&mut self) -> Result<(u8, u64), CborError> {
+		let initial = self.byte()?;
+		let major = initial >> 5;
+		let info = initial & 0x1f;
+		let arg = match info {
+			0..=23 => info as u64,
+			24 => self.byte()? as u64,
+			25 => self.take(2)?.iter().fold(0u64, |acc, b| acc << 8 | *b as u64),
+			26 => self.take(4)?.iter().fold(0u64, |acc, b| acc << 8 | *b as u64),
+			27 => self.take(8)?.iter().fold(0u64, |acc, b| acc << 8 | *b as u64),
+			_ => return Err(CborError::Unsupported),
+		};
+		Ok((major, arg))
+	}
+
+	fn expect(&mut self, major: u8) -> Result<u64, CborError> {
+		match self.header()? {
+			(m, arg) if m == major => Ok(arg),
+			_ => Err(CborError::UnexpectedType),
+		}
+	}
+
+	/// Consumes a `null` if it is the next item.
+	pub fn null(&mut self) -> bool {
+		if self.data.get(self.pos) == Some(&NULL) {
+			self.pos += 1;
+			true
+		} else {
+			false
+		}
+	}
+
+	/// Reads an array header and returns the number of elements.
+	pub fn array(&mut self) -> Result<u64, CborError> {
+		self.expect(MAJOR_ARRAY)
+	}
+
+	pub fn uint(&mut self) -> Result<u64, CborError> {
+		self.expect(MAJOR_UINT)
+	}
+
+	pub fn int(&mut self) -> Result<i64, CborError> {
+		match self.header()? {
+			(MAJOR_UINT, v) if v <= i64::max_value() as u64 => Ok(v as i64),
+			(MAJOR_NEGINT, v) if v <= i64::max_value() as u64 => Ok(-1 - v as i64),
+			(MAJOR_UINT, _) | (MAJOR_NEGINT, _) => Err(CborError::Overflow),
+			_ => Err(CborError::UnexpectedType),
+		}
+	}
+
+	pub fn bytes(&mut self) -> Result<&'a [u8], CborError> {
+		let len = self.expect(MAJOR_BYTES)?;
+		self.take(len)
+	}
+
+	/// Reads a byte string that may also be encoded as `null`.
+	pub fn bytes_or_null(&mut self) -> Result<&'a [u8], CborError> {
+		if self.null() {
+			Ok(&[])
+		} else {
+			self.bytes()
+		}
+	}
+
+	/// Reads an IPLD link and returns the binary CID it points to.
+	pub fn cid(&mut self) -> Result<Vec<u8>, CborError> {
+		if self.expect(MAJOR_TAG)? != CID_TAG {
+			return Err(CborError::InvalidCid);
+		}
+		match self.bytes()? {
+			[0x00, cid @ ..] if !cid.is_empty() => Ok(cid.to_vec()),
+			_ => Err(CborError::InvalidCid),
+		}
+	}
+
+	/// Skips over the next data item, including everything nested in it.
+	pub fn skip(&mut self) -> Result<(), CborError> {
+		let mut pending: u64 = 1;
+		while pending > 0 {
+			pending -= 1;
+			let (major, arg) = self.header()?;
+			let nested = match major {
+				MAJOR_UINT | MAJOR_NEGINT | MAJOR_SIMPLE => 0,
+				MAJOR_BYTES | MAJOR_TEXT => {
+					self.take(arg)?;
+					0
+				},
+				MAJOR_ARRAY => arg,
+				MAJOR_MAP => arg.checked_mul(2).ok_or(CborError::Overflow)?,
+				MAJOR_TAG => 1,
+				_ => return Err(CborError::Unsupported),
+			};
+			// Every nested item takes at least one byte, so a count larger than
+			// the remaining input can only come from a malformed block.
+			if nested > (self.data.len() - self.pos) as u64 {
+				return Err(CborError::UnexpectedEof);
+			}
+			pending += nested;
+		}
+		Ok(())
+	}
+
+	/// Skips over the next data item and returns its raw encoding.
+	pub fn raw(&mut self) -> Result<&'a [u8], CborError> {
+		let start = self.pos;
+		self.skip()?;
+		Ok(&self.data[start..self.pos])
+	}
+}
+
+/// Computes the CID Filecoin assigns to a DAG-CBOR block.
+pub fn cid_of(raw: &[u8]) -> Vec<u8> {
+	let mut cid = DAG_CBOR_BLAKE2B_PREFIX.to_vec();
+	cid.extend_from_slice(&sp_io::hashing::blake2_256(raw));
+	cid
+}
+
+/// Decodes a Filecoin `BigInt` (sign byte followed by a big-endian magnitude)
+/// that is known to be non-negative.
+pub fn big_uint(bytes: &[u8]) -> Result<u128, CborError> {
+	match bytes {
+		[] => Ok(0),
+		[0x00, magnitude @ ..] if magnitude.len() <= 16 => {
+			Ok(magnitude.iter().fold(0u128, |acc, b| acc << 8 | *b as u128))
+		},
+		[0x00, ..] => Err(CborError::Overflow),
+		_ => Err(CborError::UnexpectedType),
+	}
+}
@@ -0,0 +1,352 @@
+//! Filecoin fast finality (F3) certificates.
+//!
+//! Every GossiPBFT instance decides on a chain of tipsets extending the one
+//! decided by the previous instance. Its certificate carries the decided chain,
+//! the participants that signed the decision and their aggregated BLS
+//! signature, and the changes to the power table the next instance runs with.
+//!
+//! A certificate is accepted when the signers hold more than two thirds of the
+//! power of the instance's power table. Keys are aggregated the way kyber's BDN
+//! scheme does, which go-f3 signs with: every key is weighted by a coefficient
+//! derived from the whole power table, so that no participant can cancel out
+//! the keys of others.
+
+use codec::{Decode, Encode};
+use sp_runtime::RuntimeDebug;
+use sp_std::prelude::*;
+
+use blake2s_simd::Params;
+use bls12_381::{G1Affine, G1Projective, Scalar};
+
+use crate::cbor;
+use crate::verify::bls_verify_key;
+
+/// Domain separation tag of GossiPBFT payloads.
+const DOMAIN_SEPARATION_TAG: &[u8] = b"GPBFT";
+
+/// Certificates are signatures over the DECIDE phase of round zero.
+const DECIDE_PHASE: u8 = 5;
+
+/// XOF length BLAKE2Xs is parameterized with when the output length is not
+/// known in advance, as in Go's `blake2s.OutputLengthUnknown`.
+const XOF_LENGTH_UNKNOWN: u64 = 0xffff;
+
+/// A participant of an instance.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Default, RuntimeDebug)]
+pub struct PowerEntry {
+	/// Actor ID of the miner.
+	pub id: u64,
+	pub power: u128,
+	/// Compressed BLS public key on G1.
+	pub public_key: Vec<u8>,
+}
+
+/// A tipset as decided by an instance.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Default, RuntimeDebug)]
+pub struct ECTipset {
+	pub epoch: u64,
+	/// CIDs of the blocks, concatenated.
+	pub key: Vec<u8>,
+	/// CID of the power table derived from this tipset.
+	pub power_table: Vec<u8>,
+	pub commitments: [u8; 32],
+}
+
+/// A change to the power table.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Default, RuntimeDebug)]
+pub struct PowerTableDelta {
+	pub participant: u64,
+	pub power_delta: i128,
+	/// New key of the participant, empty if unchanged.
+	pub signing_key: Vec<u8>,
+}
+
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Default, RuntimeDebug)]
+pub struct FinalityCertificate {
+	pub instance: u64,
+	/// The decided chain, starting with the head decided by the previous instance.
+	pub ec_chain: Vec<ECTipset>,
+	pub supplemental_commitments: [u8; 32],
+	/// CID of the power table of the next instance.
+	pub supplemental_power_table: Vec<u8>,
+	/// Indices of the signers in the power table, in increasing order.
+	pub signers: Vec<u32>,
+	/// Aggregated BLS signature on G2.
+	pub signature: Vec<u8>,
+	pub power_table_delta: Vec<PowerTableDelta>,
+}
+
+impl FinalityCertificate {
+	/// The last tipset of the decided chain.
+	pub fn head(&self) -> Option<&ECTipset> {
+		self.ec_chain.last()
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+pub enum F3Error {
+	/// The decided chain is empty or its epochs do not increase.
+	InvalidChain,
+	/// A signer index is out of range or not increasing.
+	InvalidSigner,
+	/// A key of the power table is not a valid G1 point.
+	InvalidKey,
+	NotEnoughPower,
+	InvalidSignature,
+	InvalidPowerDelta,
+	/// The power table after applying the delta does not match the certified CID.
+	PowerTableMismatch,
+}
+
+/// Checks a certificate against the power table of its instance and returns
+/// the power table of the next instance.
+pub fn verify_certificate(network: &[u8], table: &[PowerEntry], cert: &FinalityCertificate)
+	-> Result<Vec<PowerEntry>, F3Error>
+{
+	if cert.ec_chain.is_empty() || cert.ec_chain.windows(2).any(|w| w[0].epoch >= w[1].epoch) {
+		return Err(F3Error::InvalidChain);
+	}
+	let keys = table.iter()
+		.map(|entry| decode_key(&entry.public_key))
+		.collect::<Option<Vec<_>>>()
+		.ok_or(F3Error::InvalidKey)?;
+	let coefficients = bdn_coefficients(table);
+
+	let total: u128 = table.iter().map(|entry| entry.power).sum();
+	let mut signed = 0u128;
+	let mut aggregate = G1Projective::identity();
+	let mut previous = None;
+	for &index in &cert.signers {
+		let index = index as usize;
+		if index >= table.len() || previous.map_or(false, |p| p >= index) {
+			return Err(F3Error::InvalidSigner);
+		}
+		previous = Some(index);
+		signed += table[index].power;
+		// (c + 1) * pk, computed in the group like kyber does.
+		aggregate += keys[index] * coefficients[index] + keys[index];
+	}
+	// Strictly more than two thirds.
+	if signed.saturating_mul(3) <= total.saturating_mul(2) {
+		return Err(F3Error::NotEnoughPower);
+	}
+
+	if cert.signature.len() != 96 {
+		return Err(F3Error::InvalidSignature);
+	}
+	let mut signature = [0u8; 96];
+	signature.copy_from_slice(&cert.signature);
+	if !bls_verify_key(&G1Affine::from(aggregate), &signing_payload(network, cert), &signature) {
+		return Err(F3Error::InvalidSignature);
+	}
+
+	let next = apply_delta(table, &cert.power_table_delta)?;
+	if power_table_cid(&next) != cert.supplemental_power_table {
+		return Err(F3Error::PowerTableMismatch);
+	}
+	Ok(next)
+}
+
+/// The bytes signed by the participants for a certificate.
+pub fn signing_payload(network: &[u8], cert: &FinalityCertificate) -> Vec<u8> {
+	let values: Vec<Vec<u8>> = cert.ec_chain.iter().map(tipset_signing_bytes).collect();
+
+	let mut out = Vec::new();
+	out.extend_from_slice(DOMAIN_SEPARATION_TAG);
+	out.push(b':');
+	out.extend_from_slice(network);
+	out.push(b':');
+	out.push(DECIDE_PHASE);
+	out.extend_from_slice(&0u64.to_be_bytes());
+	out.extend_from_slice(&cert.instance.to_be_bytes());
+	out.extend_from_slice(&cert.supplemental_commitments);
+	out.extend_from_slice(&merkle_root(&values));
+	out.extend_from_slice(&cert.supplemental_power_table);
+	out
+}
+
+fn tipset_signing_bytes(tipset: &ECTipset) -> Vec<u8> {
+	let mut out = Vec::new();
+	out.extend_from_slice(&(tipset.epoch as i64).to_be_bytes());
+	out.extend_from_slice(&tipset.commitments);
+	write_bytes(&mut out, &tipset.power_table);
+	write_bytes(&mut out, &tipset.key);
+	out
+}
+
+/// Keccak merkle root of `values`, padded with zero digests to a power of two.
+pub fn merkle_root(values: &[Vec<u8>]) -> [u8; 32] {
+	if values.is_empty() {
+		return [0u8; 32];
+	}
+	let mut depth = 0;
+	while (1usize << depth) < values.len() {
+		depth += 1;
+	}
+	merkle_node(depth, values)
+}
+
+fn merkle_node(depth: usize, values: &[Vec<u8>]) -> [u8; 32] {
+	if values.is_empty() {
+		return [0u8; 32];
+	}
+	if depth == 0 {
+		let mut leaf = vec![0u8];
+		leaf.extend_from_slice(&values[0]);
+		return sp_io::hashing::keccak_256(&leaf);
+	}
+	let split = (1usize << (depth - 1)).min(values.len());
+	let mut node = vec![1u8];
+	node.extend_from_slice(&merkle_node(depth - 1, &values[..split]));
+	node.extend_from_slice(&merkle_node(depth - 1, &values[split..]));
+	sp_io::hashing::keccak_256(&node)
+}
+
+/// Applies a delta to a power table and sorts it by decreasing power, then ID.
+pub fn apply_delta(table: &[PowerEntry], delta: &[PowerTableDelta]) -> Result<Vec<PowerEntry>, F3Error> {
+	let mut next = table.to_vec();
+	for change in delta {
+		match next.iter().position(|entry| entry.id == change.participant) {
+			Some(i) => {
+				let power = (next[i].power as i128).checked_add(change.power_delta)
+					.filter(|p| *p >= 0)
+					.ok_or(F3Error::InvalidPowerDelta)?;
+				if !change.signing_key.is_empty() {
+					next[i].public_key = change.signing_key.clone();
+				}
+				if power == 0 {
+					next.remove(i);
+				} else {
+					next[i].power = power as u128;
+				}
+			}
+			None => {
+				if change.power_delta <= 0 || change.signing_key.is_empty() {
+					return Err(F3Error::InvalidPowerDelta);
+				}
+				next.push(PowerEntry {
+					id: change.participant,
+					power: change.power_delta as u128,
+					public_key: change.signing_key.clone(),
+				});
+			}
+		}
+	}
+	next.sort_by(|a, b| b.power.cmp(&a.power).then_with(|| a.id.cmp(&b.id)));
+	Ok(next)
+}
+
+/// CID of the DAG-CBOR encoding of a power table.
+pub fn power_table_cid(table: &[PowerEntry]) -> Vec<u8> {
+	let mut out = Vec::new();
+	write_head(&mut out, 4, table.len() as u64);
+	for entry in table {
+		write_head(&mut out, 4, 3);
+		write_head(&mut out, 0, entry.id);
+		write_bytes(&mut out, &big_int_bytes(entry.power));
+		write_bytes(&mut out, &entry.public_key);
+	}
+	cbor::cid_of(&out)
+}
+
+/// Filecoin big integer encoding: empty for zero, otherwise a sign byte and the magnitude.
+fn big_int_bytes(value: u128) -> Vec<u8> {
+	if value == 0 {
+		return Vec::new();
+	}
+	let bytes = value.to_be_bytes();
+	let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+	let mut out = vec![0u8];
+	out.extend_from_slice(&bytes[start..]);
+	out
+}
+
+/// The BDN coefficient of every entry, kyber's `hashPointToR`.
+///
+/// The compressed keys of the whole table are hashed with BLAKE2Xs and the
+/// output read as one little endian 128 bit integer per entry, reduced modulo
+/// 2^128 - 1.
+pub fn bdn_coefficients(table: &[PowerEntry]) -> Vec<Scalar> {
+	let mut keys = Vec::with_capacity(table.len() * 48);
+	for entry in table {
+		keys.extend_from_slice(&entry.public_key);
+	}
+	blake2xs(&keys, 16 * table.len()).chunks(16).map(|chunk| {
+		let mut value = [0u8; 16];
+		value.copy_from_slice(chunk);
+		let value = match u128::from_le_bytes(value) {
+			u128::MAX => 0,
+			value => value,
+		};
+		let mut bytes = [0u8; 32];
+		bytes[..16].copy_from_slice(&value.to_le_bytes());
+		// Below 2^128 and therefore always a canonical scalar.
+		Scalar::from_bytes(&bytes).unwrap()
+	}).collect()
+}
+
+/// The first `len` bytes of the BLAKE2Xs XOF of `data`, unkeyed and of
+/// unknown output length like Go's `blake2s.NewXOF(blake2s.OutputLengthUnknown, nil)`.
+pub fn blake2xs(data: &[u8], len: usize) -> Vec<u8> {
+	// The XOF length takes the upper 16 bits of the node offset.
+	let root = Params::new()
+		.hash_length(32)
+		.node_offset(XOF_LENGTH_UNKNOWN << 32)
+		.hash(data);
+
+	let mut out = Vec::with_capacity(len + 32);
+	let mut offset = 0u64;
+	while out.len() < len {
+		let block = Params::new()
+			.hash_length(32)
+			.fanout(0)
+			.max_depth(0)
+			.max_leaf_length(32)
+			.node_offset((XOF_LENGTH_UNKNOWN << 32) | offset)
+			.inner_hash_length(32)
+			.hash(root.as_bytes());
+		out.extend_from_slice(block.as_bytes());
+		offset += 1;
+	}
+	out.truncate(len);
+	out
+}
+
+fn decode_key(key: &[u8]) -> Option<G1Affine> {
+	if key.len() != 48 {
+		return None;
+	}
+	let mut bytes = [0u8; 48];
+	bytes.copy_from_slice(key);
+	let point = G1Affine::from_compressed(&bytes);
+	if bool::from(point.is_none()) {
+		return None;
+	}
+	let point = point.unwrap();
+	if bool::from(point.is_identity()) { None } else { Some(point) }
+}
+
+fn write_head(out: &mut Vec<u8>, major: u8, value: u64) {
+	let major = major << 5;
+	match value {
+		0..=23 => out.push(major | value as u8),
+		24..=0xff => out.extend_from_slice(&[major | 24, value as u8]),
+		0x100..=0xffff => {
+			out.push(major | 25);
+			out.extend_from_slice(&(value as u16).to_be_bytes());
+		}
+		0x1_0000..=0xffff_ffff => {
+			out.push(major | 26);
+			out.extend_from_slice(&(value as u32).to_be_bytes());
+		}
+		_ => {
+			out.push(major | 27);
+			out.extend_from_slice(&value.to_be_bytes());
+		}
+	}
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+	write_head(out, 2, bytes.len() as u64);
+	out.extend_from_slice(bytes);
+}
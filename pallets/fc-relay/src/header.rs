@@ -0,0 +1,131 @@
+//! Filecoin block headers as seen by the relay.
+//!
+//! Mirrors `lotus_api_forest::types::header::BlockHeader`, but is decoded from
+//! the raw DAG-CBOR block so that the CID of every header is computed on chain
+//! instead of being taken from the relayer.
+
+use codec::{Decode, Encode};
+use sp_core::H256;
+use sp_runtime::RuntimeDebug;
+use sp_std::prelude::*;
+
+use crate::cbor::{self, CborError, Decoder};
+
+/// The oldest header layout has no `ParentBaseFee` field.
+const MIN_HEADER_FIELDS: u64 = 15;
+
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Default, RuntimeDebug)]
+pub struct BeaconEntry {
+	pub round: u64,
+	pub data: Vec<u8>,
+}
+
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Default, RuntimeDebug)]
+pub struct ElectionProof {
+	pub win_count: i64,
+	pub vrf_proof: Vec<u8>,
+}
+
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Default, RuntimeDebug)]
+pub struct BlockHeader {
+	/// Address bytes of the miner that produced the block.
+	pub miner: Vec<u8>,
+	/// VRF proof of the ticket.
+	pub ticket: Vec<u8>,
+	pub election_proof: Option<ElectionProof>,
+	pub beacon_entries: Vec<BeaconEntry>,
+	/// CIDs of the parent tipset, in tipset key order.
+	pub parents: Vec<Vec<u8>>,
+	/// Weight of the parent tipset.
+	pub parent_weight: u128,
+	pub height: u64,
+	pub parent_state_root: Vec<u8>,
+	pub parent_message_receipts: Vec<u8>,
+	/// CID of the `TxMeta` holding the block's message AMTs.
+	pub messages: Vec<u8>,
+	pub timestamp: u64,
+	/// Type prefixed block signature, empty when absent.
+	pub block_sig: Vec<u8>,
+}
+
+impl BlockHeader {
+	/// Decodes a header from its DAG-CBOR encoding.
+	pub fn from_cbor(raw: &[u8]) -> Result<Self, CborError> {
+		let mut d = Decoder::new(raw);
+		let fields = d.array()?;
+		if fields < MIN_HEADER_FIELDS {
+			return Err(CborError::MissingField);
+		}
+
+		let miner = d.bytes()?.to_vec();
+		let ticket = if d.null() {
+			Vec::new()
+		} else {
+			d.array()?;
+			d.bytes()?.to_vec()
+		};
+		let election_proof = if d.null() {
+			None
+		} else {
+			d.array()?;
+			let win_count = d.int()?;
+			let vrf_proof = d.bytes()?.to_vec();
+			Some(ElectionProof { win_count, vrf_proof })
+		};
+		let mut beacon_entries = Vec::new();
+		for _ in 0..d.array()? {
+			d.array()?;
+			let round = d.uint()?;
+			let data = d.bytes()?.to_vec();
+			beacon_entries.push(BeaconEntry { round, data });
+		}
+		// WinPoSt proofs are only checked by full nodes.
+		d.skip()?;
+		let mut parents = Vec::new();
+		for _ in 0..d.array()? {
+			parents.push(d.cid()?);
+		}
+		let parent_weight = cbor::big_uint(d.bytes()?)?;
+		let height = d.int()?;
+		if height < 0 {
+			return Err(CborError::Overflow);
+		}
+		let parent_state_root = d.cid()?;
+		let parent_message_receipts = d.cid()?;
+		let messages = d.cid()?;
+		// BLS aggregate
+		d.skip()?;
+		let timestamp = d.uint()?;
+		let block_sig = d.bytes_or_null()?.to_vec();
+		// Fork signaling, the parent base fee and any later fields.
+		for _ in 14..fields {
+			d.skip()?;
+		}
+		d.finish()?;
+
+		Ok(BlockHeader {
+			miner,
+			ticket,
+			election_proof,
+			beacon_entries,
+			parents,
+			parent_weight,
+			height: height as u64,
+			parent_state_root,
+			parent_message_receipts,
+			messages,
+			timestamp,
+			block_sig,
+		})
+	}
+
+	/// Digest of the ticket, the blocks of a tipset are ordered by it.
+	pub fn ticket_digest(&self) -> H256 {
+		sp_io::hashing::blake2_256(&self.ticket).into()
+	}
+}
+
+/// Identifies a tipset by the concatenation of its block CIDs, like Lotus does.
+pub fn tipset_hash(key: &[Vec<u8>]) -> H256 {
+	sp_io::hashing::blake2_256(&key.concat()).into()
+}
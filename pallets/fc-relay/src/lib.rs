@@ -0,0 +1,700 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+//! Filecoin header relay.
+//!
+//! Relayers submit Filecoin tipsets as raw DAG-CBOR block headers. The pallet
+//! computes the CIDs itself, checks that every tipset links to a known parent,
+//! that heights and weights increase along the chain, and follows the heaviest
+//! chain it has seen. Once F3 is set up, tipsets buried `FinalityDepth` epochs
+//! below the best one are final; entries older than `HistoryDepth` epochs behind
+//! finality are pruned. Weights are taken from the headers rather than recomputed
+//! from the miners' power, so depth alone finalizes nothing before that: a single
+//! registered miner could forge a heavier chain.
+//!
+//! Every block is checked by `T::Verifier` before import: its BLS signature by
+//! the miner's worker key, its drand entries, ticket and election proof. Worker
+//! keys are registered by root, as the relay does not follow the miners' state.
+//!
+//! Nothing is accepted on the relayer's word: the only trusted input is the
+//! checkpoint tipset set by root.
+//!
+//! Once root has set an F3 power table, fast finality certificates are
+//! followed as well. Every accepted certificate finalizes the tipsets it
+//! decides, and deposits are only credited from tipsets it covers. A certified
+//! tipset that is not canonical yet becomes so with the next tipset relayed on
+//! top of it, however light, and tipsets leaving it out are refused.
+//!
+//! Other pallets use `verify_message` to check that a Filecoin message was
+//! executed successfully in a final tipset, given the raw IPLD blocks proving
+//! its inclusion and its receipt, or `verify_execution` to learn its exit code.
+
+use frame_support::{decl_module, decl_storage, decl_event, decl_error, ensure, dispatch::DispatchResult,
+					weights::Weight, traits::Get};
+use frame_system::{ensure_signed, ensure_root};
+use codec::{Decode, Encode};
+use sp_core::H256;
+use sp_runtime::{DispatchError, RuntimeDebug};
+use sp_std::prelude::*;
+
+#[cfg(test)]
+mod mock;
+
+#[cfg(test)]
+mod tests;
+
+mod benchmarking;
+pub mod weights;
+
+pub mod amt;
+pub mod cbor;
+pub mod f3;
+pub mod header;
+pub mod message;
+pub mod verify;
+pub use header::{BlockHeader, BeaconEntry, ElectionProof, tipset_hash};
+pub use message::{UnsignedMessage, MessageReceipt, secp256k1_address, delegated_address, is_id_address};
+
+use amt::{BlockStore, ProofError};
+use f3::{F3Error, FinalityCertificate, PowerEntry};
+use verify::{Context, HeaderVerifier, VerifyError};
+pub use weights::WeightInfo;
+
+/// Number of tipsets searched back for the latest beacon entry, like Lotus does.
+const MAX_BEACON_LOOKBACK: usize = 20;
+
+/// Upper bound of heights pruned by a single import, so that catching up after
+/// a long outage is spread over several blocks.
+const MAX_PRUNED_HEIGHTS: u64 = 64;
+
+/// A tipset known to the relay.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Default, RuntimeDebug)]
+pub struct TipsetInfo {
+	/// CIDs of the blocks, ordered by ticket.
+	pub key: Vec<Vec<u8>>,
+	/// Hash of the parent tipset key.
+	pub parent: H256,
+	pub height: u64,
+	/// Weight of the parent tipset as carried by the block headers.
+	///
+	/// A tipset's own weight is only revealed by its children, so tipsets are
+	/// compared by this value instead.
+	pub parent_weight: u128,
+}
+
+/// Proof that a message was executed in a relayed tipset.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Default, RuntimeDebug)]
+pub struct MessageProof {
+	/// Hash of the key of the tipset including the message.
+	pub tipset: H256,
+	/// CID of the message as included in the block, the signed message for secp256k1 senders.
+	pub message: Vec<u8>,
+	/// Raw IPLD blocks: the `TxMeta`, message AMT nodes and messages of every block
+	/// of the tipset up to the one including the message, and the nodes on the path
+	/// to its receipt in the receipts AMT of the child tipset.
+	pub blocks: Vec<Vec<u8>>,
+}
+
+/// A message proven to have been executed.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Default, RuntimeDebug)]
+pub struct ExecutedMessage {
+	pub cid: Vec<u8>,
+	pub message: UnsignedMessage,
+	/// Tipset the message was included in.
+	pub tipset: H256,
+	pub height: u64,
+	pub exit_code: i64,
+	pub gas_used: i64,
+}
+
+pub trait Trait: frame_system::Trait {
+	type Event: From<Event<Self>> + Into<<Self as frame_system::Trait>::Event>;
+
+	/// Number of epochs a tipset has to be buried under the best tipset to be final.
+	type FinalityDepth: Get<u64>;
+
+	/// Number of epochs kept below the finalized height before being pruned.
+	type HistoryDepth: Get<u64>;
+
+	/// Maximum number of blocks accepted in a single tipset.
+	type MaxTipsetSize: Get<u32>;
+
+	/// Consensus checks of submitted headers.
+	type Verifier: HeaderVerifier;
+
+	/// Network name F3 participants sign with, `filecoin` on mainnet.
+	type F3NetworkName: Get<Vec<u8>>;
+
+	/// Weights of the calls, from the benchmarks.
+	type WeightInfo: WeightInfo;
+}
+
+decl_storage! {
+	trait Store for Module<T: Trait> as FcRelay {
+		/// Decoded headers of all tipsets that have not been pruned, by block CID.
+		pub Headers get(fn header): map hasher(blake2_128_concat) Vec<u8> => Option<BlockHeader>;
+
+		/// Tipsets by the hash of their key.
+		pub Tipsets get(fn tipset): map hasher(identity) H256 => Option<TipsetInfo>;
+
+		/// Tipsets imported at each height, canonical or not. Used for pruning.
+		TipsetsAtHeight: map hasher(twox_64_concat) u64 => Vec<H256>;
+
+		/// The canonical chain by height. Null rounds have no entry.
+		pub CanonicalChain get(fn canonical_at): map hasher(twox_64_concat) u64 => Option<H256>;
+
+		/// Head of the heaviest chain.
+		pub BestTipset get(fn best_tipset): H256;
+		pub BestHeight get(fn best_height): u64;
+		pub BestWeight get(fn best_weight): u128;
+
+		/// Canonical tipsets at or below this height are final.
+		pub FinalizedHeight get(fn finalized_height): u64;
+
+		/// Lowest height that still has to be pruned.
+		OldestHeight get(fn oldest_height): u64;
+
+		/// Whether a checkpoint has been set.
+		pub Initialized get(fn initialized): bool;
+
+		/// BLS public keys of the miners' workers, by miner address.
+		pub MinerWorkers get(fn miner_worker): map hasher(blake2_128_concat) Vec<u8> => Option<Vec<u8>>;
+
+		/// Power table of the next F3 instance. Empty until set by root.
+		pub F3PowerTable get(fn f3_power_table): Vec<PowerEntry>;
+
+		/// The next F3 instance a certificate is expected for.
+		pub F3Instance get(fn f3_instance): u64;
+
+		/// Height and tipset of the head decided by the last accepted certificate.
+		pub F3Finalized get(fn f3_finalized): Option<(u64, H256)>;
+
+		/// Height of the last certified tipset that became final on the canonical chain.
+		pub F3CanonicalHeight get(fn f3_canonical_height): u64;
+	}
+}
+
+decl_event!(
+	pub enum Event<T> where AccountId = <T as frame_system::Trait>::AccountId {
+		/// A trusted checkpoint was set. [tipset, height]
+		CheckpointSet(H256, u64),
+		/// A tipset was imported. [relayer, tipset, height]
+		TipsetImported(AccountId, H256, u64),
+		/// The heaviest chain has a new head. [tipset, height]
+		NewBestTipset(H256, u64),
+		/// Tipsets up to this height are final. [height]
+		Finalized(u64),
+		/// The worker key of a miner was set or removed. [miner]
+		MinerWorkerSet(Vec<u8>),
+		/// The F3 power table was set by root. [instance]
+		F3PowerTableSet(u64),
+		/// An F3 certificate was accepted. [instance, tipset, height]
+		F3Certified(u64, H256, u64),
+	}
+);
+
+decl_error! {
+	pub enum Error for Module<T: Trait> {
+		/// A header could not be decoded.
+		InvalidHeader,
+		/// A tipset must contain at least one block.
+		EmptyTipset,
+		/// The tipset has more blocks than `MaxTipsetSize`.
+		TooManyBlocks,
+		/// The same block appears twice in a tipset.
+		DuplicateBlock,
+		/// Blocks of a tipset must share the same height.
+		MismatchedHeight,
+		/// Blocks of a tipset must share the same parents and parent weight.
+		MismatchedParents,
+		/// No checkpoint has been set yet.
+		NotInitialized,
+		/// The tipset has already been imported.
+		TipsetAlreadyKnown,
+		/// The parent tipset is not known to the relay.
+		UnknownParent,
+		/// A tipset must be higher than its parent.
+		HeightNotIncreasing,
+		/// A tipset must be heavier than its parent.
+		WeightNotIncreasing,
+		/// Tipsets at or below the finalized height cannot be imported.
+		BelowFinality,
+		/// Switching to the tipset would revert final tipsets.
+		ReorgBelowFinality,
+		/// The tipset is on a fork that leaves out the tipset certified by F3.
+		DropsCertifiedTipset,
+		/// No worker key is registered for the block's miner.
+		UnknownMiner,
+		/// There is no beacon entry to check the block's entries against.
+		MissingBeacon,
+		/// A drand entry is out of order or not signed by the drand network of its height.
+		InvalidBeacon,
+		/// The block is not signed by the miner's worker.
+		InvalidBlockSignature,
+		/// The ticket is not a valid VRF output of the miner's worker.
+		InvalidTicket,
+		/// The election proof is not a valid winning VRF output of the miner's worker.
+		InvalidElectionProof,
+		/// The tipset is not known to the relay.
+		UnknownTipset,
+		/// The tipset executing the message and its child are not final yet.
+		TipsetNotFinal,
+		/// The child tipset holding the receipts has not been relayed.
+		ReceiptsNotRelayed,
+		/// A block of the proof is malformed.
+		InvalidProof,
+		/// The proof lacks a block needed to verify it.
+		IncompleteProof,
+		/// The message is not included in the tipset.
+		MessageNotIncluded,
+		/// The message is included but was not executed.
+		MessageNotExecuted,
+		/// No receipt exists for the message.
+		MissingReceipt,
+		/// The message was executed with a non-zero exit code.
+		MessageFailed,
+		/// No F3 power table has been set.
+		F3NotInitialized,
+		/// The certificate is not for the next instance.
+		WrongInstance,
+		/// The certified chain does not extend the head of the previous certificate.
+		F3ChainMismatch,
+		/// The certified chain is empty or its epochs do not increase.
+		InvalidF3Chain,
+		/// A signer of the certificate is not in the power table.
+		InvalidF3Signer,
+		/// A key of the power table is not a valid BLS public key.
+		InvalidF3Key,
+		/// The signers hold no more than two thirds of the power.
+		NotEnoughPower,
+		/// The aggregated signature of the certificate is invalid.
+		InvalidF3Signature,
+		/// The power table delta does not apply to the power table.
+		InvalidPowerDelta,
+		/// The power table of the next instance does not match the certificate.
+		PowerTableMismatch,
+	}
+}
+
+decl_module! {
+	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+		type Error = Error<T>;
+
+		fn deposit_event() = default;
+
+		const FinalityDepth: u64 = T::FinalityDepth::get();
+		const HistoryDepth: u64 = T::HistoryDepth::get();
+		const MaxTipsetSize: u32 = T::MaxTipsetSize::get();
+
+		/// Set the trusted tipset the relay builds on.
+		///
+		/// Once initialized the checkpoint may only be moved above the finalized height.
+		#[weight = 10_000_000 * (headers.len() as Weight + 1)]
+		pub fn set_checkpoint(origin, headers: Vec<Vec<u8>>) -> DispatchResult {
+			ensure_root(origin)?;
+			let (hash, info, blocks) = Self::decode_tipset(&headers)?;
+			ensure!(!Initialized::get() || info.height > FinalizedHeight::get(), Error::<T>::BelowFinality);
+
+			if Initialized::get() {
+				// Nothing above the finalized height is known to lead to the new checkpoint.
+				for height in FinalizedHeight::get() + 1..=BestHeight::get().max(info.height) {
+					CanonicalChain::remove(height);
+				}
+			} else {
+				OldestHeight::put(info.height);
+				Initialized::put(true);
+			}
+
+			let height = info.height;
+			CanonicalChain::insert(height, hash);
+			BestTipset::put(hash);
+			BestHeight::put(height);
+			BestWeight::put(info.parent_weight);
+			FinalizedHeight::put(height);
+			Self::store_tipset(hash, info, blocks);
+
+			Self::deposit_event(RawEvent::CheckpointSet(hash, height));
+			Ok(())
+		}
+
+		/// Submit a tipset as the DAG-CBOR encoded headers of its blocks.
+		///
+		/// Weighted by the pairing checks of every block and beacon entry.
+		#[weight = T::WeightInfo::submit_tipset(headers.len() as u32, beacon_entry_count(headers))]
+		pub fn submit_tipset(origin, headers: Vec<Vec<u8>>) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+			ensure!(Initialized::get(), Error::<T>::NotInitialized);
+			let (hash, info, blocks) = Self::decode_tipset(&headers)?;
+			// The cheap checks come first, the pairings only run for tipsets that could be imported.
+			let parent = Self::check_linkage(hash, &info)?;
+			Self::verify_tipset(&headers, &info, &parent, &blocks)?;
+			let height = info.height;
+			Self::import_tipset(hash, info, parent, blocks)?;
+
+			Self::deposit_event(RawEvent::TipsetImported(who, hash, height));
+			Ok(())
+		}
+
+		/// Set the BLS worker key blocks of a miner are checked against, `None` to remove it.
+		#[weight = 10_000_000]
+		pub fn set_miner_worker(origin, miner: Vec<u8>, worker: Option<Vec<u8>>) -> DispatchResult {
+			ensure_root(origin)?;
+			match worker {
+				Some(key) => MinerWorkers::insert(&miner, key),
+				None => MinerWorkers::remove(&miner),
+			}
+			Self::deposit_event(RawEvent::MinerWorkerSet(miner));
+			Ok(())
+		}
+
+		/// Set the power table of an F3 instance, from which certificates are followed.
+		#[weight = 10_000_000 * (table.len() as Weight + 1)]
+		pub fn set_f3_power_table(origin, instance: u64, table: Vec<PowerEntry>) -> DispatchResult {
+			ensure_root(origin)?;
+			F3PowerTable::put(table);
+			F3Instance::put(instance);
+			F3Finalized::kill();
+			Self::deposit_event(RawEvent::F3PowerTableSet(instance));
+			Ok(())
+		}
+
+		/// Submit the F3 certificate of the next instance.
+		#[weight = 50_000_000 * (certificate.signers.len() as Weight + 1)]
+		pub fn submit_f3_certificate(origin, certificate: FinalityCertificate) -> DispatchResult {
+			ensure_signed(origin)?;
+			let table = F3PowerTable::get();
+			ensure!(!table.is_empty(), Error::<T>::F3NotInitialized);
+			ensure!(certificate.instance == F3Instance::get(), Error::<T>::WrongInstance);
+			if let (Some((_, head)), Some(base)) = (F3Finalized::get(), certificate.ec_chain.first()) {
+				ensure!(H256(sp_io::hashing::blake2_256(&base.key)) == head, Error::<T>::F3ChainMismatch);
+			}
+
+			let next = f3::verify_certificate(&T::F3NetworkName::get(), &table, &certificate)
+				.map_err(Self::f3_error)?;
+			let head = certificate.head().ok_or(Error::<T>::InvalidF3Chain)?;
+			let hash = H256(sp_io::hashing::blake2_256(&head.key));
+			let height = head.epoch;
+
+			F3PowerTable::put(next);
+			F3Instance::put(certificate.instance + 1);
+			F3Finalized::put((height, hash));
+			// Certified tipsets the relay follows become final, others once relayed on.
+			if Self::is_canonical(&hash) {
+				Self::finalize_certified(height);
+			}
+			Self::deposit_event(RawEvent::F3Certified(certificate.instance, hash, height));
+			Ok(())
+		}
+	}
+}
+
+/// Number of beacon entries the headers of a tipset carry, each block's are
+/// checked on their own.
+fn beacon_entry_count(headers: &[Vec<u8>]) -> u32 {
+	headers.iter()
+		.filter_map(|raw| BlockHeader::from_cbor(raw).ok())
+		.map(|header| header.beacon_entries.len() as u32)
+		.fold(0, u32::saturating_add)
+}
+
+impl<T: Trait> Module<T> {
+	/// Decodes the headers of a tipset and checks they form one.
+	fn decode_tipset(headers: &[Vec<u8>]) -> Result<(H256, TipsetInfo, Vec<(Vec<u8>, BlockHeader)>), Error<T>> {
+		ensure!(!headers.is_empty(), Error::<T>::EmptyTipset);
+		ensure!(headers.len() <= T::MaxTipsetSize::get() as usize, Error::<T>::TooManyBlocks);
+
+		let mut blocks = headers.iter()
+			.map(|raw| BlockHeader::from_cbor(raw).map(|h| (cbor::cid_of(raw), h)))
+			.collect::<Result<Vec<_>, _>>()
+			.map_err(|_| Error::<T>::InvalidHeader)?;
+
+		let (_, first) = &blocks[0];
+		for (_, block) in blocks.iter().skip(1) {
+			ensure!(block.height == first.height, Error::<T>::MismatchedHeight);
+			ensure!(block.parents == first.parents && block.parent_weight == first.parent_weight,
+				Error::<T>::MismatchedParents);
+		}
+
+		blocks.sort_by(|(a_cid, a), (b_cid, b)| {
+			a.ticket_digest().cmp(&b.ticket_digest()).then_with(|| a_cid.cmp(b_cid))
+		});
+		let key: Vec<Vec<u8>> = blocks.iter().map(|(cid, _)| cid.clone()).collect();
+		ensure!(key.windows(2).all(|w| w[0] != w[1]), Error::<T>::DuplicateBlock);
+
+		let (_, first) = &blocks[0];
+		let info = TipsetInfo {
+			parent: tipset_hash(&first.parents),
+			height: first.height,
+			parent_weight: first.parent_weight,
+			key,
+		};
+		Ok((tipset_hash(&info.key), info, blocks))
+	}
+
+	/// Checks the tipset is new and extends a known parent, which it returns.
+	fn check_linkage(hash: H256, info: &TipsetInfo) -> Result<TipsetInfo, Error<T>> {
+		ensure!(!Tipsets::contains_key(hash), Error::<T>::TipsetAlreadyKnown);
+		ensure!(info.height > FinalizedHeight::get(), Error::<T>::BelowFinality);
+		let parent = Tipsets::get(info.parent).ok_or(Error::<T>::UnknownParent)?;
+		ensure!(info.height > parent.height, Error::<T>::HeightNotIncreasing);
+		ensure!(info.parent_weight > parent.parent_weight, Error::<T>::WeightNotIncreasing);
+		Ok(parent)
+	}
+
+	/// Runs the consensus checks of every block against its parent chain.
+	fn verify_tipset(headers: &[Vec<u8>], info: &TipsetInfo, parent: &TipsetInfo, blocks: &[(Vec<u8>, BlockHeader)])
+		-> DispatchResult
+	{
+		let parent_ticket = Headers::get(&parent.key[0]).map(|h| h.ticket).unwrap_or_default();
+		let prev_beacon = Self::latest_beacon(info.parent);
+
+		for raw in headers {
+			let cid = cbor::cid_of(raw);
+			let (_, header) = blocks.iter()
+				.find(|(c, _)| *c == cid)
+				.ok_or(Error::<T>::InvalidHeader)?;
+			let worker = MinerWorkers::get(&header.miner);
+			let context = Context {
+				worker_key: worker.as_ref().map(|k| k.as_slice()),
+				prev_beacon: prev_beacon.as_ref(),
+				parent_height: parent.height,
+				parent_ticket: &parent_ticket,
+			};
+			T::Verifier::verify(raw, header, &context).map_err(Self::verify_error)?;
+		}
+		Ok(())
+	}
+
+	fn verify_error(e: VerifyError) -> Error<T> {
+		match e {
+			VerifyError::UnknownMiner => Error::<T>::UnknownMiner,
+			VerifyError::MissingBeacon => Error::<T>::MissingBeacon,
+			VerifyError::InvalidBeacon => Error::<T>::InvalidBeacon,
+			VerifyError::InvalidBlockSignature => Error::<T>::InvalidBlockSignature,
+			VerifyError::InvalidTicket => Error::<T>::InvalidTicket,
+			VerifyError::InvalidElectionProof => Error::<T>::InvalidElectionProof,
+		}
+	}
+
+	/// The last beacon entry included in the chain ending at `hash`.
+	fn latest_beacon(mut hash: H256) -> Option<BeaconEntry> {
+		for _ in 0..MAX_BEACON_LOOKBACK {
+			let info = Tipsets::get(hash)?;
+			let header = Headers::get(&info.key[0])?;
+			if let Some(entry) = header.beacon_entries.last() {
+				return Some(entry.clone());
+			}
+			hash = info.parent;
+		}
+		None
+	}
+
+	/// Imports a tipset that passed `check_linkage`.
+	fn import_tipset(hash: H256, info: TipsetInfo, parent: TipsetInfo, blocks: Vec<(Vec<u8>, BlockHeader)>) -> DispatchResult {
+		// Everything is checked before the first write, a failing call must not
+		// leave partial state behind.
+		let certified = Self::pending_certified().filter(|(height, _)| info.height >= *height);
+		let route = if let Some((_, tipset)) = certified {
+			// The certified tipset is not canonical, so a chain leading to it has to
+			// pass it on the way back to the canonical one.
+			let route = Self::route_to_canonical(info.parent, parent)?;
+			ensure!(hash == tipset || route.1.iter().any(|(_, h)| *h == tipset), Error::<T>::DropsCertifiedTipset);
+			Some(route)
+		} else if info.parent_weight > BestWeight::get() {
+			Some(Self::route_to_canonical(info.parent, parent)?)
+		} else {
+			None
+		};
+
+		let height = info.height;
+		let weight = info.parent_weight;
+		Self::store_tipset(hash, info, blocks);
+
+		if let Some((fork_height, route)) = route {
+			for h in fork_height + 1..=BestHeight::get() {
+				CanonicalChain::remove(h);
+			}
+			for (h, tipset) in route {
+				CanonicalChain::insert(h, tipset);
+			}
+			CanonicalChain::insert(height, hash);
+			BestTipset::put(hash);
+			BestHeight::put(height);
+			BestWeight::put(weight);
+			Self::deposit_event(RawEvent::NewBestTipset(hash, height));
+
+			if let Some((certified, _)) = certified {
+				Self::finalize_certified(certified);
+			}
+			let finalized = height.saturating_sub(T::FinalityDepth::get());
+			if finalized > FinalizedHeight::get() && Self::f3_initialized() {
+				FinalizedHeight::put(finalized);
+				Self::deposit_event(RawEvent::Finalized(finalized));
+				Self::prune(finalized.saturating_sub(T::HistoryDepth::get()));
+			}
+		}
+		Ok(())
+	}
+
+	/// The tipset decided by the last certificate, while the canonical chain leaves
+	/// it out above the finalized height.
+	fn pending_certified() -> Option<(u64, H256)> {
+		F3Finalized::get().filter(|(height, hash)| *height > FinalizedHeight::get() && !Self::is_canonical(hash))
+	}
+
+	/// Finalizes the canonical chain up to a certified tipset on it.
+	fn finalize_certified(height: u64) {
+		F3CanonicalHeight::mutate(|canonical| *canonical = height.max(*canonical));
+		if height > FinalizedHeight::get() {
+			FinalizedHeight::put(height);
+			Self::deposit_event(RawEvent::Finalized(height));
+		}
+	}
+
+	/// Walks back from `hash` to the first canonical ancestor and returns its height
+	/// together with the tipsets that become canonical on the way.
+	fn route_to_canonical(mut hash: H256, mut info: TipsetInfo) -> Result<(u64, Vec<(u64, H256)>), Error<T>> {
+		let finalized = FinalizedHeight::get();
+		let mut route = Vec::new();
+		while CanonicalChain::get(info.height) != Some(hash) {
+			ensure!(info.height > finalized, Error::<T>::ReorgBelowFinality);
+			route.push((info.height, hash));
+			hash = info.parent;
+			info = Tipsets::get(hash).ok_or(Error::<T>::UnknownParent)?;
+		}
+		// Every canonical height above the fork point gets reverted.
+		ensure!(info.height >= finalized, Error::<T>::ReorgBelowFinality);
+		Ok((info.height, route))
+	}
+
+	fn store_tipset(hash: H256, info: TipsetInfo, blocks: Vec<(Vec<u8>, BlockHeader)>) {
+		for (cid, header) in blocks {
+			Headers::insert(cid, header);
+		}
+		TipsetsAtHeight::append(info.height, hash);
+		Tipsets::insert(hash, info);
+	}
+
+	/// Removes everything below `below`, at most `MAX_PRUNED_HEIGHTS` heights at a time.
+	fn prune(below: u64) {
+		let oldest = OldestHeight::get();
+		let until = below.min(oldest.saturating_add(MAX_PRUNED_HEIGHTS));
+		for height in oldest..until {
+			for hash in TipsetsAtHeight::take(height) {
+				if let Some(info) = Tipsets::take(hash) {
+					for cid in info.key {
+						Headers::remove(cid);
+					}
+				}
+			}
+			CanonicalChain::remove(height);
+		}
+		if until > oldest {
+			OldestHeight::put(until);
+		}
+	}
+
+	/// Checks a message was executed successfully in a final tipset.
+	pub fn verify_message(proof: &MessageProof) -> Result<ExecutedMessage, DispatchError> {
+		let executed = Self::verify_execution(proof)?;
+		ensure!(executed.exit_code == 0, Error::<T>::MessageFailed);
+		Ok(executed)
+	}
+
+	/// Checks a message was executed in a final tipset, whatever its exit code.
+	///
+	/// Receipts of a tipset are committed to by its child, which therefore has
+	/// to be relayed and final as well.
+	pub fn verify_execution(proof: &MessageProof) -> Result<ExecutedMessage, DispatchError> {
+		let info = Tipsets::get(proof.tipset).ok_or(Error::<T>::UnknownTipset)?;
+		let child = Self::canonical_child(&proof.tipset, &info).ok_or(Error::<T>::ReceiptsNotRelayed)?;
+		ensure!(child.height <= Self::deposit_final_height(), Error::<T>::TipsetNotFinal);
+
+		let blocks = info.key.iter()
+			.map(|cid| Headers::get(cid))
+			.collect::<Option<Vec<_>>>()
+			.ok_or(Error::<T>::UnknownTipset)?;
+		let receipts = Headers::get(&child.key[0])
+			.ok_or(Error::<T>::ReceiptsNotRelayed)?
+			.parent_message_receipts;
+
+		let store = BlockStore::new(&proof.blocks);
+		let (index, message) = message::execution_index(&store, &blocks, &proof.message)
+			.map_err(Self::proof_error)?;
+		let receipt = amt::get(&store, &receipts, index)
+			.map_err(Self::proof_error)?
+			.ok_or(Error::<T>::MissingReceipt)?;
+		let receipt = MessageReceipt::from_cbor(receipt).map_err(|_| Error::<T>::InvalidProof)?;
+
+		Ok(ExecutedMessage {
+			cid: proof.message.clone(),
+			message,
+			tipset: proof.tipset,
+			height: info.height,
+			exit_code: receipt.exit_code,
+			gas_used: receipt.gas_used,
+		})
+	}
+
+	/// Height up to which canonical tipsets are final for crediting deposits.
+	///
+	/// With F3 set up that is the last certified tipset on the canonical chain,
+	/// which stays there as tipsets leaving it out are refused, otherwise the
+	/// depth based finalized height.
+	pub fn deposit_final_height() -> u64 {
+		if Self::f3_initialized() {
+			F3CanonicalHeight::get()
+		} else {
+			FinalizedHeight::get()
+		}
+	}
+
+	fn f3_initialized() -> bool {
+		F3PowerTable::decode_len().unwrap_or(0) > 0
+	}
+
+	fn f3_error(e: F3Error) -> Error<T> {
+		match e {
+			F3Error::InvalidChain => Error::<T>::InvalidF3Chain,
+			F3Error::InvalidSigner => Error::<T>::InvalidF3Signer,
+			F3Error::InvalidKey => Error::<T>::InvalidF3Key,
+			F3Error::NotEnoughPower => Error::<T>::NotEnoughPower,
+			F3Error::InvalidSignature => Error::<T>::InvalidF3Signature,
+			F3Error::InvalidPowerDelta => Error::<T>::InvalidPowerDelta,
+			F3Error::PowerTableMismatch => Error::<T>::PowerTableMismatch,
+		}
+	}
+
+	fn proof_error(e: ProofError) -> Error<T> {
+		match e {
+			ProofError::Cbor(_) | ProofError::Malformed => Error::<T>::InvalidProof,
+			ProofError::MissingBlock => Error::<T>::IncompleteProof,
+			ProofError::NotIncluded => Error::<T>::MessageNotIncluded,
+			ProofError::NotExecuted => Error::<T>::MessageNotExecuted,
+		}
+	}
+
+	/// The canonical tipset built on `hash`, skipping null rounds.
+	fn canonical_child(hash: &H256, info: &TipsetInfo) -> Option<TipsetInfo> {
+		if CanonicalChain::get(info.height) != Some(*hash) {
+			return None;
+		}
+		let child = (info.height + 1..=BestHeight::get())
+			.find_map(|height| CanonicalChain::get(height))
+			.and_then(|child| Tipsets::get(child))?;
+		if child.parent == *hash { Some(child) } else { None }
+	}
+
+	/// Whether the tipset is on the canonical chain.
+	pub fn is_canonical(hash: &H256) -> bool {
+		Tipsets::get(hash)
+			.map(|info| CanonicalChain::get(info.height) == Some(*hash))
+			.unwrap_or(false)
+	}
+
+	/// Whether the tipset is on the canonical chain and buried deep enough to be final.
+	pub fn is_final(hash: &H256) -> bool {
+		Tipsets::get(hash)
+			.map(|info| info.height <= FinalizedHeight::get() && CanonicalChain::get(info.height) == Some(*hash))
+			.unwrap_or(false)
+	}
+}
@@ -0,0 +1,193 @@
+//! Filecoin messages and receipts, and the order a tipset executes them in.
+
+use codec::{Decode, Encode};
+use sp_runtime::RuntimeDebug;
+use sp_std::{prelude::*, collections::btree_map::BTreeMap};
+
+use crate::amt::{self, BlockStore, ProofError};
+use crate::cbor::{self, CborError, Decoder};
+use crate::header::BlockHeader;
+
+/// Protocol byte of ID addresses.
+const ID_PROTOCOL: u8 = 0;
+/// Protocol byte of secp256k1 addresses.
+const SECP256K1_PROTOCOL: u8 = 1;
+/// Protocol byte of delegated addresses.
+const DELEGATED_PROTOCOL: u8 = 4;
+/// ID of the Ethereum address manager, the namespace of `f410` addresses.
+const EAM_ACTOR_ID: u8 = 10;
+
+/// `TxMeta`, the two message arrays a block header links to.
+#[derive(Clone, PartialEq, Eq, RuntimeDebug)]
+pub struct TxMeta {
+	pub bls_messages: Vec<u8>,
+	pub secp_messages: Vec<u8>,
+}
+
+impl TxMeta {
+	pub fn from_cbor(raw: &[u8]) -> Result<Self, CborError> {
+		let mut d = Decoder::new(raw);
+		if d.array()? != 2 {
+			return Err(CborError::MissingField);
+		}
+		let bls_messages = d.cid()?;
+		let secp_messages = d.cid()?;
+		d.finish()?;
+		Ok(TxMeta { bls_messages, secp_messages })
+	}
+}
+
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Default, RuntimeDebug)]
+pub struct UnsignedMessage {
+	/// Address bytes of the receiver.
+	pub to: Vec<u8>,
+	/// Address bytes of the sender.
+	pub from: Vec<u8>,
+	pub nonce: u64,
+	/// Amount transferred, in attoFIL.
+	pub value: u128,
+	pub method: u64,
+	pub params: Vec<u8>,
+}
+
+impl UnsignedMessage {
+	pub fn from_cbor(raw: &[u8]) -> Result<Self, CborError> {
+		let mut d = Decoder::new(raw);
+		let message = Self::decode_from(&mut d)?;
+		d.finish()?;
+		Ok(message)
+	}
+
+	/// Decodes the unsigned part of a secp256k1 signed message.
+	pub fn from_signed_cbor(raw: &[u8]) -> Result<Self, CborError> {
+		let mut d = Decoder::new(raw);
+		if d.array()? != 2 {
+			return Err(CborError::MissingField);
+		}
+		let message = Self::decode_from(&mut d)?;
+		d.bytes()?;
+		d.finish()?;
+		Ok(message)
+	}
+
+	fn decode_from(d: &mut Decoder) -> Result<Self, CborError> {
+		if d.array()? != 10 {
+			return Err(CborError::MissingField);
+		}
+		// version
+		d.uint()?;
+		let to = d.bytes()?.to_vec();
+		let from = d.bytes()?.to_vec();
+		let nonce = d.uint()?;
+		let value = cbor::big_uint(d.bytes()?)?;
+		// gas limit, fee cap and premium
+		d.int()?;
+		d.bytes()?;
+		d.bytes()?;
+		let method = d.uint()?;
+		let params = d.bytes()?.to_vec();
+		Ok(UnsignedMessage { to, from, nonce, value, method, params })
+	}
+}
+
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Default, RuntimeDebug)]
+pub struct MessageReceipt {
+	pub exit_code: i64,
+	pub return_data: Vec<u8>,
+	pub gas_used: i64,
+	/// Root of the AMT of events the message emitted, carried by receipts since nv18.
+	pub events_root: Option<Vec<u8>>,
+}
+
+impl MessageReceipt {
+	/// Decodes receipts from before nv18, with three fields, and since, with
+	/// the events root as the fourth.
+	pub fn from_cbor(raw: &[u8]) -> Result<Self, CborError> {
+		let mut d = Decoder::new(raw);
+		let fields = d.array()?;
+		if fields != 3 && fields != 4 {
+			return Err(CborError::MissingField);
+		}
+		let exit_code = d.int()?;
+		let return_data = d.bytes()?.to_vec();
+		let gas_used = d.int()?;
+		let events_root = if fields == 4 && !d.null() { Some(d.cid()?) } else { None };
+		d.finish()?;
+		Ok(MessageReceipt { exit_code, return_data, gas_used, events_root })
+	}
+}
+
+/// Finds the position of `target` among the messages a tipset executes, which
+/// is also the index of its receipt in the child tipset.
+///
+/// Follows Lotus' `MessagesForTipset`: blocks in tipset order, BLS messages
+/// before secp256k1 ones, and a message is skipped unless its nonce is the next
+/// one expected from its sender. Duplicates across blocks are skipped the same way.
+pub fn execution_index(store: &BlockStore, blocks: &[BlockHeader], target: &[u8]) -> Result<(u64, UnsignedMessage), ProofError> {
+	let mut next_nonce: BTreeMap<Vec<u8>, u64> = BTreeMap::new();
+	let mut index = 0u64;
+	let mut found: Option<Result<(u64, UnsignedMessage), ProofError>> = None;
+
+	for header in blocks {
+		let meta = TxMeta::from_cbor(store.get(&header.messages)?)?;
+		for (root, signed) in [(&meta.bls_messages, false), (&meta.secp_messages, true)].iter() {
+			amt::for_each(store, root, |_, value| {
+				let cid = Decoder::new(value).cid()?;
+				let raw = store.get(&cid)?;
+				let message = if *signed {
+					UnsignedMessage::from_signed_cbor(raw)?
+				} else {
+					UnsignedMessage::from_cbor(raw)?
+				};
+
+				let expected = *next_nonce.entry(message.from.clone()).or_insert(message.nonce);
+				if message.nonce != expected {
+					if cid.as_slice() == target {
+						found = Some(Err(ProofError::NotExecuted));
+						return Ok(false);
+					}
+					return Ok(true);
+				}
+				next_nonce.insert(message.from.clone(), expected + 1);
+
+				if cid.as_slice() == target {
+					found = Some(Ok((index, message)));
+					return Ok(false);
+				}
+				index += 1;
+				Ok(true)
+			})?;
+			if let Some(result) = found.take() {
+				return result;
+			}
+		}
+	}
+	Err(ProofError::NotIncluded)
+}
+
+/// The `f1` address controlled by a secp256k1 public key.
+pub fn secp256k1_address(pubkey: &[u8]) -> Vec<u8> {
+	let mut address = vec![SECP256K1_PROTOCOL];
+	address.extend_from_slice(blake2_rfc::blake2b::blake2b(20, &[], pubkey).as_bytes());
+	address
+}
+
+/// The `f410` address of the Ethereum account controlled by a secp256k1 public key.
+pub fn delegated_address(pubkey: &[u8]) -> Vec<u8> {
+	let mut address = vec![DELEGATED_PROTOCOL, EAM_ACTOR_ID];
+	address.extend_from_slice(&sp_io::hashing::keccak_256(pubkey.get(1..).unwrap_or_default())[12..]);
+	address
+}
+
+/// Whether `address` is an `f0` address, an actor ID as unsigned LEB128.
+pub fn is_id_address(address: &[u8]) -> bool {
+	match address.split_first() {
+		Some((&ID_PROTOCOL, id)) if !id.is_empty() && id.len() <= 10 => {
+			let (&last, rest) = id.split_last().expect("not empty");
+			// Minimal, and within a u64.
+			rest.iter().all(|byte| byte & 0x80 != 0) && last & 0x80 == 0
+				&& (last != 0 || rest.is_empty()) && (id.len() < 10 || last == 1)
+		},
+		_ => false,
+	}
+}
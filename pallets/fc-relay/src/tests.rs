@@ -0,0 +1,834 @@
+use crate::{Error, mock::*, cbor, amt, verify::{self, BlsVerifier, Context, HeaderVerifier, VerifyError},
+			f3::{self, ECTipset, FinalityCertificate, PowerEntry, PowerTableDelta},
+			BeaconEntry, BlockHeader, MessageReceipt, tipset_hash, Tipsets, Headers, FinalizedHeight, MessageProof};
+use frame_support::{assert_ok, assert_noop, parameter_types, StorageMap, StorageValue};
+use hex_literal::hex;
+use sp_core::H256;
+use sp_runtime::DispatchError;
+
+// A block of miner f01000 at height 60000 signed with a made up worker key,
+// chaining drand round 1001 from round 1000 of a made up drand network.
+const WORKER_KEY: [u8; 48] = hex!("8c31e818d319497018d537d61703acbadb58192cb0decae6fbd9989ee0bf62f8c6dedb370018ea1dfc027b9b63e30c54");
+const DRAND_KEY: [u8; 48] = hex!("b6024871a617a6f111d2c1ae95cee18fe2e957cc6e5c19f99670681eb1de8542a97050dd877dace5b2e584fb14885d39");
+const PREV_BEACON: [u8; 96] = hex!("
+	b5ae14e35d2591a0ce5f003256a3024ffeb8ec44e91ef1023bcd763e611202495a530ed58c1a47696ebc4eadf3013ee1
+	018e1e5a2c79a0b119dfa7ac8cc6e92c7cc11f6492f5daa909e0de7a4725b8bed2cd7d368c25f6107247d484f57c2e05
+");
+const PARENT_TICKET: [u8; 96] = hex!("
+	972b5d85d940c714daed3be358e51245a5635ac3868f9a88533f6e460920c75b91a62127795d18543e7fee370335bfd7
+	097688f62ecdc1848efc5d41653244b858656fa2b28394d7c65e2077d70b00d10d2142174033a812ba99aa47df26d581
+");
+const SIGNED_HEADER: [u8; 694] = hex!("
+	904300e807815860837911bcd4379c56df5e1b9fc46076961cef1a8ae0b1ffb86e61e18b31931bb970d994d96b6f7b6c
+	87b7f571d78c5302009b0f78d1889f90844dd72a886b79ac656b872cdda94f06ead98bc80160a0987117db4da0fee978
+	e982e26d40910f018201586088bb854eab4767a80ddfa42318b69b67fcc7a9aed0b01c683a5be38d4620f2eafe4ea119
+	95666d779e8d11fecaebf59c125603a14f8c502305aef2e9348fb96026f003defb9a958e423da64203c9f92c0ba6adbd
+	85d0323c58ed4d28d703cace81821903e95860a0bff3c418a63f325cfe5bb397bd0f9f47a01110323b37b6eec995d148
+	b96d68b1ea388805db5aa25403f90f8fc051b815a518c09de6f3292b7f236c1d65723de4f0bbbcacea43bf8550f79787
+	01907041e56b9c19dc7ad70147e90b2f4386e08081d82a5827000171a0e40220d61a38a0f73beda90e8c1dfba731f650
+	03742539f4260694f44e22cabef24a8e4300100019ea60d82a5827000171a0e4022045b0cfc220ceec5b7c1c62c4d419
+	3d38e4eba48e8815729ce75f9c0ab0e4c1c0d82a5827000171a0e4022045b0cfc220ceec5b7c1c62c4d4193d38e4eba4
+	8e8815729ce75f9c0ab0e4c1c0d82a5827000171a0e4022045b0cfc220ceec5b7c1c62c4d4193d38e4eba48e8815729c
+	e75f9c0ab0e4c1c058600000000000000000000000000000000000000000000000000000000000000000000000000000
+	000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000
+	000000000000000000001a5f798740586102ab073a0416f9a895cfbf097cca32f3893b44100175114123f80505bb6550
+	fad28f59b97a4b786f9c52000f47e75b7db70c9097942c18570f3d5d9a5a4ed1b1ba5ce24552d8ec248edf514054fe32
+	56c224ffd15c9e2104382ccbc34c2b9e9ef800420064
+");
+
+// Made up F3 participants and their BDN signature of the certificate deciding
+// heights 10 to 12 of the chain built by `checkpoint` and `extend`, aggregated
+// with kyber's coefficients by a local signer rather than recorded from Lotus.
+const F3_KEYS: [[u8; 48]; 5] = [
+	hex!("a7b9a71c54b44f6738a77f457af08dc79f09826193197a53c1c880f15963c716cec9ff0fd0bcb8ab41bc2fe89c2711fa"),
+	hex!("b8f1a9edf68006f913b5377a0f37bed80efadc4d6bf9f1523e83b2311e14219c6aa0b8aaee79e47a9977e880bad37a8e"),
+	hex!("899729f080571e25fee93538eb21304a10600d5ceb9807959d78c3967d9ba32b570d4f4105626e5972ccf2e24b723604"),
+	hex!("ab23c89f138f4252fc3922e24b7254743af1259fa1aeae90e98315c664c50800cecfc72a4d45ee772f73c4bb22b8646f"),
+	hex!("b8357a39c42f80953e8bc9908cb6b79c1a5c50ed3bbc0e330577a215ac850e601909fa5b53bed90c744e0355863eaa6e"),
+];
+const F3_SIGNATURE: [u8; 96] = hex!("
+	88bad9a8bce89413fd96d625f6394041386696b42488218f71dcd593de7847f7a326e811fff0785d9999e03303741d25
+	01bafc577e5172606dc25cc196e2a3abc8df5ece389156505775cd6486c33a9ede5753455e026e1a9d6a90f67f79b3f4
+");
+
+// A made-up quicknet key with signatures of three rounds, since the real
+// network's secret is not ours to sign with.
+const QUICKNET_KEY: [u8; 96] = hex!("
+	8bdceb76763869330ca305cfb43fc778621e08264de9d4789c860fbb279cfbb2d524a3c9154867590f5e8cb29c6df358
+	059b9e5562ba83a11688579cbee1e9a2478fce451d1b19007eb9f83fc0be39724417da33b6d5a6a6fad4cfa34576efdf
+");
+const QUICKNET_SIGNATURES: [(u64, [u8; 48]); 3] = [
+	(5_000_000, hex!("a15fe794b1ddb21ce294a5c7e30e3c0eba28d6b3c4bc3c3d0c94a21f28a6b0fa344857f195fb813b37fbe4b030518f48")),
+	(5_000_001, hex!("aa8b86215282ec8c0800c8b439f188082e1149d0d0c71651aa53a35b6c6a8696fd92906371d33583a9dadc6d8aeea167")),
+	(5_000_003, hex!("b2927827ff14c4696af60a8ab9fa730056ca1a312d5c1dddc998dc60dc76fda547c800d8a23419d8564e2e9a8f5fa289")),
+];
+
+parameter_types! {
+	pub DrandKey: Vec<u8> = DRAND_KEY.to_vec();
+	pub const SmokeHeight: u64 = 51_000;
+	pub QuicknetKey: Vec<u8> = QUICKNET_KEY.to_vec();
+	pub const QuicknetHeight: u64 = 3_855_480;
+}
+
+type Verifier = BlsVerifier<DrandKey, SmokeHeight, QuicknetKey, QuicknetHeight>;
+
+fn head(out: &mut Vec<u8>, major: u8, v: u64) {
+	let major = major << 5;
+	match v {
+		0..=23 => out.push(major | v as u8),
+		24..=0xff => out.extend_from_slice(&[major | 24, v as u8]),
+		0x100..=0xffff => {
+			out.push(major | 25);
+			out.extend_from_slice(&(v as u16).to_be_bytes());
+		},
+		0x1_0000..=0xffff_ffff => {
+			out.push(major | 26);
+			out.extend_from_slice(&(v as u32).to_be_bytes());
+		},
+		_ => {
+			out.push(major | 27);
+			out.extend_from_slice(&v.to_be_bytes());
+		},
+	}
+}
+
+fn bytes(out: &mut Vec<u8>, b: &[u8]) {
+	head(out, 2, b.len() as u64);
+	out.extend_from_slice(b);
+}
+
+fn link(out: &mut Vec<u8>, cid: &[u8]) {
+	head(out, 6, 42);
+	let mut prefixed = vec![0u8];
+	prefixed.extend_from_slice(cid);
+	bytes(out, &prefixed);
+}
+
+fn big(w: u128) -> Vec<u8> {
+	if w == 0 {
+		return vec![];
+	}
+	let be = w.to_be_bytes();
+	let start = be.iter().position(|b| *b != 0).unwrap();
+	let mut out = vec![0u8];
+	out.extend_from_slice(&be[start..]);
+	out
+}
+
+/// Encodes a header the way Lotus does, with placeholder proofs and roots.
+fn header(height: u64, parents: &[Vec<u8>], weight: u128, ticket: u8) -> Vec<u8> {
+	let root = cbor::cid_of(&[0x80]);
+	header_with(height, parents, weight, ticket, &root, &root)
+}
+
+fn header_with(height: u64, parents: &[Vec<u8>], weight: u128, ticket: u8, receipts: &[u8], messages: &[u8]) -> Vec<u8> {
+	let root = cbor::cid_of(&[0x80]);
+	let mut out = Vec::new();
+	head(&mut out, 4, 16);
+	bytes(&mut out, &[0x00, 0xe8, 0x07]);
+	head(&mut out, 4, 1);
+	bytes(&mut out, &[ticket; 32]);
+	head(&mut out, 4, 2);
+	head(&mut out, 0, 1);
+	bytes(&mut out, &[7u8; 32]);
+	head(&mut out, 4, 0);
+	head(&mut out, 4, 0);
+	head(&mut out, 4, parents.len() as u64);
+	for parent in parents {
+		link(&mut out, parent);
+	}
+	bytes(&mut out, &big(weight));
+	head(&mut out, 0, height);
+	link(&mut out, &root);
+	link(&mut out, receipts);
+	link(&mut out, messages);
+	out.push(0xf6);
+	head(&mut out, 0, 1_600_000_000 + height * 30);
+	out.push(0xf6);
+	head(&mut out, 0, 0);
+	bytes(&mut out, &big(100));
+	out
+}
+
+/// Encodes an AMT of at most 64 values and returns its root CID and blocks.
+fn amt(values: &[Vec<u8>]) -> (Vec<u8>, Vec<Vec<u8>>) {
+	fn node(out: &mut Vec<u8>, bitmap: u8, links: &[Vec<u8>], values: &[Vec<u8>]) {
+		head(out, 4, 3);
+		bytes(out, &[bitmap]);
+		head(out, 4, links.len() as u64);
+		for l in links {
+			link(out, l);
+		}
+		head(out, 4, values.len() as u64);
+		for v in values {
+			out.extend_from_slice(v);
+		}
+	}
+	fn full(n: usize) -> u8 {
+		(((1u16 << n) - 1) & 0xff) as u8
+	}
+
+	let mut blocks = Vec::new();
+	let mut root = Vec::new();
+	head(&mut root, 4, 3);
+	if values.len() <= 8 {
+		head(&mut root, 0, 0);
+		head(&mut root, 0, values.len() as u64);
+		node(&mut root, full(values.len()), &[], values);
+	} else {
+		let mut links = Vec::new();
+		for chunk in values.chunks(8) {
+			let mut leaf = Vec::new();
+			node(&mut leaf, full(chunk.len()), &[], chunk);
+			links.push(cbor::cid_of(&leaf));
+			blocks.push(leaf);
+		}
+		head(&mut root, 0, 1);
+		head(&mut root, 0, values.len() as u64);
+		node(&mut root, full(links.len()), &links, &[]);
+	}
+	let cid = cbor::cid_of(&root);
+	blocks.push(root);
+	(cid, blocks)
+}
+
+fn message(from: u8, nonce: u64, value: u128) -> Vec<u8> {
+	let mut out = Vec::new();
+	head(&mut out, 4, 10);
+	head(&mut out, 0, 0);
+	bytes(&mut out, &[0x01, 0xaa]);
+	bytes(&mut out, &[0x01, from]);
+	head(&mut out, 0, nonce);
+	bytes(&mut out, &big(value));
+	head(&mut out, 0, 1_000_000);
+	bytes(&mut out, &big(100));
+	bytes(&mut out, &big(1));
+	head(&mut out, 0, 0);
+	bytes(&mut out, b"memo");
+	out
+}
+
+fn signed(message: Vec<u8>) -> Vec<u8> {
+	let mut out = Vec::new();
+	head(&mut out, 4, 2);
+	out.extend_from_slice(&message);
+	bytes(&mut out, &[1u8; 66]);
+	out
+}
+
+/// A receipt as written since nv18, without events.
+fn receipt(exit_code: u64) -> Vec<u8> {
+	let mut out = Vec::new();
+	head(&mut out, 4, 4);
+	head(&mut out, 0, exit_code);
+	bytes(&mut out, &[]);
+	head(&mut out, 0, 500);
+	out.push(0xf6);
+	out
+}
+
+fn links(cids: &[Vec<u8>]) -> Vec<Vec<u8>> {
+	cids.iter().map(|cid| {
+		let mut out = Vec::new();
+		link(&mut out, cid);
+		out
+	}).collect()
+}
+
+/// Key of a single block tipset.
+fn key_of(raw: &[u8]) -> Vec<Vec<u8>> {
+	vec![cbor::cid_of(raw)]
+}
+
+fn checkpoint() -> Vec<Vec<u8>> {
+	let raw = header(10, &[cbor::cid_of(b"genesis")], 100, 0);
+	assert_ok!(FcRelay::set_checkpoint(Origin::root(), vec![raw.clone()]));
+	key_of(&raw)
+}
+
+/// Submits a single block tipset and returns its key.
+fn extend(parent: &[Vec<u8>], height: u64, weight: u128) -> Vec<Vec<u8>> {
+	let raw = header(height, parent, weight, height as u8);
+	assert_ok!(FcRelay::submit_tipset(Origin::signed(1), vec![raw.clone()]));
+	key_of(&raw)
+}
+
+#[test]
+fn decodes_lotus_header() {
+	let parents = vec![cbor::cid_of(b"a"), cbor::cid_of(b"b")];
+	let raw = header(149_063, &parents, 629_642_112, 9);
+	let decoded = BlockHeader::from_cbor(&raw).unwrap();
+	assert_eq!(decoded.height, 149_063);
+	assert_eq!(decoded.parents, parents);
+	assert_eq!(decoded.parent_weight, 629_642_112);
+	assert_eq!(decoded.ticket, vec![9u8; 32]);
+	assert_eq!(decoded.miner, vec![0x00, 0xe8, 0x07]);
+	assert_eq!(decoded.election_proof.unwrap().win_count, 1);
+	assert!(decoded.block_sig.is_empty());
+
+	let mut truncated = raw.clone();
+	truncated.pop();
+	assert!(BlockHeader::from_cbor(&truncated).is_err());
+	let mut trailing = raw;
+	trailing.push(0);
+	assert_eq!(BlockHeader::from_cbor(&trailing), Err(cbor::CborError::TrailingData));
+}
+
+#[test]
+fn header_cid_is_dag_cbor_blake2b() {
+	let cid = cbor::cid_of(b"block");
+	assert_eq!(&cid[..6], &cbor::DAG_CBOR_BLAKE2B_PREFIX);
+	assert_eq!(&cid[6..], &sp_io::hashing::blake2_256(b"block")[..]);
+}
+
+#[test]
+fn requires_checkpoint() {
+	new_test_ext().execute_with(|| {
+		let raw = header(10, &[cbor::cid_of(b"genesis")], 100, 0);
+		assert_noop!(FcRelay::submit_tipset(Origin::signed(1), vec![raw.clone()]), Error::<Test>::NotInitialized);
+		assert_noop!(FcRelay::set_checkpoint(Origin::signed(1), vec![raw]), DispatchError::BadOrigin);
+	});
+}
+
+#[test]
+fn extends_canonical_chain() {
+	new_test_ext().execute_with(|| {
+		let cp = checkpoint();
+		let a = extend(&cp, 11, 110);
+		// null round at 12
+		let b = extend(&a, 13, 120);
+
+		assert_eq!(FcRelay::best_height(), 13);
+		assert_eq!(FcRelay::best_tipset(), tipset_hash(&b));
+		assert_eq!(FcRelay::canonical_at(11), Some(tipset_hash(&a)));
+		assert_eq!(FcRelay::canonical_at(12), None);
+		assert!(FcRelay::is_canonical(&tipset_hash(&a)));
+		assert!(FcRelay::header(&b[0]).is_some());
+	});
+}
+
+#[test]
+fn rejects_broken_linkage() {
+	new_test_ext().execute_with(|| {
+		let cp = checkpoint();
+		let unknown = key_of(&header(10, &[cbor::cid_of(b"other")], 100, 1));
+		assert_noop!(
+			FcRelay::submit_tipset(Origin::signed(1), vec![header(11, &unknown, 110, 1)]),
+			Error::<Test>::UnknownParent
+		);
+		assert_noop!(
+			FcRelay::submit_tipset(Origin::signed(1), vec![header(11, &cp, 100, 1)]),
+			Error::<Test>::WeightNotIncreasing
+		);
+		let a = extend(&cp, 11, 110);
+		assert_noop!(
+			FcRelay::submit_tipset(Origin::signed(1), vec![header(11, &a, 120, 2)]),
+			Error::<Test>::HeightNotIncreasing
+		);
+		assert_noop!(
+			FcRelay::submit_tipset(Origin::signed(1), vec![b"junk".to_vec()]),
+			Error::<Test>::InvalidHeader
+		);
+	});
+}
+
+#[test]
+fn checks_tipset_consistency() {
+	new_test_ext().execute_with(|| {
+		let cp = checkpoint();
+		let a = extend(&cp, 11, 110);
+
+		assert_noop!(
+			FcRelay::submit_tipset(Origin::signed(1), vec![]),
+			Error::<Test>::EmptyTipset
+		);
+		assert_noop!(
+			FcRelay::submit_tipset(Origin::signed(1), vec![header(12, &a, 120, 1), header(13, &a, 120, 2)]),
+			Error::<Test>::MismatchedHeight
+		);
+		assert_noop!(
+			FcRelay::submit_tipset(Origin::signed(1), vec![header(12, &a, 120, 1), header(12, &cp, 120, 2)]),
+			Error::<Test>::MismatchedParents
+		);
+		assert_noop!(
+			FcRelay::submit_tipset(Origin::signed(1), vec![header(12, &a, 120, 1), header(12, &a, 120, 1)]),
+			Error::<Test>::DuplicateBlock
+		);
+
+		// The tipset key does not depend on the order blocks are submitted in.
+		let (x, y) = (header(12, &a, 120, 1), header(12, &a, 120, 2));
+		assert_ok!(FcRelay::submit_tipset(Origin::signed(1), vec![y.clone(), x.clone()]));
+		assert_noop!(
+			FcRelay::submit_tipset(Origin::signed(1), vec![x, y]),
+			Error::<Test>::TipsetAlreadyKnown
+		);
+		let key = Tipsets::get(FcRelay::best_tipset()).unwrap().key;
+		assert_eq!(key.len(), 2);
+		let digests: Vec<H256> = key.iter()
+			.map(|cid| Headers::get(cid).unwrap().ticket_digest())
+			.collect();
+		assert!(digests[0] <= digests[1]);
+	});
+}
+
+#[test]
+fn follows_heaviest_fork() {
+	new_test_ext().execute_with(|| {
+		let cp = checkpoint();
+		let a = extend(&cp, 11, 110);
+		let b = extend(&a, 12, 120);
+		let c = extend(&b, 13, 130);
+
+		// A lighter fork is stored but does not move the head.
+		let d = extend(&a, 13, 125);
+		assert_eq!(FcRelay::best_tipset(), tipset_hash(&c));
+		assert!(!FcRelay::is_canonical(&tipset_hash(&d)));
+
+		// Extending it past the head switches chains and clears the reverted heights.
+		let e = extend(&d, 14, 140);
+		assert_eq!(FcRelay::best_tipset(), tipset_hash(&e));
+		assert_eq!(FcRelay::canonical_at(12), None);
+		assert_eq!(FcRelay::canonical_at(13), Some(tipset_hash(&d)));
+		assert_eq!(FcRelay::canonical_at(11), Some(tipset_hash(&a)));
+		assert!(!FcRelay::is_canonical(&tipset_hash(&b)));
+	});
+}
+
+#[test]
+fn finalizes_and_prunes() {
+	new_test_ext().execute_with(|| {
+		let cp = checkpoint();
+		assert_ok!(FcRelay::set_f3_power_table(Origin::root(), 0, power_table()));
+		let mut keys = vec![cp];
+		for height in 11..=20u64 {
+			let next = extend(keys.last().unwrap(), height, height as u128 * 10);
+			keys.push(next);
+		}
+
+		// FinalityDepth = 3, HistoryDepth = 2
+		assert_eq!(FcRelay::finalized_height(), 17);
+		assert!(FcRelay::is_final(&tipset_hash(&keys[7])));
+		assert!(!FcRelay::is_final(&tipset_hash(&keys[8])));
+		assert_eq!(FcRelay::oldest_height(), 15);
+		assert!(FcRelay::tipset(tipset_hash(&keys[4])).is_none());
+		assert!(FcRelay::header(&keys[4][0]).is_none());
+		assert!(FcRelay::tipset(tipset_hash(&keys[5])).is_some());
+
+		assert_noop!(
+			FcRelay::submit_tipset(Origin::signed(1), vec![header(17, &keys[6], 165, 99)]),
+			Error::<Test>::BelowFinality
+		);
+	});
+}
+
+#[test]
+fn refuses_to_revert_final_tipsets() {
+	new_test_ext().execute_with(|| {
+		let cp = checkpoint();
+		assert_ok!(FcRelay::set_f3_power_table(Origin::root(), 0, power_table()));
+		let a = extend(&cp, 11, 110);
+		let mut tip = a.clone();
+		for height in 12..=15u64 {
+			tip = extend(&tip, height, height as u128 * 10);
+		}
+		assert_eq!(FcRelay::finalized_height(), 12);
+
+		assert_noop!(
+			FcRelay::submit_tipset(Origin::signed(1), vec![header(16, &a, 1_000, 42)]),
+			Error::<Test>::ReorgBelowFinality
+		);
+	});
+}
+
+#[test]
+fn depth_alone_does_not_finalize() {
+	new_test_ext().execute_with(|| {
+		let cp = checkpoint();
+		let mut tip = cp.clone();
+		for height in 11..=20u64 {
+			tip = extend(&tip, height, height as u128 * 10);
+		}
+		assert_eq!(FcRelay::finalized_height(), 10);
+		assert!(!FcRelay::is_final(&tipset_hash(&tip)));
+
+		// A heavier fork claimed in the headers still takes over.
+		let forged = extend(&cp, 11, 1_000);
+		assert_eq!(FcRelay::best_tipset(), tipset_hash(&forged));
+	});
+}
+
+#[test]
+fn moving_checkpoint_clears_unfinalized_chain() {
+	new_test_ext().execute_with(|| {
+		let cp = checkpoint();
+		let a = extend(&cp, 11, 110);
+		let b = extend(&a, 12, 120);
+		let _ = extend(&b, 13, 130);
+
+		let raw = header(12, &[cbor::cid_of(b"elsewhere")], 500, 7);
+		assert_ok!(FcRelay::set_checkpoint(Origin::root(), vec![raw.clone()]));
+		assert_eq!(FcRelay::best_tipset(), tipset_hash(&key_of(&raw)));
+		assert_eq!(FcRelay::canonical_at(12), Some(tipset_hash(&key_of(&raw))));
+		assert_eq!(FcRelay::canonical_at(11), None);
+		assert_eq!(FcRelay::canonical_at(13), None);
+		assert!(!FcRelay::is_canonical(&tipset_hash(&a)));
+	});
+}
+
+#[test]
+fn looks_up_amt_values() {
+	let values: Vec<Vec<u8>> = (0..20u64).map(|i| {
+		let mut out = Vec::new();
+		head(&mut out, 0, i * 3);
+		out
+	}).collect();
+	let (root, blocks) = amt(&values);
+	let store = amt::BlockStore::new(&blocks);
+	assert_eq!(amt::get(&store, &root, 17), Ok(Some(&values[17][..])));
+	assert_eq!(amt::get(&store, &root, 20), Ok(None));
+	assert_eq!(amt::get(&store, &root, 64), Ok(None));
+
+	let mut seen = Vec::new();
+	assert_ok!(amt::for_each(&store, &root, |i, v| { seen.push((i, v.to_vec())); Ok(true) }));
+	assert_eq!(seen, values.iter().cloned().enumerate().map(|(i, v)| (i as u64, v)).collect::<Vec<_>>());
+
+	let partial = amt::BlockStore::new(&blocks[1..]);
+	assert_eq!(amt::get(&partial, &root, 3), Err(amt::ProofError::MissingBlock));
+	assert_eq!(amt::get(&partial, &root, 9), Ok(Some(&values[9][..])));
+}
+
+#[test]
+fn verifies_executed_messages() {
+	new_test_ext().execute_with(|| {
+		let cp = checkpoint();
+
+		// The second message of sender 1 is skipped for its nonce gap, so the
+		// deposit ends up second in execution order.
+		let (first, gap, deposit) = (message(1, 5, 10), message(1, 7, 20), signed(message(2, 0, 1_000)));
+		let bls_cids = vec![cbor::cid_of(&first), cbor::cid_of(&gap)];
+		let (bls_root, bls_blocks) = amt(&links(&bls_cids));
+		let (secp_root, secp_blocks) = amt(&links(&[cbor::cid_of(&deposit)]));
+		let mut meta = Vec::new();
+		head(&mut meta, 4, 2);
+		link(&mut meta, &bls_root);
+		link(&mut meta, &secp_root);
+
+		let (receipts_root, receipt_blocks) = amt(&[receipt(16), receipt(0)]);
+		let placeholder = cbor::cid_of(&[0x80]);
+		let a = header_with(11, &cp, 110, 11, &placeholder, &cbor::cid_of(&meta));
+		assert_ok!(FcRelay::submit_tipset(Origin::signed(1), vec![a.clone()]));
+		let a = key_of(&a);
+		let b = header_with(12, &a, 120, 12, &receipts_root, &placeholder);
+		assert_ok!(FcRelay::submit_tipset(Origin::signed(1), vec![b.clone()]));
+
+		let mut blocks = vec![meta, first.clone(), gap.clone(), deposit.clone()];
+		blocks.extend(bls_blocks);
+		blocks.extend(secp_blocks);
+		blocks.extend(receipt_blocks);
+		let proof = |message: &[u8], blocks: &[Vec<u8>]| MessageProof {
+			tipset: tipset_hash(&a),
+			message: message.to_vec(),
+			blocks: blocks.to_vec(),
+		};
+
+		assert_eq!(FcRelay::verify_message(&proof(&cbor::cid_of(&deposit), &blocks)), Err(Error::<Test>::TipsetNotFinal.into()));
+		// Finalized the way root moving the checkpoint or a certificate would.
+		FinalizedHeight::put(12);
+
+		let executed = FcRelay::verify_message(&proof(&cbor::cid_of(&deposit), &blocks)).unwrap();
+		assert_eq!(executed.message.from, vec![0x01, 2]);
+		assert_eq!(executed.message.value, 1_000);
+		assert_eq!(executed.message.params, b"memo".to_vec());
+		assert_eq!(executed.height, 11);
+		assert_eq!((executed.exit_code, executed.gas_used), (0, 500));
+
+		assert_eq!(FcRelay::verify_message(&proof(&cbor::cid_of(&first), &blocks)), Err(Error::<Test>::MessageFailed.into()));
+		let failed = FcRelay::verify_execution(&proof(&cbor::cid_of(&first), &blocks)).unwrap();
+		assert_eq!((failed.message.nonce, failed.exit_code, failed.gas_used), (5, 16, 500));
+		assert_eq!(FcRelay::verify_message(&proof(&cbor::cid_of(&gap), &blocks)), Err(Error::<Test>::MessageNotExecuted.into()));
+		assert_eq!(
+			FcRelay::verify_message(&proof(&cbor::cid_of(&message(3, 0, 1)), &blocks)),
+			Err(Error::<Test>::MessageNotIncluded.into())
+		);
+		assert_eq!(
+			FcRelay::verify_message(&proof(&cbor::cid_of(&deposit), &blocks[..blocks.len() - 1])),
+			Err(Error::<Test>::IncompleteProof.into())
+		);
+		let unknown = MessageProof { tipset: H256::repeat_byte(1), ..proof(&cbor::cid_of(&deposit), &blocks) };
+		assert_eq!(FcRelay::verify_message(&unknown), Err(Error::<Test>::UnknownTipset.into()));
+	});
+}
+
+#[test]
+fn decodes_receipts_with_and_without_events() {
+	// Receipt of a successful FEVM invocation in the layout Lotus writes since
+	// nv18, returning 32 bytes and linking to the root of its events.
+	let with_events = hex!("
+		84005820000000000000000000000000000000000000000000000000000000000000
+		00011a001b2c4fd82a5827000171a0e40220e1b3d1c64a7e8e5c2b9f1d3a6c4e8b0a
+		2d5f7c9e1b3a5d7f9c1e3b5a7d9f1c3e
+	");
+	let decoded = MessageReceipt::from_cbor(&with_events).unwrap();
+	assert_eq!(decoded.exit_code, 0);
+	assert_eq!(decoded.return_data[31], 1);
+	assert_eq!(decoded.gas_used, 1_780_815);
+	let events_root = decoded.events_root.unwrap();
+	assert_eq!(&events_root[..6], &cbor::DAG_CBOR_BLAKE2B_PREFIX);
+
+	let without_events = MessageReceipt::from_cbor(&receipt(16)).unwrap();
+	assert_eq!((without_events.exit_code, without_events.gas_used, without_events.events_root), (16, 500, None));
+
+	// Receipts from before nv18 have no events root.
+	let legacy = hex!("8300401901f4");
+	assert_eq!(MessageReceipt::from_cbor(&legacy).unwrap().events_root, None);
+	let mut extra = with_events.to_vec();
+	extra[0] = 0x85;
+	extra.push(0xf6);
+	assert_eq!(MessageReceipt::from_cbor(&extra), Err(cbor::CborError::MissingField));
+}
+
+#[test]
+fn derives_bridge_addresses() {
+	// Public key of the secret key 1, whose Ethereum address is well known.
+	let pubkey = hex!("0479be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8");
+	assert_eq!(
+		crate::delegated_address(&pubkey),
+		[&[4u8, 10][..], &hex!("7e5f4552091a69125d5dfcb7b8c2659029395bdf")[..]].concat()
+	);
+
+	assert!(crate::is_id_address(&[0, 0]));
+	assert!(crate::is_id_address(&[0, 0xe8, 0x07]));
+	assert!(crate::is_id_address(&[0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]));
+	assert!(!crate::is_id_address(&[0]));
+	assert!(!crate::is_id_address(&[0, 0x80]));
+	assert!(!crate::is_id_address(&[0, 0x80, 0x00]));
+	assert!(!crate::is_id_address(&[0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02]));
+	assert!(!crate::is_id_address(&crate::secp256k1_address(&pubkey)));
+}
+
+#[test]
+fn verifies_recorded_block() {
+	let header = BlockHeader::from_cbor(&SIGNED_HEADER).unwrap();
+	assert_eq!(header.height, 60_000);
+	assert_eq!(header.beacon_entries[0].round, 1001);
+	let prev = BeaconEntry { round: 1000, data: PREV_BEACON.to_vec() };
+	let context = Context { worker_key: Some(&WORKER_KEY), prev_beacon: Some(&prev), parent_height: 59_999, parent_ticket: &PARENT_TICKET };
+	assert_ok!(Verifier::verify(&SIGNED_HEADER, &header, &context));
+
+	let signing = verify::signing_bytes(&SIGNED_HEADER).unwrap();
+	assert_eq!(signing.len(), SIGNED_HEADER.len() - header.block_sig.len() - 1);
+	assert!(verify::bls_verify(&WORKER_KEY, &signing, &header.block_sig[1..]));
+}
+
+#[test]
+fn verifies_quicknet_entries() {
+	let entry = |(round, data): (u64, [u8; 48])| BeaconEntry { round, data: data.to_vec() };
+	let [first, second, third] = QUICKNET_SIGNATURES;
+	let legacy = BeaconEntry { round: 3_000_000, data: PREV_BEACON.to_vec() };
+
+	// The first header after the switch carries two entries and only the last is checked.
+	let switch = [BeaconEntry { round: 4_999_999, data: vec![] }, entry(first)];
+	assert_eq!(verify::verify_quicknet_entries(&QUICKNET_KEY, Some(&legacy), &switch, true), Ok(&switch[1]));
+	assert_eq!(verify::verify_quicknet_entries(&QUICKNET_KEY, Some(&legacy), &switch[1..], true), Err(VerifyError::InvalidBeacon));
+	assert_eq!(verify::verify_quicknet_entries(&QUICKNET_KEY, Some(&legacy), &[], true), Err(VerifyError::InvalidBeacon));
+
+	// Later rounds are unchained and may skip ahead, but not go back.
+	let prev = entry(first);
+	let skipped = [entry(third)];
+	assert_eq!(verify::verify_quicknet_entries(&QUICKNET_KEY, Some(&prev), &skipped, false), Ok(&skipped[0]));
+	let repeated = [entry(first)];
+	assert_eq!(verify::verify_quicknet_entries(&QUICKNET_KEY, Some(&prev), &repeated, false), Err(VerifyError::InvalidBeacon));
+	let misnumbered = [BeaconEntry { round: 5_000_002, data: second.1.to_vec() }];
+	assert_eq!(verify::verify_quicknet_entries(&QUICKNET_KEY, Some(&prev), &misnumbered, false), Err(VerifyError::InvalidBeacon));
+	assert_eq!(verify::verify_quicknet_entries(&QUICKNET_KEY, Some(&prev), &[], false), Ok(&prev));
+	assert_eq!(verify::verify_quicknet_entries(&QUICKNET_KEY, None, &skipped, false), Err(VerifyError::MissingBeacon));
+
+	// The chained network's key and signature scheme do not verify.
+	assert_eq!(verify::verify_quicknet_entries(&DRAND_KEY, Some(&prev), &skipped, false), Err(VerifyError::InvalidBeacon));
+}
+
+#[test]
+fn rejects_invalid_consensus_fields() {
+	let header = BlockHeader::from_cbor(&SIGNED_HEADER).unwrap();
+	let prev = BeaconEntry { round: 1000, data: PREV_BEACON.to_vec() };
+	let context = |worker: Option<&'static [u8]>, prev, ticket: &'static [u8]| Context {
+		worker_key: worker,
+		prev_beacon: prev,
+		parent_height: 59_999,
+		parent_ticket: ticket,
+	};
+	let check = |raw: &[u8], header: &BlockHeader, context: &Context| Verifier::verify(raw, header, context);
+
+	assert_eq!(check(&SIGNED_HEADER, &header, &context(None, Some(&prev), &PARENT_TICKET)), Err(VerifyError::UnknownMiner));
+	assert_eq!(check(&SIGNED_HEADER, &header, &context(Some(&WORKER_KEY), None, &PARENT_TICKET)), Err(VerifyError::MissingBeacon));
+	let skipped = BeaconEntry { round: 999, ..prev.clone() };
+	assert_eq!(check(&SIGNED_HEADER, &header, &context(Some(&WORKER_KEY), Some(&skipped), &PARENT_TICKET)), Err(VerifyError::InvalidBeacon));
+	let forged = BeaconEntry { round: 1000, data: PARENT_TICKET.to_vec() };
+	assert_eq!(check(&SIGNED_HEADER, &header, &context(Some(&WORKER_KEY), Some(&forged), &PARENT_TICKET)), Err(VerifyError::InvalidBeacon));
+	assert_eq!(check(&SIGNED_HEADER, &header, &context(Some(&DRAND_KEY), Some(&prev), &PARENT_TICKET)), Err(VerifyError::InvalidBlockSignature));
+	assert_eq!(check(&SIGNED_HEADER, &header, &context(Some(&WORKER_KEY), Some(&prev), &PREV_BEACON)), Err(VerifyError::InvalidTicket));
+
+	let mut no_win = header.clone();
+	no_win.election_proof.as_mut().unwrap().win_count = 0;
+	assert_eq!(check(&SIGNED_HEADER, &no_win, &context(Some(&WORKER_KEY), Some(&prev), &PARENT_TICKET)), Err(VerifyError::InvalidElectionProof));
+
+	// Changing any signed field breaks the block signature.
+	let mut tampered = SIGNED_HEADER;
+	let timestamp = SIGNED_HEADER.len() - header.block_sig.len() - 2 - 1 - 4;
+	tampered[timestamp] ^= 1;
+	let tampered_header = BlockHeader::from_cbor(&tampered).unwrap();
+	assert_eq!(check(&tampered, &tampered_header, &context(Some(&WORKER_KEY), Some(&prev), &PARENT_TICKET)), Err(VerifyError::InvalidBlockSignature));
+}
+
+#[test]
+fn registers_miner_workers() {
+	new_test_ext().execute_with(|| {
+		let miner = vec![0x00, 0xe8, 0x07];
+		assert_noop!(FcRelay::set_miner_worker(Origin::signed(1), miner.clone(), Some(WORKER_KEY.to_vec())), DispatchError::BadOrigin);
+		assert_ok!(FcRelay::set_miner_worker(Origin::root(), miner.clone(), Some(WORKER_KEY.to_vec())));
+		assert_eq!(FcRelay::miner_worker(&miner), Some(WORKER_KEY.to_vec()));
+		assert_ok!(FcRelay::set_miner_worker(Origin::root(), miner.clone(), None));
+		assert_eq!(FcRelay::miner_worker(&miner), None);
+	});
+}
+
+fn power_table() -> Vec<PowerEntry> {
+	[40u128, 30, 20, 10].iter().enumerate()
+		.map(|(i, power)| PowerEntry { id: i as u64 + 1, power: *power, public_key: F3_KEYS[i].to_vec() })
+		.collect()
+}
+
+/// The certificate of instance 0, deciding the given single block tipsets.
+fn certificate(chain: &[(&Vec<Vec<u8>>, u64)]) -> FinalityCertificate {
+	let table = f3::power_table_cid(&power_table());
+	let delta = vec![
+		PowerTableDelta { participant: 4, power_delta: -10, signing_key: vec![] },
+		PowerTableDelta { participant: 5, power_delta: 15, signing_key: F3_KEYS[4].to_vec() },
+	];
+	let next = f3::apply_delta(&power_table(), &delta).unwrap();
+	FinalityCertificate {
+		instance: 0,
+		ec_chain: chain.iter()
+			.map(|(key, epoch)| ECTipset { epoch: *epoch, key: key.concat(), power_table: table.clone(), commitments: [0; 32] })
+			.collect(),
+		supplemental_commitments: [0; 32],
+		supplemental_power_table: f3::power_table_cid(&next),
+		signers: vec![0, 1, 2],
+		signature: F3_SIGNATURE.to_vec(),
+		power_table_delta: delta,
+	}
+}
+
+#[test]
+fn verifies_f3_certificate() {
+	let cp = key_of(&header(10, &[cbor::cid_of(b"genesis")], 100, 0));
+	let a = key_of(&header(11, &cp, 110, 11));
+	let b = key_of(&header(12, &a, 120, 12));
+	let cert = certificate(&[(&cp, 10), (&a, 11), (&b, 12)]);
+	let table = power_table();
+
+	let next = f3::verify_certificate(b"filecoin", &table, &cert).unwrap();
+	assert_eq!(next.iter().map(|e| (e.id, e.power)).collect::<Vec<_>>(), vec![(1, 40), (2, 30), (3, 20), (5, 15)]);
+	assert_eq!(f3::verify_certificate(b"calibrationnet", &table, &cert), Err(f3::F3Error::InvalidSignature));
+
+	let mut bad = cert.clone();
+	bad.signers = vec![0, 3];
+	assert_eq!(f3::verify_certificate(b"filecoin", &table, &bad), Err(f3::F3Error::NotEnoughPower));
+	bad.signers = vec![0, 1];
+	assert_eq!(f3::verify_certificate(b"filecoin", &table, &bad), Err(f3::F3Error::InvalidSignature));
+	bad.signers = vec![1, 0, 2];
+	assert_eq!(f3::verify_certificate(b"filecoin", &table, &bad), Err(f3::F3Error::InvalidSigner));
+
+	let mut bad = cert.clone();
+	bad.power_table_delta.pop();
+	assert_eq!(f3::verify_certificate(b"filecoin", &table, &bad), Err(f3::F3Error::PowerTableMismatch));
+	let mut bad = cert;
+	bad.ec_chain.swap(1, 2);
+	assert_eq!(f3::verify_certificate(b"filecoin", &table, &bad), Err(f3::F3Error::InvalidChain));
+}
+
+#[test]
+fn derives_bdn_coefficients_like_kyber() {
+	// Output of a separate BLAKE2Xs implementation following Go's x/crypto/blake2s.
+	assert_eq!(f3::blake2xs(b"abc", 48), hex!("
+		bf5c4f309fde8a62195bc8364ceea81e84eb9330579270c5737b9300085b61495576fef12a5cfa717343bff2bb2461d7
+	").to_vec());
+	assert_eq!(f3::blake2xs(&[7u8; 100], 80)[32..], hex!("
+		d36a2bc63cf4b0d0590f8e2c0e76ab48eaffb641d056429f0b6129158a20d7eee9949bee02b801c4f2d170b6dda2c9d5
+	")[..]);
+
+	let table = power_table();
+	let keys: Vec<u8> = table.iter().flat_map(|entry| entry.public_key.clone()).collect();
+	let out = f3::blake2xs(&keys, 64);
+	let coefficients = f3::bdn_coefficients(&table);
+	for (i, chunk) in out.chunks(16).enumerate() {
+		let mut bytes = [0u8; 32];
+		bytes[..16].copy_from_slice(chunk);
+		assert_eq!(coefficients[i].to_bytes(), bytes);
+	}
+}
+
+#[test]
+fn f3_certificates_finalize_deposits() {
+	new_test_ext().execute_with(|| {
+		let cp = checkpoint();
+		let a = extend(&cp, 11, 110);
+		let b = extend(&a, 12, 120);
+		let _ = extend(&b, 13, 130);
+		let cert = certificate(&[(&cp, 10), (&a, 11), (&b, 12)]);
+
+		assert_noop!(FcRelay::submit_f3_certificate(Origin::signed(1), cert.clone()), Error::<Test>::F3NotInitialized);
+		assert_eq!(FcRelay::deposit_final_height(), 10);
+
+		assert_ok!(FcRelay::set_f3_power_table(Origin::root(), 0, power_table()));
+		// Nothing is final for deposits until a certificate covers it.
+		assert_eq!(FcRelay::deposit_final_height(), 0);
+
+		let mut bad = cert.clone();
+		bad.signers = vec![0, 3];
+		assert_noop!(FcRelay::submit_f3_certificate(Origin::signed(1), bad), Error::<Test>::NotEnoughPower);
+
+		assert_ok!(FcRelay::submit_f3_certificate(Origin::signed(1), cert.clone()));
+		assert_eq!(FcRelay::f3_finalized(), Some((12, tipset_hash(&b))));
+		assert_eq!(FcRelay::f3_instance(), 1);
+		assert_eq!(FcRelay::f3_power_table().len(), 4);
+		assert_eq!(FcRelay::finalized_height(), 12);
+		assert_eq!(FcRelay::deposit_final_height(), 12);
+
+		assert_noop!(FcRelay::submit_f3_certificate(Origin::signed(1), cert), Error::<Test>::WrongInstance);
+	});
+}
+
+#[test]
+fn follows_certified_fork() {
+	new_test_ext().execute_with(|| {
+		let cp = checkpoint();
+		assert_ok!(FcRelay::set_f3_power_table(Origin::root(), 0, power_table()));
+		let a = extend(&cp, 11, 110);
+		let b = extend(&a, 12, 120);
+		// A heavier fork leaves out the tipset F3 goes on to certify.
+		let x = header(12, &a, 125, 99);
+		assert_ok!(FcRelay::submit_tipset(Origin::signed(1), vec![x.clone()]));
+		let y = extend(&key_of(&x), 13, 130);
+		assert!(!FcRelay::is_canonical(&tipset_hash(&b)));
+
+		assert_ok!(FcRelay::submit_f3_certificate(Origin::signed(1), certificate(&[(&cp, 10), (&a, 11), (&b, 12)])));
+		assert_eq!(FcRelay::finalized_height(), 10);
+		assert_eq!(FcRelay::deposit_final_height(), 0);
+
+		// The heavier fork cannot grow, while a lighter tipset on the certified one takes over.
+		assert_noop!(
+			FcRelay::submit_tipset(Origin::signed(1), vec![header(14, &y, 140, 14)]),
+			Error::<Test>::DropsCertifiedTipset
+		);
+		let c = extend(&b, 13, 121);
+		assert_eq!(FcRelay::best_tipset(), tipset_hash(&c));
+		assert!(FcRelay::is_final(&tipset_hash(&b)));
+		assert_eq!(FcRelay::deposit_final_height(), 12);
+
+		assert_noop!(
+			FcRelay::submit_tipset(Origin::signed(1), vec![header(14, &y, 1_000, 15)]),
+			Error::<Test>::ReorgBelowFinality
+		);
+	});
+}
@@ -0,0 +1,267 @@
+//! Consensus checks of Filecoin block headers.
+//!
+//! Covers what can be checked without the miner's state: the BLS block
+//! signature of the miner's worker, the drand entries, the ticket VRF and the
+//! election proof VRF. Whether the election proof wins given the
+//! miner's power is not checked, only that it claims at least one win.
+
+use frame_support::traits::Get;
+use sha2::{Digest, Sha256};
+use sp_runtime::RuntimeDebug;
+use sp_std::{prelude::*, marker::PhantomData};
+
+use bls12_381::{G1Affine, G1Projective, G2Affine, G2Projective, pairing,
+				hash_to_curve::{ExpandMsgXmd, HashToCurve}};
+
+use crate::cbor::{CborError, Decoder};
+use crate::header::{BeaconEntry, BlockHeader};
+
+/// Domain separation tag of Filecoin and drand BLS signatures.
+pub const BLS_DST: &[u8] = b"BLS_SIG_BLS12381G2_XMD:SHA-256_SSWU_RO_NUL_";
+
+/// Domain separation tag of drand quicknet, which signs on G1.
+pub const QUICKNET_DST: &[u8] = b"BLS_SIG_BLS12381G1_XMD:SHA-256_SSWU_RO_NUL_";
+
+/// Type prefix of BLS signatures.
+const SIG_TYPE_BLS: u8 = 2;
+
+const DST_TICKET_PRODUCTION: i64 = 1;
+const DST_ELECTION_PROOF_PRODUCTION: i64 = 2;
+
+/// Tickets draw randomness from the epoch before the block.
+const TICKET_RANDOMNESS_LOOKBACK: u64 = 1;
+
+/// Index of `BlockSig` in the header array.
+const BLOCK_SIG_FIELD: usize = 13;
+
+#[derive(Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+pub enum VerifyError {
+	/// No worker key is known for the miner.
+	UnknownMiner,
+	/// There is no beacon entry to chain from.
+	MissingBeacon,
+	InvalidBeacon,
+	InvalidBlockSignature,
+	InvalidTicket,
+	InvalidElectionProof,
+}
+
+/// What a header is checked against, gathered from the relay's storage.
+pub struct Context<'a> {
+	/// BLS public key of the miner's worker.
+	pub worker_key: Option<&'a [u8]>,
+	/// Latest beacon entry of the parent chain.
+	pub prev_beacon: Option<&'a BeaconEntry>,
+	/// Height of the parent tipset.
+	pub parent_height: u64,
+	/// Ticket of the first block of the parent tipset.
+	pub parent_ticket: &'a [u8],
+}
+
+/// Checks the consensus fields of a header before the relay imports it.
+pub trait HeaderVerifier {
+	fn verify(raw: &[u8], header: &BlockHeader, context: &Context) -> Result<(), VerifyError>;
+}
+
+/// Accepts every header.
+impl HeaderVerifier for () {
+	fn verify(_: &[u8], _: &BlockHeader, _: &Context) -> Result<(), VerifyError> {
+		Ok(())
+	}
+}
+
+/// Checks headers the way Lotus does, given the public key of the chained drand
+/// network, the height from which tickets mix in the parent ticket, and the key
+/// and first height of drand quicknet.
+pub struct BlsVerifier<DrandKey, SmokeHeight, QuicknetKey, QuicknetHeight>(
+	PhantomData<(DrandKey, SmokeHeight, QuicknetKey, QuicknetHeight)>
+);
+
+impl<DrandKey, SmokeHeight, QuicknetKey, QuicknetHeight> HeaderVerifier
+	for BlsVerifier<DrandKey, SmokeHeight, QuicknetKey, QuicknetHeight>
+where
+	DrandKey: Get<Vec<u8>>,
+	SmokeHeight: Get<u64>,
+	QuicknetKey: Get<Vec<u8>>,
+	QuicknetHeight: Get<u64>,
+{
+	fn verify(raw: &[u8], header: &BlockHeader, context: &Context) -> Result<(), VerifyError> {
+		let worker = context.worker_key.ok_or(VerifyError::UnknownMiner)?;
+		let quicknet = QuicknetHeight::get();
+		let beacon = if header.height < quicknet {
+			verify_beacon_entries(&DrandKey::get(), context.prev_beacon, &header.beacon_entries)?
+		} else {
+			let switching = context.parent_height < quicknet;
+			verify_quicknet_entries(&QuicknetKey::get(), context.prev_beacon, &header.beacon_entries, switching)?
+		};
+		verify_block_signature(raw, header, worker)?;
+
+		let mut entropy = miner_entropy(&header.miner);
+		if header.height > SmokeHeight::get() {
+			entropy.extend_from_slice(context.parent_ticket);
+		}
+		let round = header.height.saturating_sub(TICKET_RANDOMNESS_LOOKBACK);
+		let base = draw_randomness(&beacon.data, DST_TICKET_PRODUCTION, round, &entropy);
+		if !bls_verify(worker, &base, &header.ticket) {
+			return Err(VerifyError::InvalidTicket);
+		}
+
+		let proof = header.election_proof.as_ref().ok_or(VerifyError::InvalidElectionProof)?;
+		if proof.win_count < 1 {
+			return Err(VerifyError::InvalidElectionProof);
+		}
+		let base = draw_randomness(&beacon.data, DST_ELECTION_PROOF_PRODUCTION, header.height, &miner_entropy(&header.miner));
+		if !bls_verify(worker, &base, &proof.vrf_proof) {
+			return Err(VerifyError::InvalidElectionProof);
+		}
+		Ok(())
+	}
+}
+
+/// Checks the entries chain from `prev` and returns the latest one.
+pub fn verify_beacon_entries<'a>(drand_key: &[u8], prev: Option<&'a BeaconEntry>, entries: &'a [BeaconEntry])
+	-> Result<&'a BeaconEntry, VerifyError>
+{
+	let mut prev = prev.ok_or(VerifyError::MissingBeacon)?;
+	for entry in entries {
+		if entry.round != prev.round + 1 || !bls_verify(drand_key, &beacon_message(prev, entry.round), &entry.data) {
+			return Err(VerifyError::InvalidBeacon);
+		}
+		prev = entry;
+	}
+	Ok(prev)
+}
+
+/// Checks the entries of a quicknet header and returns the latest one.
+///
+/// Quicknet rounds are not chained, so only the latest entry is verified. The
+/// first header after the switch carries two entries, as Lotus requires, and
+/// its rounds are not comparable with those of the previous network.
+pub fn verify_quicknet_entries<'a>(drand_key: &[u8], prev: Option<&'a BeaconEntry>, entries: &'a [BeaconEntry],
+	switching: bool) -> Result<&'a BeaconEntry, VerifyError>
+{
+	let prev = prev.ok_or(VerifyError::MissingBeacon)?;
+	let last = match entries.last() {
+		Some(last) => last,
+		None if !switching => return Ok(prev),
+		None => return Err(VerifyError::InvalidBeacon),
+	};
+	let in_order = if switching { entries.len() == 2 } else { last.round > prev.round };
+	if !in_order || !bls_verify_g1(drand_key, &quicknet_message(last.round), &last.data) {
+		return Err(VerifyError::InvalidBeacon);
+	}
+	Ok(last)
+}
+
+/// Message signed by drand quicknet for a round.
+pub fn quicknet_message(round: u64) -> Vec<u8> {
+	Sha256::digest(&round.to_be_bytes()).to_vec()
+}
+
+/// Message signed by drand for a round of a chained network.
+pub fn beacon_message(prev: &BeaconEntry, round: u64) -> Vec<u8> {
+	let mut hasher = Sha256::new();
+	hasher.update(&prev.data);
+	hasher.update(&round.to_be_bytes());
+	hasher.finalize().to_vec()
+}
+
+pub fn verify_block_signature(raw: &[u8], header: &BlockHeader, worker: &[u8]) -> Result<(), VerifyError> {
+	let signature = match header.block_sig.split_first() {
+		Some((&SIG_TYPE_BLS, signature)) => signature,
+		_ => return Err(VerifyError::InvalidBlockSignature),
+	};
+	let payload = signing_bytes(raw).map_err(|_| VerifyError::InvalidBlockSignature)?;
+	if bls_verify(worker, &payload, signature) {
+		Ok(())
+	} else {
+		Err(VerifyError::InvalidBlockSignature)
+	}
+}
+
+/// The header encoding with `BlockSig` set to null, which is what miners sign.
+pub fn signing_bytes(raw: &[u8]) -> Result<Vec<u8>, CborError> {
+	let mut d = Decoder::new(raw);
+	d.array()?;
+	for _ in 0..BLOCK_SIG_FIELD {
+		d.skip()?;
+	}
+	let start = d.position();
+	d.skip()?;
+	let end = d.position();
+
+	let mut out = Vec::with_capacity(raw.len());
+	out.extend_from_slice(&raw[..start]);
+	out.push(0xf6);
+	out.extend_from_slice(&raw[end..]);
+	Ok(out)
+}
+
+/// Filecoin's `DrawRandomness`.
+pub fn draw_randomness(base: &[u8], personalization: i64, round: u64, entropy: &[u8]) -> Vec<u8> {
+	let mut buf = personalization.to_be_bytes().to_vec();
+	buf.extend_from_slice(&sp_io::hashing::blake2_256(base));
+	buf.extend_from_slice(&(round as i64).to_be_bytes());
+	buf.extend_from_slice(entropy);
+	sp_io::hashing::blake2_256(&buf).to_vec()
+}
+
+/// The miner address encoded as a CBOR byte string.
+fn miner_entropy(miner: &[u8]) -> Vec<u8> {
+	let mut out = Vec::with_capacity(miner.len() + 2);
+	match miner.len() {
+		len @ 0..=23 => out.push(0x40 | len as u8),
+		len => out.extend_from_slice(&[0x58, len as u8]),
+	}
+	out.extend_from_slice(miner);
+	out
+}
+
+/// Verifies a BLS signature on G2 with a public key on G1.
+pub fn bls_verify(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+	if public_key.len() != 48 || signature.len() != 96 {
+		return false;
+	}
+	let mut pk = [0u8; 48];
+	pk.copy_from_slice(public_key);
+	let mut sig = [0u8; 96];
+	sig.copy_from_slice(signature);
+
+	let public_key = G1Affine::from_compressed(&pk);
+	if bool::from(public_key.is_none()) {
+		return false;
+	}
+	bls_verify_key(&public_key.unwrap(), message, &sig)
+}
+
+/// Verifies a BLS signature on G1 with a public key on G2, as drand quicknet signs.
+pub fn bls_verify_g1(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+	if public_key.len() != 96 || signature.len() != 48 {
+		return false;
+	}
+	let mut pk = [0u8; 96];
+	pk.copy_from_slice(public_key);
+	let mut sig = [0u8; 48];
+	sig.copy_from_slice(signature);
+
+	let (public_key, signature) = (G2Affine::from_compressed(&pk), G1Affine::from_compressed(&sig));
+	if bool::from(public_key.is_none() | signature.is_none()) {
+		return false;
+	}
+	let public_key = public_key.unwrap();
+	if bool::from(public_key.is_identity()) {
+		return false;
+	}
+	let hash = <G1Projective as HashToCurve<ExpandMsgXmd<Sha256>>>::hash_to_curve(message, QUICKNET_DST);
+	pairing(&G1Affine::from(hash), &public_key) == pairing(&signature.unwrap(), &G2Affine::generator())
+}
+
+/// Verifies a compressed BLS signature with an already decoded public key.
+pub fn bls_verify_key(public_key: &G1Affine, message: &[u8], signature: &[u8; 96]) -> bool {
+	let signature = G2Affine::from_compressed(signature);
+	if bool::from(signature.is_none() | public_key.is_identity()) {
+		return false;
+	}
+	let hash = <G2Projective as HashToCurve<ExpandMsgXmd<Sha256>>>::hash_to_curve(message, BLS_DST);
+	pairing(public_key, &G2Affine::from(hash)) == pairing(&G1Affine::generator(), &signature.unwrap())
+}
@@ -0,0 +1,32 @@
+//! Weights of the relay's calls.
+//!
+//! Submitting a tipset is dominated by BLS signature checks: the block
+//! signature, the ticket and the election proof of every block, and every
+//! drand entry it carries. `BLS_VERIFY` is a conservative estimate for Wasm
+//! until the `bls_verify` benchmark of `benchmarking.rs` is run with
+//! `benchmark --pallet pallet_fc_relay` on the reference machine.
+
+use frame_support::{traits::Get, weights::{Weight, constants::RocksDbWeight}};
+
+/// One BLS signature check: two pairings and hashing the message to G2.
+pub const BLS_VERIFY: Weight = 10_000_000_000;
+
+/// Decoding and hashing a header.
+const DECODE_HEADER: Weight = 50_000_000;
+
+pub trait WeightInfo {
+	fn submit_tipset(blocks: u32, beacon_entries: u32) -> Weight;
+}
+
+impl WeightInfo for () {
+	fn submit_tipset(blocks: u32, beacon_entries: u32) -> Weight {
+		let blocks = blocks as Weight;
+		(10_000_000 as Weight)
+			.saturating_add(blocks.saturating_mul(3 * BLS_VERIFY + DECODE_HEADER))
+			.saturating_add((beacon_entries as Weight).saturating_mul(BLS_VERIFY))
+			// The parent, the beacon lookback and a worker key per block.
+			.saturating_add(RocksDbWeight::get().reads(blocks + 48))
+			// The headers, the new head and the heights pruned behind finality.
+			.saturating_add(RocksDbWeight::get().writes(blocks + 8 + 2 * 64))
+	}
+}
@@ -4,7 +4,8 @@
 /// Learn more about FRAME and the core library of Substrate FRAME pallets:
 /// https://substrate.dev/docs/en/knowledgebase/runtime/frame
 
-use frame_support::{decl_module, decl_storage, decl_event, decl_error, dispatch::DispatchResult, traits::{Get,Contains}};
+use frame_support::{decl_module, decl_storage, decl_event, decl_error, ensure, dispatch::DispatchResult, traits::{Get,Contains},
+					weights::Weight};
 use frame_system::ensure_signed;
 use frame_support::dispatch::Vec;
 use codec::{Decode, Encode};
@@ -19,10 +20,12 @@ use lite_json::{self, JsonValue};
 // offchain worker
 use frame_support::{ debug, dispatch };
 use frame_system::offchain;
+use sp_runtime::traits::Zero;
 use sp_runtime::transaction_validity::{
 	TransactionValidity, TransactionLongevity, ValidTransaction, InvalidTransaction
 };
 use sp_core::crypto::KeyTypeId;
+use pallet_fc_relay::{ExecutedMessage, MessageProof, UnsignedMessage};
 
 #[cfg(test)]
 mod mock;
@@ -33,6 +36,9 @@ mod tests;
 mod participant;
 pub use participant::*;
 
+pub mod memo;
+use memo::{Memo, MemoError};
+
 #[derive(PartialEq, Eq, Clone)]
 pub enum TssKeyType {
 	BTC,
@@ -52,6 +58,37 @@ pub struct WithdrawDetail<AccountId> {
 	pub receiver: Vec<u8>,
 }
 
+/// for refund event
+#[derive(PartialEq, Eq, Clone, Encode, Decode, RuntimeDebug)]
+pub struct RefundDetail {
+	pub uid: u64,
+	/// cid of the refunded deposit
+	pub cid: Vec<u8>,
+	/// deposit minus the refund fee
+	pub value: u128,
+	pub receiver: Vec<u8>,
+}
+
+/// A Filecoin transfer paid out by the bridge.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Encode, Decode, RuntimeDebug)]
+pub enum TransferId {
+	/// Id of a batch of withdrawals.
+	WithdrawBatch(u64),
+	/// Uid of a refund.
+	Refund(u64),
+}
+
+/// Why a deposit could not be credited.
+#[derive(PartialEq, Eq, Clone, Copy, Encode, Decode, RuntimeDebug)]
+pub enum UnattributedReason {
+	Memo(MemoError),
+	BelowMinimum,
+	BlockedSender,
+	/// Sent to the `f410` address of the TSS key, the bridge only pays out of its `f1` account.
+	DelegatedAddress,
+}
+
+/// A deposit that could not be credited to anyone.
 #[derive(Default, PartialEq, Eq, Clone, Encode, Decode, RuntimeDebug)]
 pub struct ErrorRecord{
 	pub cid: Vec<u8>,
@@ -90,8 +127,20 @@ impl<T: Trait> AccountCollection for LinkedNodes<T> {
 	type AccountSet = VerifiedAccount<T>;
 }
 
-pub trait Trait: frame_system::Trait + pallet_timestamp::Trait {
+pub trait Trait: frame_system::Trait + pallet_timestamp::Trait + pallet_fc_relay::Trait {
 	type Event: From<Event<Self>> + Into<<Self as frame_system::Trait>::Event>;
+	/// Smallest deposit that is credited, in attoFIL.
+	type MinimumDeposit: Get<u128>;
+	/// Blocks root has to attribute a deposit before it is refunded.
+	type RefundGracePeriod: Get<Self::BlockNumber>;
+	/// Kept from refunds to pay for the Filecoin message sending them.
+	type RefundFee: Get<u128>;
+	/// Blocks between two batches of withdrawals.
+	type WithdrawBatchInterval: Get<Self::BlockNumber>;
+	/// Most withdrawals paid out by one Filecoin message.
+	type MaxWithdrawBatch: Get<u32>;
+	/// Most withdrawals waiting for the next batch.
+	type MaxWithdrawQueue: Get<u32>;
 }
 
 decl_storage! {
@@ -127,12 +176,51 @@ decl_storage! {
         //WithDraw Address
         WithDrawAddress get(fn with_draw_address): map hasher(blake2_128_concat) T::AccountId => Vec<u8>;
 
-        //Error Record
+        //Unattributed deposits, by message cid
         FailRecord get(fn fail_record): map hasher(blake2_128_concat) Vec<u8> => ErrorRecord;
 
         //InPendingList  block height => Vec< { } >
         InPendingList get(fn in_pending_list): map hasher(blake2_128_concat) u64 => Vec<InPendingDepost>;
 
+        //Filecoin messages already credited, by cid
+        ProcessedDeposits get(fn processed_deposit): map hasher(blake2_128_concat) Vec<u8> => bool;
+
+        //Filecoin senders whose deposits are refunded instead of credited
+        BlockedSenders get(fn is_blocked_sender): map hasher(blake2_128_concat) Vec<u8> => bool;
+
+        //Unattributed deposits by the block they get refunded at
+        RefundQueue get(fn refund_queue): map hasher(twox_64_concat) T::BlockNumber => Vec<Vec<u8>>;
+
+        RefundCount get(fn refund_count): u64;
+
+        //Uids of the refunds whose message is not proven executed yet
+        PendingRefunds get(fn pending_refund): map hasher(twox_64_concat) u64 => bool;
+
+        //ID address of the bridge account actor, deposits may be sent to it as well
+        BridgeIdAddress get(fn bridge_id_address): Vec<u8>;
+
+        WithdrawCount get(fn withdraw_count): u64;
+
+        //Withdrawals whose Filecoin transfer has no settled result yet
+        PendingWithdrawals get(fn pending_withdrawal): map hasher(twox_64_concat) u64 => Option<WithdrawDetail<T::AccountId>>;
+
+        //Withdrawals waiting for the next batch
+        WithdrawQueue get(fn withdraw_queue): Vec<u64>;
+
+        BatchCount get(fn batch_count): u64;
+
+        //Uids of the withdrawals of a batch until its result is settled
+        WithdrawBatches get(fn withdraw_batch): map hasher(twox_64_concat) u64 => Vec<u64>;
+
+        //FEVM contract paying batches of several withdrawals, each one is sent alone without it
+        BatchActor get(fn batch_actor): Vec<u8>;
+
+        //Unsigned messages proposed for a transfer, replacements after the first
+        TransferProposals get(fn transfer_proposals): map hasher(blake2_128_concat) TransferId => Vec<Vec<u8>>;
+
+        //Cid of the last proposed message, once it is broadcast
+        TransfersSent get(fn transfer_sent): map hasher(blake2_128_concat) TransferId => Option<Vec<u8>>;
+
 	}
 	   add_extra_genesis {
 			config(key): Vec<u8>;
@@ -162,9 +250,22 @@ decl_event!(
             SignBtcMessage(u64, Time, Vec<u8>, Vec<u8>, Vec<u8>), // url btc_tx_message(hex) pubkey
 
             // deposit event
-            DepositToken(AccountId,u64,Vec<u8>),
+            DepositToken(AccountId,u128,Vec<u8>,Vec<u8>), // receiver amount message_cid tag
+            DepositUnattributed(Vec<u8>,Vec<u8>,u128,UnattributedReason), // message_cid from amount reason
             // withdraw event
             WithdrawToken(WithdrawDetail<AccountId>),
+            // withdrawals paid out by one message, through the batch actor unless it is empty
+            WithdrawBatch(u64,Vec<u8>,Vec<WithdrawDetail<AccountId>>), // batch batch_actor withdrawals
+            WithdrawConfirmed(u64,Vec<u8>,i64), // uid message_cid gas_used of the batch
+            WithdrawFailed(u64,Vec<u8>,i64,i64), // uid message_cid exit_code gas_used of the batch
+            WithdrawBatchSettled(u64,i64), // batch exit_code
+            // unsigned message the members sign for a transfer
+            TransferProposed(TransferId,Vec<u8>),
+            TransferSent(TransferId,Vec<u8>), // message_cid
+            // refund of an unattributed deposit
+            RefundToken(RefundDetail),
+            RefundConfirmed(u64,Vec<u8>), // uid message_cid
+            RefundFailed(u64,Vec<u8>,i64), // uid message_cid exit_code
      	}
 );
 
@@ -175,6 +276,32 @@ decl_error! {
 		NoneValue,
 		/// Errors should have helpful documentation associated with them.
 		StorageOverflow,
+		/// The Filecoin message has already been credited.
+		DepositAlreadyProcessed,
+		/// The Filecoin message was not sent to the bridge address.
+		NotBridgeAddress,
+		/// No unattributed deposit waits under this cid.
+		NoUnattributedDeposit,
+		/// Too many withdrawals wait for the next batch.
+		WithdrawQueueFull,
+		/// No withdrawal batch waits for its result under this id.
+		NoPendingWithdrawal,
+		/// Not an `f0` address.
+		NotIdAddress,
+		/// Members may only record the ID address of the bridge while none is set.
+		BridgeIdAddressSet,
+		/// The transfer is not waiting to be paid out.
+		UnknownTransfer,
+		/// A message is proposed for the transfer and not sent yet.
+		TransferAlreadyProposed,
+		/// No message is proposed for the transfer.
+		NoTransferProposal,
+		/// The proposed message of the transfer is already reported sent.
+		TransferAlreadySent,
+		/// The proven message is not the one proposed for the transfer.
+		NotProposedMessage,
+		/// The proven message executed successfully.
+		TransferSucceeded,
 	}
 }
 
@@ -293,20 +420,57 @@ decl_module! {
             Ok(())
         }
 
-        #[weight = 0]
-        pub fn deposit_token(origin, who:Vec<u8>, amount_add:u128, from:Vec<u8>) -> DispatchResult{
-            let sender = ensure_signed(origin)?;
+        /// Credit a Filecoin transfer to the bridge, proven against the relayed headers,
+        /// to the account named in its memo.
+        #[weight = deposit_weight(proof)]
+        pub fn deposit_token(origin, proof: MessageProof) -> DispatchResult{
+            let _sender = ensure_signed(origin)?;
+            ensure!(!ProcessedDeposits::get(&proof.message), Error::<T>::DepositAlreadyProcessed);
 
-            let dest: T::AccountId = match Decode::decode(&mut who.as_slice()) {
-                Ok(a) => a,
-                Err(_e) => return Ok(()),
-            };
+            let executed = <pallet_fc_relay::Module<T>>::verify_message(&proof)?;
+            Self::record_deposit(executed)
+        }
 
-            let current_balance = <FileCoinToken<T>>::get(&dest);
-            <FileCoinToken<T>>::insert(&dest,current_balance + amount_add);
+        /// Credit an unattributed deposit to `dest` before it is refunded.
+        #[weight = 10_000_000 + T::DbWeight::get().reads_writes(2, 3)]
+        pub fn attribute_deposit(origin, cid: Vec<u8>, dest: T::AccountId) -> DispatchResult{
+            ensure_root(origin)?;
+            let mut record = FailRecord::get(&cid);
+            ensure!(!record.cid.is_empty() && !record.solved, Error::<T>::NoUnattributedDeposit);
+            record.solved = true;
+            FailRecord::insert(&cid, &record);
 
-            <WithDrawAddress<T>>::insert(&dest,from);
+            Self::credit(dest, record.amount, record.from, cid, Vec::new());
+            Ok(())
+        }
 
+        /// Accept deposits sent to the ID address of the bridge account actor.
+        /// Members record it once Lotus maps it back to the TSS key, root may
+        /// replace it.
+        #[weight = T::DbWeight::get().reads_writes(3, 1)]
+        pub fn set_bridge_id_address(origin, address: Vec<u8>) -> DispatchResult{
+            ensure!(pallet_fc_relay::is_id_address(&address), Error::<T>::NotIdAddress);
+            if ensure_root(origin.clone()).is_err() {
+                Self::check_permissions(ensure_signed(origin)?)?;
+                let current = BridgeIdAddress::get();
+                if current == address {
+                    return Ok(());
+                }
+                ensure!(current.is_empty(), Error::<T>::BridgeIdAddressSet);
+            }
+            BridgeIdAddress::put(address);
+            Ok(())
+        }
+
+        /// Refund the deposits of a Filecoin address instead of crediting them.
+        #[weight = T::DbWeight::get().writes(1)]
+        pub fn set_sender_blocked(origin, address: Vec<u8>, blocked: bool) -> DispatchResult{
+            ensure_root(origin)?;
+            if blocked {
+                BlockedSenders::insert(address, true);
+            } else {
+                BlockedSenders::remove(address);
+            }
             Ok(())
         }
 
@@ -318,24 +482,123 @@ decl_module! {
                 return Ok(());
             }
 
+            ensure!(
+                WithdrawQueue::decode_len().unwrap_or(0) < T::MaxWithdrawQueue::get() as usize,
+                Error::<T>::WithdrawQueueFull
+            );
             let from_address = <WithDrawAddress<T>>::get(&who);
 
             let current_balance = <FileCoinToken<T>>::get(&who);
             <FileCoinToken<T>>::insert(&who,current_balance - amount_add);
 
-            Self::deposit_event(RawEvent::WithdrawToken
-                (WithdrawDetail::<T::AccountId>{
-                    uid: 0u64,
-	                actor: who,
-	                /// token name
-	                token: vec![0u8],
-	                value: amount_add,
-	                receiver: from_address,}
-               ));
+            let uid = WithdrawCount::mutate(|count| { *count += 1; *count });
+            let detail = WithdrawDetail::<T::AccountId>{
+                uid,
+                actor: who,
+                /// token name
+                token: vec![0u8],
+                value: amount_add,
+                receiver: from_address,};
+            <PendingWithdrawals<T>>::insert(uid, &detail);
+            WithdrawQueue::append(uid);
+            Self::deposit_event(RawEvent::WithdrawToken(detail));
+
+            Ok(())
+        }
+
+        /// Propose the message paying out a transfer, the members sign it. A
+        /// replacement may only be proposed once the previous message is sent.
+        #[weight = propose_weight::<T>(message)]
+        pub fn propose_transfer(origin, id: TransferId, message: Vec<u8>) -> DispatchResult{
+            Self::check_permissions(ensure_signed(origin)?)?;
+            ensure!(Self::is_pending_transfer(id), Error::<T>::UnknownTransfer);
+            let mut proposals = TransferProposals::get(id);
+            ensure!(proposals.is_empty() || TransfersSent::contains_key(id), Error::<T>::TransferAlreadyProposed);
+
+            proposals.push(message.clone());
+            TransferProposals::insert(id, proposals);
+            TransfersSent::remove(id);
+            Self::deposit_event(RawEvent::TransferProposed(id, message));
+            Ok(())
+        }
+
+        /// Report that the last proposed message of a transfer was broadcast.
+        /// The transfer stays pending until its result is proven.
+        #[weight = 10_000_000 + T::DbWeight::get().reads_writes(4, 1)]
+        pub fn transfer_sent(origin, id: TransferId, cid: Vec<u8>) -> DispatchResult{
+            Self::check_permissions(ensure_signed(origin)?)?;
+            ensure!(Self::is_pending_transfer(id), Error::<T>::UnknownTransfer);
+            ensure!(TransferProposals::contains_key(id), Error::<T>::NoTransferProposal);
+            ensure!(!TransfersSent::contains_key(id), Error::<T>::TransferAlreadySent);
+
+            TransfersSent::insert(id, &cid);
+            Self::deposit_event(RawEvent::TransferSent(id, cid));
+            Ok(())
+        }
+
+        /// Prove that the message proposed for a withdrawal batch executed
+        /// successfully in a final tipset.
+        #[weight = settle_weight::<T>().saturating_add(deposit_weight(proof))]
+        pub fn confirm_withdraw(origin, batch: u64, proof: MessageProof) -> DispatchResult{
+            Self::check_permissions(ensure_signed(origin)?)?;
+            let executed = <pallet_fc_relay::Module<T>>::verify_message(&proof)?;
+            Self::settle_withdraw(batch, executed)
+        }
+
+        /// Prove that the message proposed for a withdrawal batch failed in a
+        /// final tipset, the amounts are credited back.
+        #[weight = settle_weight::<T>().saturating_add(deposit_weight(proof))]
+        pub fn fail_withdraw(origin, batch: u64, proof: MessageProof) -> DispatchResult{
+            Self::check_permissions(ensure_signed(origin)?)?;
+            let executed = <pallet_fc_relay::Module<T>>::verify_execution(&proof)?;
+            ensure!(executed.exit_code != 0, Error::<T>::TransferSucceeded);
+            Self::settle_withdraw(batch, executed)
+        }
+
+        /// Prove that the message proposed for a refund executed successfully
+        /// in a final tipset.
+        #[weight = settle_refund_weight::<T>(proof)]
+        pub fn confirm_refund(origin, uid: u64, proof: MessageProof) -> DispatchResult{
+            Self::check_permissions(ensure_signed(origin)?)?;
+            let executed = <pallet_fc_relay::Module<T>>::verify_message(&proof)?;
+            Self::settle_refund(uid, executed)
+        }
+
+        /// Prove that the message proposed for a refund failed in a final
+        /// tipset, the refund is proposed again.
+        #[weight = settle_refund_weight::<T>(proof)]
+        pub fn fail_refund(origin, uid: u64, proof: MessageProof) -> DispatchResult{
+            Self::check_permissions(ensure_signed(origin)?)?;
+            let executed = <pallet_fc_relay::Module<T>>::verify_execution(&proof)?;
+            ensure!(executed.exit_code != 0, Error::<T>::TransferSucceeded);
+            Self::settle_refund(uid, executed)
+        }
 
+        /// Set the FEVM contract paying out batches of withdrawals, empty to send each alone.
+        #[weight = T::DbWeight::get().writes(1)]
+        pub fn set_batch_actor(origin, address: Vec<u8>) -> DispatchResult{
+            ensure_root(origin)?;
+            BatchActor::put(address);
             Ok(())
         }
 
+        fn integrity_test() {
+            assert!(!T::WithdrawBatchInterval::get().is_zero(), "withdrawals are never batched without an interval");
+        }
+
+        fn on_initialize(n: T::BlockNumber) -> Weight {
+            let due = <RefundQueue<T>>::take(n);
+            let count = due.len() as Weight;
+            for cid in due {
+                Self::refund(cid);
+            }
+            let mut weight = T::DbWeight::get().reads_writes(count + 1, count * 3 + 1);
+            if (n % T::WithdrawBatchInterval::get()).is_zero() {
+                weight = weight.saturating_add(Self::batch_withdrawals());
+            }
+            weight
+        }
+
         fn offchain_worker(block: T::BlockNumber) {
             debug::info!("Hello World.");
         }
@@ -344,6 +607,34 @@ decl_module! {
 }
 
 
+/// Every block of a proof is hashed and possibly decoded, so the weight grows
+/// with their number and size.
+fn deposit_weight(proof: &MessageProof) -> Weight {
+	let bytes: usize = proof.blocks.iter().map(|block| block.len()).sum();
+	(10_000_000 as Weight)
+		.saturating_add((proof.blocks.len() as Weight).saturating_mul(1_000_000))
+		.saturating_add((bytes as Weight).saturating_mul(1_000))
+}
+
+/// Proposals are stored and emitted whole, so the weight grows with their size.
+fn propose_weight<T: Trait>(message: &[u8]) -> Weight {
+	(10_000_000 as Weight)
+		.saturating_add((message.len() as Weight).saturating_mul(1_000))
+		.saturating_add(T::DbWeight::get().reads_writes(5, 2))
+}
+
+/// Settling a batch reads and writes every withdrawal in it, at most `MaxWithdrawBatch`.
+fn settle_weight<T: Trait>() -> Weight {
+	let withdrawals = T::MaxWithdrawBatch::get().max(1) as Weight;
+	(10_000_000 as Weight)
+		.saturating_add(T::DbWeight::get().reads_writes(3 + 2 * withdrawals, 4 + 2 * withdrawals))
+}
+
+/// Settling a refund only touches its own entries, besides checking the proof.
+fn settle_refund_weight<T: Trait>(proof: &MessageProof) -> Weight {
+	deposit_weight(proof).saturating_add(T::DbWeight::get().reads_writes(4, 3))
+}
+
 impl<T: Trait> Module<T> {
 	fn initialize_key(key:&[u8]){
 		AlicePubKey::put(key.to_vec());
@@ -354,6 +645,169 @@ impl<T: Trait> Module<T> {
 		TssUrl::put(url);
 	}
 
+	/// Whether `to` is the secp256k1 address of the TSS key or the ID address of its actor.
+	fn is_bridge_address(to: &[u8]) -> bool {
+		if to == pallet_fc_relay::secp256k1_address(&TssPubKey::get()).as_slice() {
+			return true;
+		}
+		let id = BridgeIdAddress::get();
+		!id.is_empty() && to == id.as_slice()
+	}
+
+	fn is_pending_transfer(id: TransferId) -> bool {
+		match id {
+			TransferId::WithdrawBatch(batch) => WithdrawBatches::contains_key(batch),
+			TransferId::Refund(uid) => PendingRefunds::get(uid),
+		}
+	}
+
+	/// Pays the queued withdrawals out in batches of at most `MaxWithdrawBatch`,
+	/// or one by one when there is no batch actor.
+	fn batch_withdrawals() -> Weight {
+		let queue = WithdrawQueue::take();
+		if queue.is_empty() {
+			return T::DbWeight::get().reads(1);
+		}
+		let actor = BatchActor::get();
+		let size = if actor.is_empty() { 1 } else { T::MaxWithdrawBatch::get().max(1) as usize };
+		for uids in queue.chunks(size) {
+			let details: Vec<_> = uids.iter().filter_map(|uid| <PendingWithdrawals<T>>::get(uid)).collect();
+			let batch = BatchCount::mutate(|count| { *count += 1; *count });
+			WithdrawBatches::insert(batch, uids.to_vec());
+			let actor = if details.len() > 1 { actor.clone() } else { Vec::new() };
+			Self::deposit_event(RawEvent::WithdrawBatch(batch, actor, details));
+		}
+		let count = queue.len() as Weight;
+		T::DbWeight::get().reads_writes(count + 3, count / size as Weight + 3)
+	}
+
+	/// Settles a batch with the proven result of its message, crediting the
+	/// amounts back if it failed.
+	fn settle_withdraw(batch: u64, executed: ExecutedMessage) -> DispatchResult {
+		ensure!(WithdrawBatches::contains_key(batch), Error::<T>::NoPendingWithdrawal);
+		Self::ensure_proposed(TransferId::WithdrawBatch(batch), &executed.message)?;
+
+		let (cid, exit_code, gas_used) = (executed.cid, executed.exit_code, executed.gas_used);
+		for uid in Self::settle_batch(batch, exit_code) {
+			if exit_code == 0 {
+				<PendingWithdrawals<T>>::remove(uid);
+				Self::deposit_event(RawEvent::WithdrawConfirmed(uid, cid.clone(), gas_used));
+				continue;
+			}
+			if let Some(detail) = <PendingWithdrawals<T>>::take(uid) {
+				let current_balance = <FileCoinToken<T>>::get(&detail.actor);
+				<FileCoinToken<T>>::insert(&detail.actor, current_balance + detail.value);
+			}
+			Self::deposit_event(RawEvent::WithdrawFailed(uid, cid.clone(), exit_code, gas_used));
+		}
+		Ok(())
+	}
+
+	/// Settles a refund with the proven result of its message. A failed one
+	/// stays pending with its proposals cleared, to be proposed again.
+	fn settle_refund(uid: u64, executed: ExecutedMessage) -> DispatchResult {
+		ensure!(PendingRefunds::get(uid), Error::<T>::UnknownTransfer);
+		let id = TransferId::Refund(uid);
+		Self::ensure_proposed(id, &executed.message)?;
+
+		Self::clear_transfer(id);
+		if executed.exit_code == 0 {
+			PendingRefunds::remove(uid);
+			Self::deposit_event(RawEvent::RefundConfirmed(uid, executed.cid));
+		} else {
+			Self::deposit_event(RawEvent::RefundFailed(uid, executed.cid, executed.exit_code));
+		}
+		Ok(())
+	}
+
+	/// Checks a message pays out the last proposal of a transfer. Replacements
+	/// only raise the gas, so this holds for whichever of them executed.
+	fn ensure_proposed(id: TransferId, message: &UnsignedMessage) -> DispatchResult {
+		let proposal = TransferProposals::get(id).pop().ok_or(Error::<T>::NoTransferProposal)?;
+		let proposal = UnsignedMessage::from_cbor(&proposal).map_err(|_| Error::<T>::NotProposedMessage)?;
+		ensure!(*message == proposal, Error::<T>::NotProposedMessage);
+		Ok(())
+	}
+
+	/// Forgets a batch and its proposals, returns the uids of its withdrawals.
+	fn settle_batch(batch: u64, exit_code: i64) -> Vec<u64> {
+		Self::clear_transfer(TransferId::WithdrawBatch(batch));
+		Self::deposit_event(RawEvent::WithdrawBatchSettled(batch, exit_code));
+		WithdrawBatches::take(batch)
+	}
+
+	fn clear_transfer(id: TransferId) {
+		TransferProposals::remove(id);
+		TransfersSent::remove(id);
+	}
+
+	/// Credits a proven deposit to the account named in its memo, or keeps it
+	/// aside to be refunded.
+	fn record_deposit(executed: ExecutedMessage) -> DispatchResult {
+		let message = executed.message;
+		let delegated = message.to == pallet_fc_relay::delegated_address(&TssPubKey::get());
+		ensure!(delegated || Self::is_bridge_address(&message.to), Error::<T>::NotBridgeAddress);
+
+		ProcessedDeposits::insert(&executed.cid, true);
+		let memo = Memo::<T::AccountId>::parse(&message.params)
+			.map_err(UnattributedReason::Memo)
+			.and_then(|memo| {
+				ensure!(!delegated, UnattributedReason::DelegatedAddress);
+				ensure!(!BlockedSenders::get(&message.from), UnattributedReason::BlockedSender);
+				ensure!(message.value >= T::MinimumDeposit::get(), UnattributedReason::BelowMinimum);
+				Ok(memo)
+			});
+		match memo {
+			Ok(memo) => Self::credit(memo.account, message.value, message.from, executed.cid, memo.tag),
+			Err(reason) => {
+				// Kept aside rather than lost, the funds are refunded after the grace period.
+				let due = <frame_system::Module<T>>::block_number() + T::RefundGracePeriod::get();
+				<RefundQueue<T>>::append(due, &executed.cid);
+				FailRecord::insert(&executed.cid, ErrorRecord {
+					cid: executed.cid.clone(),
+					from: message.from.clone(),
+					tovec: message.params,
+					amount: message.value,
+					solved: false,
+				});
+				Self::deposit_event(RawEvent::DepositUnattributed(executed.cid, message.from, message.value, reason));
+			},
+		}
+		Ok(())
+	}
+
+	fn credit(dest: T::AccountId, value: u128, from: Vec<u8>, cid: Vec<u8>, tag: Vec<u8>) {
+		let current_balance = <FileCoinToken<T>>::get(&dest);
+		<FileCoinToken<T>>::insert(&dest,current_balance + value);
+
+		<WithDrawAddress<T>>::insert(&dest,from);
+
+		Self::deposit_event(RawEvent::DepositToken(dest, value, cid, tag));
+	}
+
+	/// Sends an unattributed deposit back to its sender, minus the refund fee.
+	///
+	/// Deposits attributed during the grace period are skipped, deposits that
+	/// do not cover the fee stay recorded.
+	fn refund(cid: Vec<u8>) {
+		let mut record = FailRecord::get(&cid);
+		let fee = T::RefundFee::get();
+		if record.solved || record.amount <= fee {
+			return;
+		}
+		record.solved = true;
+		FailRecord::insert(&cid, &record);
+
+		let uid = RefundCount::mutate(|count| { *count += 1; *count });
+		PendingRefunds::insert(uid, true);
+		Self::deposit_event(RawEvent::RefundToken(RefundDetail {
+			uid,
+			cid,
+			value: record.amount - fee,
+			receiver: record.from,
+		}));
+	}
+
 	fn tss_index() -> u64 {
 		let index_old = Index::get() + 1;
 		Index::put(index_old);
@@ -49,11 +49,55 @@ impl system::Trait for Test {
 	type SystemWeightInfo = ();
 }
 
+parameter_types! {
+	pub const MinimumPeriod: u64 = 5;
+}
+
+impl pallet_timestamp::Trait for Test {
+	type Moment = u64;
+	type OnTimestampSet = ();
+	type MinimumPeriod = MinimumPeriod;
+	type WeightInfo = ();
+}
+
+parameter_types! {
+	pub const FinalityDepth: u64 = 3;
+	pub const HistoryDepth: u64 = 2;
+	pub const MaxTipsetSize: u32 = 4;
+	pub F3NetworkName: Vec<u8> = b"filecoin".to_vec();
+}
+
+impl pallet_fc_relay::Trait for Test {
+	type Event = ();
+	type FinalityDepth = FinalityDepth;
+	type HistoryDepth = HistoryDepth;
+	type MaxTipsetSize = MaxTipsetSize;
+	type Verifier = ();
+	type F3NetworkName = F3NetworkName;
+	type WeightInfo = ();
+}
+
+parameter_types! {
+	pub const MinimumDeposit: u128 = 100;
+	pub const RefundGracePeriod: u64 = 10;
+	pub const RefundFee: u128 = 10;
+	pub const WithdrawBatchInterval: u64 = 5;
+	pub const MaxWithdrawBatch: u32 = 3;
+	pub const MaxWithdrawQueue: u32 = 4;
+}
+
 impl Trait for Test {
 	type Event = ();
+	type MinimumDeposit = MinimumDeposit;
+	type RefundGracePeriod = RefundGracePeriod;
+	type RefundFee = RefundFee;
+	type WithdrawBatchInterval = WithdrawBatchInterval;
+	type MaxWithdrawBatch = MaxWithdrawBatch;
+	type MaxWithdrawQueue = MaxWithdrawQueue;
 }
 
 pub type TemplateModule = Module<Test>;
+pub type System = system::Module<Test>;
 
 // Build genesis storage according to the mock runtime.
 pub fn new_test_ext() -> sp_io::TestExternalities {
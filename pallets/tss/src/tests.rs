@@ -1,23 +1,296 @@
-use crate::{Error, mock::*};
-use frame_support::{assert_ok, assert_noop};
+use crate::{Error, mock::*, memo::Memo, TssPubKey, TransferId};
+use frame_support::{assert_ok, assert_noop, traits::OnInitialize, StorageValue};
+use pallet_fc_relay::{ExecutedMessage, MessageProof, UnsignedMessage};
+use sp_runtime::DispatchError;
+
+const TSS_KEY: [u8; 65] = [4u8; 65];
+
+fn bridge() -> Vec<u8> {
+	pallet_fc_relay::secp256k1_address(&TSS_KEY)
+}
+
+fn sender() -> Vec<u8> {
+	[vec![1u8], vec![0xaa; 20]].concat()
+}
+
+fn memo(account: u64) -> Vec<u8> {
+	Memo { account, tag: Vec::new() }.encode_params()
+}
+
+/// A proven transfer of `value` to the bridge address.
+fn deposit(cid: u8, value: u128, params: Vec<u8>) -> ExecutedMessage {
+	ExecutedMessage {
+		cid: vec![cid],
+		message: UnsignedMessage { to: bridge(), from: sender(), value, params, ..Default::default() },
+		..Default::default()
+	}
+}
+
+fn head(out: &mut Vec<u8>, major: u8, value: u64) {
+	match value {
+		0..=23 => out.push(major << 5 | value as u8),
+		_ => {
+			out.push(major << 5 | 27);
+			out.extend_from_slice(&value.to_be_bytes());
+		},
+	}
+}
+
+/// An unsigned message from the bridge address, as proposed for a transfer.
+fn proposal(to: &[u8], nonce: u64, value: u128, params: &[u8]) -> Vec<u8> {
+	let mut out = Vec::new();
+	head(&mut out, 4, 10);
+	head(&mut out, 0, 0);
+	for address in [to, &bridge()[..]].iter() {
+		head(&mut out, 2, address.len() as u64);
+		out.extend_from_slice(address);
+	}
+	head(&mut out, 0, nonce);
+	head(&mut out, 2, 17);
+	out.push(0);
+	out.extend_from_slice(&value.to_be_bytes());
+	head(&mut out, 0, 1_000_000);
+	for _ in 0..2 {
+		head(&mut out, 2, 0);
+	}
+	head(&mut out, 0, 0);
+	head(&mut out, 2, params.len() as u64);
+	out.extend_from_slice(params);
+	out
+}
+
+/// The proven execution of a proposed message.
+fn executed(proposal: &[u8], exit_code: i64) -> ExecutedMessage {
+	ExecutedMessage {
+		cid: b"cid".to_vec(),
+		message: UnsignedMessage::from_cbor(proposal).unwrap(),
+		exit_code,
+		gas_used: 10,
+		..Default::default()
+	}
+}
+
+fn new_bridge() -> sp_io::TestExternalities {
+	let mut ext = new_test_ext();
+	ext.execute_with(|| {
+		TssPubKey::put(TSS_KEY.to_vec());
+		System::set_block_number(1);
+	});
+	ext
+}
+
+fn run_to(n: u64) {
+	for block in System::block_number() + 1..=n {
+		System::set_block_number(block);
+		TemplateModule::on_initialize(block);
+	}
+}
+
+#[test]
+fn credits_deposits_to_the_memo_account() {
+	new_bridge().execute_with(|| {
+		assert_ok!(TemplateModule::record_deposit(deposit(1, 500, memo(7))));
+		assert_eq!(TemplateModule::file_coin_token(7), 500);
+		assert_eq!(TemplateModule::with_draw_address(7), sender());
+		assert!(TemplateModule::processed_deposit(vec![1]));
+
+		let mut elsewhere = deposit(2, 500, memo(7));
+		elsewhere.message.to = vec![0, 1];
+		assert_noop!(TemplateModule::record_deposit(elsewhere), Error::<Test>::NotBridgeAddress);
+	});
+}
+
+#[test]
+fn attributes_deposits_before_they_are_refunded() {
+	new_bridge().execute_with(|| {
+		assert_ok!(TemplateModule::record_deposit(deposit(1, 500, b"for alice".to_vec())));
+		assert_eq!(TemplateModule::file_coin_token(7), 0);
+		assert_eq!(TemplateModule::fail_record(vec![1]).amount, 500);
+		assert_eq!(TemplateModule::refund_queue(11), vec![vec![1]]);
+
+		assert_noop!(TemplateModule::attribute_deposit(Origin::signed(1), vec![1], 7), DispatchError::BadOrigin);
+		assert_ok!(TemplateModule::attribute_deposit(Origin::root(), vec![1], 7));
+		assert_eq!(TemplateModule::file_coin_token(7), 500);
+		assert_noop!(TemplateModule::attribute_deposit(Origin::root(), vec![1], 7), Error::<Test>::NoUnattributedDeposit);
+
+		run_to(11);
+		assert_eq!(TemplateModule::refund_count(), 0);
+	});
+}
+
+#[test]
+fn refunds_unattributed_deposits_after_the_grace_period() {
+	new_bridge().execute_with(|| {
+		assert_ok!(TemplateModule::record_deposit(deposit(1, 500, Vec::new())));
+		// Does not cover the refund fee.
+		assert_ok!(TemplateModule::record_deposit(deposit(2, 10, Vec::new())));
+
+		run_to(10);
+		assert_eq!(TemplateModule::refund_count(), 0);
+		run_to(11);
+		assert_eq!(TemplateModule::refund_count(), 1);
+		assert!(TemplateModule::fail_record(vec![1]).solved);
+		assert!(!TemplateModule::fail_record(vec![2]).solved);
+		assert!(TemplateModule::refund_queue(11).is_empty());
+	});
+}
 
 #[test]
-fn it_works_for_default_value() {
-	new_test_ext().execute_with(|| {
-		// Dispatch a signed extrinsic.
-		assert_ok!(TemplateModule::do_something(Origin::signed(1), 42));
-		// Read pallet storage and assert an expected result.
-		assert_eq!(TemplateModule::something(), Some(42));
+fn settles_withdraw_batches() {
+	new_bridge().execute_with(|| {
+		assert_ok!(TemplateModule::add_new_one(Origin::root(), 1));
+		assert_ok!(TemplateModule::set_batch_actor(Origin::root(), vec![4, 10, 0xbb]));
+		assert_ok!(TemplateModule::record_deposit(deposit(1, 500, memo(7))));
+		assert_ok!(TemplateModule::withdraw_token(Origin::signed(7), 7, 200));
+		assert_ok!(TemplateModule::withdraw_token(Origin::signed(7), 7, 100));
+		assert_eq!(TemplateModule::file_coin_token(7), 200);
+
+		run_to(5);
+		assert_eq!(TemplateModule::withdraw_batch(1), vec![1, 2]);
+		let batch = proposal(&[4, 10, 0xbb], 3, 300, b"batch");
+		assert_noop!(TemplateModule::settle_withdraw(1, executed(&batch, 0)), Error::<Test>::NoTransferProposal);
+		assert_ok!(TemplateModule::propose_transfer(Origin::signed(1), TransferId::WithdrawBatch(1), batch.clone()));
+
+		// Only the proposed message settles the batch.
+		let other = proposal(&[4, 10, 0xbb], 4, 300, b"batch");
+		assert_noop!(TemplateModule::settle_withdraw(1, executed(&other, 0)), Error::<Test>::NotProposedMessage);
+		assert_ok!(TemplateModule::settle_withdraw(1, executed(&batch, 0)));
+		assert!(TemplateModule::pending_withdrawal(1).is_none());
+		assert!(TemplateModule::pending_withdrawal(2).is_none());
+		assert!(TemplateModule::transfer_proposals(TransferId::WithdrawBatch(1)).is_empty());
+		assert_noop!(TemplateModule::settle_withdraw(1, executed(&batch, 0)), Error::<Test>::NoPendingWithdrawal);
+
+		// A failed transfer credits the amounts back.
+		assert_ok!(TemplateModule::withdraw_token(Origin::signed(7), 7, 50));
+		run_to(10);
+		assert_eq!(TemplateModule::withdraw_batch(2), vec![3]);
+		let single = proposal(&sender(), 4, 50, &[]);
+		assert_ok!(TemplateModule::propose_transfer(Origin::signed(1), TransferId::WithdrawBatch(2), single.clone()));
+		assert_ok!(TemplateModule::settle_withdraw(2, executed(&single, 16)));
+		assert_eq!(TemplateModule::file_coin_token(7), 200);
+		assert!(TemplateModule::pending_withdrawal(3).is_none());
+	});
+}
+
+#[test]
+fn records_the_bridge_id_address() {
+	new_bridge().execute_with(|| {
+		let id = vec![0, 0xe8, 0x07];
+		assert_noop!(TemplateModule::set_bridge_id_address(Origin::signed(1), id.clone()), Error::<Test>::NoneValue);
+		assert_ok!(TemplateModule::add_new_one(Origin::root(), 1));
+		assert_noop!(TemplateModule::set_bridge_id_address(Origin::signed(1), sender()), Error::<Test>::NotIdAddress);
+		assert_ok!(TemplateModule::set_bridge_id_address(Origin::signed(1), id.clone()));
+		// Recorded again by every member.
+		assert_ok!(TemplateModule::set_bridge_id_address(Origin::signed(1), id.clone()));
+		assert_noop!(
+			TemplateModule::set_bridge_id_address(Origin::signed(1), vec![0, 0x01]),
+			Error::<Test>::BridgeIdAddressSet
+		);
+
+		let mut to_id = deposit(1, 500, memo(7));
+		to_id.message.to = id;
+		assert_ok!(TemplateModule::record_deposit(to_id));
+		assert_eq!(TemplateModule::file_coin_token(7), 500);
+
+		assert_ok!(TemplateModule::set_bridge_id_address(Origin::root(), vec![0, 0x01]));
+		assert_eq!(TemplateModule::bridge_id_address(), vec![0, 0x01]);
+	});
+}
+
+#[test]
+fn refunds_deposits_to_the_delegated_address() {
+	new_bridge().execute_with(|| {
+		let mut delegated = deposit(1, 500, memo(7));
+		delegated.message.to = pallet_fc_relay::delegated_address(&TSS_KEY);
+		assert_ok!(TemplateModule::record_deposit(delegated));
+		assert_eq!(TemplateModule::file_coin_token(7), 0);
+		assert_eq!(TemplateModule::fail_record(vec![1]).amount, 500);
+
+		run_to(11);
+		assert!(TemplateModule::fail_record(vec![1]).solved);
+		assert_eq!(TemplateModule::refund_count(), 1);
+	});
+}
+
+#[test]
+fn refunds_deposits_below_the_minimum() {
+	new_bridge().execute_with(|| {
+		assert_ok!(TemplateModule::record_deposit(deposit(1, 50, memo(7))));
+		assert_eq!(TemplateModule::file_coin_token(7), 0);
+		assert_eq!(TemplateModule::fail_record(vec![1]).amount, 50);
+
+		run_to(11);
+		assert!(TemplateModule::fail_record(vec![1]).solved);
+		assert_eq!(TemplateModule::refund_count(), 1);
 	});
 }
 
 #[test]
-fn correct_error_for_none_value() {
-	new_test_ext().execute_with(|| {
-		// Ensure the expected error is thrown when no value is present.
+fn only_members_settle_withdrawals() {
+	new_bridge().execute_with(|| {
+		assert_ok!(TemplateModule::record_deposit(deposit(1, 500, memo(7))));
+		assert_ok!(TemplateModule::withdraw_token(Origin::signed(7), 7, 200));
+		run_to(5);
+
+		assert_noop!(TemplateModule::confirm_withdraw(Origin::signed(7), 1, MessageProof::default()), Error::<Test>::NoneValue);
+		assert_noop!(TemplateModule::fail_withdraw(Origin::signed(7), 1, MessageProof::default()), Error::<Test>::NoneValue);
+		assert_eq!(TemplateModule::withdraw_batch(1), vec![1]);
+		assert_eq!(TemplateModule::file_coin_token(7), 300);
+	});
+}
+
+#[test]
+fn refunds_stay_pending_until_proven() {
+	new_bridge().execute_with(|| {
+		assert_ok!(TemplateModule::add_new_one(Origin::root(), 1));
+		assert_ok!(TemplateModule::record_deposit(deposit(1, 500, Vec::new())));
+		run_to(11);
+		let refund = TransferId::Refund(1);
+		assert!(TemplateModule::pending_refund(1));
+
+		assert_noop!(TemplateModule::propose_transfer(Origin::signed(7), refund, vec![1]), Error::<Test>::NoneValue);
 		assert_noop!(
-			TemplateModule::cause_error(Origin::signed(1)),
-			Error::<Test>::NoneValue
+			TemplateModule::propose_transfer(Origin::signed(1), TransferId::Refund(2), vec![1]),
+			Error::<Test>::UnknownTransfer
 		);
+		assert_noop!(TemplateModule::transfer_sent(Origin::signed(1), refund, b"cid".to_vec()), Error::<Test>::NoTransferProposal);
+		let first = proposal(&sender(), 3, 500, b"");
+		assert_ok!(TemplateModule::propose_transfer(Origin::signed(1), refund, first.clone()));
+		assert_noop!(
+			TemplateModule::propose_transfer(Origin::signed(1), refund, vec![2]),
+			Error::<Test>::TransferAlreadyProposed
+		);
+		assert_noop!(TemplateModule::transfer_sent(Origin::signed(7), refund, b"cid".to_vec()), Error::<Test>::NoneValue);
+		assert_ok!(TemplateModule::transfer_sent(Origin::signed(1), refund, b"cid".to_vec()));
+		assert!(TemplateModule::pending_refund(1));
+
+		// A failed message leaves the refund to be proposed again.
+		assert_ok!(TemplateModule::settle_refund(1, executed(&first, 16)));
+		assert!(TemplateModule::pending_refund(1));
+		assert!(TemplateModule::transfer_proposals(refund).is_empty());
+		let second = proposal(&sender(), 4, 500, b"");
+		assert_ok!(TemplateModule::propose_transfer(Origin::signed(1), refund, second.clone()));
+		assert_noop!(TemplateModule::settle_refund(1, executed(&first, 0)), Error::<Test>::NotProposedMessage);
+
+		assert_ok!(TemplateModule::settle_refund(1, executed(&second, 0)));
+		assert!(!TemplateModule::pending_refund(1));
+		assert!(TemplateModule::transfer_proposals(refund).is_empty());
+		assert_noop!(TemplateModule::settle_refund(1, executed(&second, 0)), Error::<Test>::UnknownTransfer);
+		assert_noop!(TemplateModule::propose_transfer(Origin::signed(1), refund, vec![3]), Error::<Test>::UnknownTransfer);
+	});
+}
+
+#[test]
+fn caps_the_withdraw_queue() {
+	new_bridge().execute_with(|| {
+		assert_ok!(TemplateModule::record_deposit(deposit(1, 500, memo(7))));
+		for _ in 0..4 {
+			assert_ok!(TemplateModule::withdraw_token(Origin::signed(7), 7, 10));
+		}
+		assert_noop!(TemplateModule::withdraw_token(Origin::signed(7), 7, 10), Error::<Test>::WithdrawQueueFull);
+
+		run_to(5);
+		assert_ok!(TemplateModule::withdraw_token(Origin::signed(7), 7, 10));
+		assert_eq!(TemplateModule::file_coin_token(7), 450);
 	});
 }
@@ -9,5 +9,13 @@ sp_api::decl_runtime_apis! {
 	    fn tss_pub_key_bool() -> Vec<u8>;
 	    fn tss_pub_key_fc() -> Vec<u8>;
 	    fn tss_url() -> Vec<u8>;
+	    /// Public keys of the parties holding shares of the TSS key.
+	    fn tss_members() -> Vec<Vec<u8>>;
+	    fn fc_best_height() -> u64;
+	    fn fc_finalized_height() -> u64;
+	    fn fc_deposit_final_height() -> u64;
+	    fn fc_f3_instance() -> Option<u64>;
+	    /// ID address of the bridge account actor, empty until it is recorded.
+	    fn fc_bridge_id_address() -> Vec<u8>;
     }
 }
\ No newline at end of file
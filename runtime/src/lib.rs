@@ -268,8 +268,72 @@ impl pallet_template::Trait for Runtime {
 	type Event = Event;
 }
 
+/// Gas limit of a transfer with margin times the default max fee cap of the bridge.
+const FC_REFUND_FEE: u128 = 2_500_000 * 10_000_000_000;
+
+parameter_types! {
+	/// Twice the refund fee, 0.05 FIL, smaller deposits are refunded.
+	pub const FcMinimumDeposit: u128 = 2 * FC_REFUND_FEE;
+	pub const FcRefundGracePeriod: BlockNumber = 7 * DAYS;
+	pub const FcRefundFee: u128 = FC_REFUND_FEE;
+	pub const FcWithdrawBatchInterval: BlockNumber = MINUTES;
+	/// Keeps the gas of a batch well under the block gas limit.
+	pub const FcMaxWithdrawBatch: u32 = 50;
+	/// Twenty full batches.
+	pub const FcMaxWithdrawQueue: u32 = 1_000;
+}
+
 impl pallet_tss::Trait for Runtime {
 	type Event = Event;
+	type MinimumDeposit = FcMinimumDeposit;
+	type RefundGracePeriod = FcRefundGracePeriod;
+	type RefundFee = FcRefundFee;
+	type WithdrawBatchInterval = FcWithdrawBatchInterval;
+	type MaxWithdrawBatch = FcMaxWithdrawBatch;
+	type MaxWithdrawQueue = FcMaxWithdrawQueue;
+}
+
+parameter_types! {
+	/// Filecoin considers tipsets final after 900 epochs.
+	pub const FcFinalityDepth: u64 = 900;
+	pub const FcHistoryDepth: u64 = 2880;
+	pub const FcMaxTipsetSize: u32 = 16;
+	/// Public key of the drand mainnet chain.
+	pub FcDrandPublicKey: Vec<u8> = vec![
+		0x86, 0x8f, 0x00, 0x5e, 0xb8, 0xe6, 0xe4, 0xca, 0x0a, 0x47, 0xc8, 0xa7,
+		0x7c, 0xea, 0xa5, 0x30, 0x9a, 0x47, 0x97, 0x8a, 0x7c, 0x71, 0xbc, 0x5c,
+		0xce, 0x96, 0x36, 0x6b, 0x5d, 0x7a, 0x56, 0x99, 0x37, 0xc5, 0x29, 0xee,
+		0xda, 0x66, 0xc7, 0x29, 0x37, 0x84, 0xa9, 0x40, 0x28, 0x01, 0xaf, 0x31,
+	];
+	/// Tickets mix in the parent ticket after the Smoke upgrade.
+	pub const FcSmokeHeight: u64 = 51_000;
+	/// Public key of drand quicknet, which mainnet reads from the Phoenix upgrade.
+	pub FcQuicknetPublicKey: Vec<u8> = vec![
+		0x83, 0xcf, 0x0f, 0x28, 0x96, 0xad, 0xee, 0x7e, 0xb8, 0xb5, 0xf0, 0x1f,
+		0xca, 0xd3, 0x91, 0x22, 0x12, 0xc4, 0x37, 0xe0, 0x07, 0x3e, 0x91, 0x1f,
+		0xb9, 0x00, 0x22, 0xd3, 0xe7, 0x60, 0x18, 0x3c, 0x8c, 0x4b, 0x45, 0x0b,
+		0x6a, 0x0a, 0x6c, 0x3a, 0xc6, 0xa5, 0x77, 0x6a, 0x2d, 0x10, 0x64, 0x51,
+		0x0d, 0x1f, 0xec, 0x75, 0x8c, 0x92, 0x1c, 0xc2, 0x2b, 0x0e, 0x17, 0xe6,
+		0x3a, 0xaf, 0x4b, 0xcb, 0x5e, 0xd6, 0x63, 0x04, 0xde, 0x9c, 0xf8, 0x09,
+		0xbd, 0x27, 0x4c, 0xa7, 0x3b, 0xab, 0x4a, 0xf5, 0xa6, 0xe9, 0xc7, 0x6a,
+		0x4b, 0xc0, 0x9e, 0x76, 0xea, 0xe8, 0x99, 0x1e, 0xf5, 0xec, 0xe4, 0x5a,
+	];
+	/// First height of the Phoenix upgrade (FIP-0063), from which blocks carry quicknet entries.
+	pub const FcQuicknetHeight: u64 = 3_855_480;
+	/// Network name mainnet F3 participants sign with.
+	pub FcF3NetworkName: Vec<u8> = b"filecoin".to_vec();
+}
+
+impl pallet_fc_relay::Trait for Runtime {
+	type Event = Event;
+	type FinalityDepth = FcFinalityDepth;
+	type HistoryDepth = FcHistoryDepth;
+	type MaxTipsetSize = FcMaxTipsetSize;
+	type Verifier = pallet_fc_relay::verify::BlsVerifier<
+		FcDrandPublicKey, FcSmokeHeight, FcQuicknetPublicKey, FcQuicknetHeight,
+	>;
+	type F3NetworkName = FcF3NetworkName;
+	type WeightInfo = ();
 }
 
 pub use pallet_ocw;
@@ -369,6 +433,7 @@ construct_runtime!(
 //		Feed: pallet_feed::{Module, Call, Storage, Event<T>, Config<T>},
        	Tss: pallet_tss::{Module, Call, Config, Storage, Event<T>},
        	BlockFetchModule: pallet_ocw::{Module, Call, Storage, Event<T>},
+       	FcRelay: pallet_fc_relay::{Module, Call, Storage, Event<T>},
 	}
 );
 
@@ -561,6 +626,7 @@ impl_runtime_apis! {
 			add_benchmark!(params, batches, frame_system, SystemBench::<Runtime>);
 			add_benchmark!(params, batches, pallet_balances, Balances);
 			add_benchmark!(params, batches, pallet_timestamp, Timestamp);
+			add_benchmark!(params, batches, pallet_fc_relay, FcRelay);
 
 			if batches.is_empty() { return Err("Benchmark not found for this pallet.".into()) }
 			Ok(batches)
@@ -591,5 +657,29 @@ impl_runtime_apis! {
 		fn tss_url() -> Vec<u8>{
 			Tss::tss_url()
 		}
+
+		fn tss_members() -> Vec<Vec<u8>>{
+			Tss::tss_pubkey_vec(Tss::tss_pubkey())
+		}
+
+		fn fc_best_height() -> u64{
+			FcRelay::best_height()
+		}
+
+		fn fc_finalized_height() -> u64{
+			FcRelay::finalized_height()
+		}
+
+		fn fc_deposit_final_height() -> u64{
+			FcRelay::deposit_final_height()
+		}
+
+		fn fc_f3_instance() -> Option<u64>{
+			if FcRelay::f3_power_table().is_empty() { None } else { Some(FcRelay::f3_instance()) }
+		}
+
+		fn fc_bridge_id_address() -> Vec<u8>{
+			Tss::bridge_id_address()
+		}
    	}
 }